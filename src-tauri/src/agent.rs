@@ -0,0 +1,588 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use tokio::sync::broadcast;
+
+use crate::history::{self, HistoryStore};
+use crate::library::{self, LibraryStore};
+use crate::queue::UploadQueue;
+use crate::staging::StagedFiles;
+use crate::watcher::FileWatcher;
+
+/// Receives the events the agent reports (`upload_started`, `file_detected`, ...)
+/// and the notifications it shows to the user
+pub trait EventSink: Send + Sync {
+    /// Deliver an event with its JSON payload
+    fn emit_value(&self, event: &str, payload: Value);
+
+    /// Show a notification to the user
+    fn notify(&self, title: &str, body: &str);
+}
+
+impl dyn EventSink {
+    /// Serialize a payload and deliver it
+    pub fn emit<S: Serialize + ?Sized>(&self, event: &str, payload: &S) {
+        match serde_json::to_value(payload) {
+            Ok(payload) => self.emit_value(event, payload),
+            Err(e) => tracing::warn!("Failed to serialize {} event: {}", event, e),
+        }
+    }
+}
+
+/// Events kept for subscribers that fall behind before they miss some
+const EVENT_BUS_CAPACITY: usize = 256;
+
+/// An event as delivered to subscribers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentEvent {
+    pub event: String,
+    pub payload: Value,
+}
+
+/// Event sink that passes events on to the host's sink and to any subscribers
+pub struct EventBus {
+    inner: Arc<dyn EventSink>,
+    sender: broadcast::Sender<AgentEvent>,
+}
+
+impl EventBus {
+    pub fn new(inner: Arc<dyn EventSink>) -> Self {
+        let (sender, _) = broadcast::channel(EVENT_BUS_CAPACITY);
+        Self { inner, sender }
+    }
+
+    /// Receive every event delivered from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<AgentEvent> {
+        self.sender.subscribe()
+    }
+}
+
+impl EventSink for EventBus {
+    fn emit_value(&self, event: &str, payload: Value) {
+        if self.sender.receiver_count() > 0 {
+            let _ = self.sender.send(AgentEvent {
+                event: event.to_string(),
+                payload: payload.clone(),
+            });
+        }
+        self.inner.emit_value(event, payload);
+    }
+
+    fn notify(&self, title: &str, body: &str) {
+        self.inner.notify(title, body);
+    }
+}
+
+/// Persistent key-value files (config.json, session.json, ...) and the directory
+/// the agent keeps its databases in
+pub trait Storage: Send + Sync {
+    /// Get a value by key from a store file
+    fn get(&self, file: &str, key: &str) -> Result<Option<Value>, String>;
+
+    /// Set a value and persist the store file
+    fn set(&self, file: &str, key: &str, value: Value) -> Result<(), String>;
+
+    /// Delete a value and persist the store file
+    fn delete(&self, file: &str, key: &str) -> Result<(), String>;
+
+    /// Replace a value with what `update` makes of the current one (None deletes it)
+    /// The store file stays locked in between, so concurrent updates aren't lost
+    fn update(
+        &self,
+        file: &str,
+        key: &str,
+        update: &mut dyn FnMut(Option<Value>) -> Result<Option<Value>, String>,
+    ) -> Result<(), String>;
+
+    /// Get a value from the store file's last good backup, if it has one
+    fn backup(&self, _file: &str, _key: &str) -> Result<Option<Value>, String> {
+        Ok(None)
+    }
+
+    /// Directory for the history and library databases
+    fn data_dir(&self) -> Result<PathBuf, String>;
+}
+
+/// Source of the current time for records and retry schedules
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The system clock
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Everything the watcher, uploader and stores need from their host
+///
+/// The desktop app builds one from its `AppHandle` (see `desktop`), other hosts
+/// can provide their own event sink, storage and clock. Cloning is cheap.
+#[derive(Clone)]
+pub struct AgentContext {
+    pub events: Arc<dyn EventSink>,
+    pub storage: Arc<dyn Storage>,
+    pub clock: Arc<dyn Clock>,
+    /// Uploads held back while the session needs re-authentication
+    pub upload_queue: Arc<UploadQueue>,
+    /// Watcher of the replay folders
+    pub watcher: Arc<Mutex<FileWatcher>>,
+    /// Staged copies the uploads in progress use
+    pub staged: Arc<StagedFiles>,
+    bus: Arc<EventBus>,
+    history: Arc<OnceLock<HistoryStore>>,
+    /// Held while opening the history, so concurrent callers open it once
+    history_opening: Arc<Mutex<()>>,
+    library: Option<Arc<LibraryStore>>,
+    runtime: tokio::runtime::Handle,
+    active_uploads: Arc<AtomicUsize>,
+    shutting_down: Arc<AtomicBool>,
+    /// Held while the replay cleanup runs
+    cleanup_running: Arc<Mutex<()>>,
+    /// IDs of the records being retried
+    retrying: Arc<Mutex<HashSet<String>>>,
+}
+
+/// Counts an upload as in progress until dropped
+pub struct UploadGuard {
+    active_uploads: Arc<AtomicUsize>,
+}
+
+impl Drop for UploadGuard {
+    fn drop(&mut self) {
+        self.active_uploads.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Marks a record as being retried until dropped
+pub struct RetryGuard {
+    retrying: Arc<Mutex<HashSet<String>>>,
+    record_id: String,
+}
+
+impl Drop for RetryGuard {
+    fn drop(&mut self) {
+        self.retrying.lock().unwrap().remove(&self.record_id);
+    }
+}
+
+impl AgentContext {
+    /// Create a context without stores, background tasks run on `runtime`
+    pub fn new(
+        events: Arc<dyn EventSink>,
+        storage: Arc<dyn Storage>,
+        clock: Arc<dyn Clock>,
+        runtime: tokio::runtime::Handle,
+    ) -> Self {
+        let bus = Arc::new(EventBus::new(events));
+        Self {
+            events: bus.clone(),
+            bus,
+            storage,
+            clock,
+            upload_queue: Arc::new(UploadQueue::new()),
+            watcher: Arc::new(Mutex::new(FileWatcher::new())),
+            staged: Arc::new(StagedFiles::default()),
+            history: Arc::new(OnceLock::new()),
+            history_opening: Arc::new(Mutex::new(())),
+            library: None,
+            runtime,
+            active_uploads: Arc::new(AtomicUsize::new(0)),
+            shutting_down: Arc::new(AtomicBool::new(false)),
+            cleanup_running: Arc::new(Mutex::new(())),
+            retrying: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    /// Open the history and library databases in the storage's data directory
+    /// Stores that can't be opened are left out and reported as unavailable
+    pub fn open_stores(mut self) -> Self {
+        match history::init(&self) {
+            Ok(store) => self = self.with_history(store),
+            Err(e) => tracing::error!("Failed to open upload history: {}", e),
+        }
+
+        match library::init(&self) {
+            Ok(store) => self = self.with_library(store),
+            Err(e) => tracing::error!("Failed to open replay library: {}", e),
+        }
+
+        self
+    }
+
+    pub fn with_history(mut self, history: HistoryStore) -> Self {
+        self.history = Arc::new(OnceLock::from(history));
+        self
+    }
+
+    pub fn with_library(mut self, library: LibraryStore) -> Self {
+        self.library = Some(Arc::new(library));
+        self
+    }
+
+    /// The upload history, opened again if it couldn't be before (e.g. a locked database)
+    pub fn history(&self) -> Result<&HistoryStore, String> {
+        if let Some(store) = self.history.get() {
+            return Ok(store);
+        }
+
+        let _opening = self.history_opening.lock().unwrap();
+        if let Some(store) = self.history.get() {
+            return Ok(store);
+        }
+        let store = history::init(self)
+            .map_err(|e| format!("Upload history is unavailable: {}", e))?;
+        tracing::info!("Opened upload history");
+        Ok(self.history.get_or_init(|| store))
+    }
+
+    /// The replay library, if it could be opened
+    pub fn library(&self) -> Result<&LibraryStore, String> {
+        self.library
+            .as_deref()
+            .ok_or_else(|| "Replay library is unavailable".to_string())
+    }
+
+    /// Serialize a payload and deliver it to the event sink
+    pub fn emit<S: Serialize + ?Sized>(&self, event: &str, payload: &S) {
+        self.events.emit(event, payload);
+    }
+
+    /// Receive every event emitted from now on, in addition to the host's sink
+    pub fn subscribe(&self) -> broadcast::Receiver<AgentEvent> {
+        self.bus.subscribe()
+    }
+
+    pub fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
+
+    /// Count an upload as in progress while the returned guard lives
+    pub fn track_upload(&self) -> UploadGuard {
+        self.active_uploads.fetch_add(1, Ordering::SeqCst);
+        UploadGuard {
+            active_uploads: self.active_uploads.clone(),
+        }
+    }
+
+    /// Mark a record as being retried while the returned guard lives, None if it already is
+    pub fn try_start_retry(&self, record_id: &str) -> Option<RetryGuard> {
+        if !self.retrying.lock().unwrap().insert(record_id.to_string()) {
+            return None;
+        }
+        Some(RetryGuard {
+            retrying: self.retrying.clone(),
+            record_id: record_id.to_string(),
+        })
+    }
+
+    /// Number of uploads in progress
+    pub fn active_uploads(&self) -> usize {
+        self.active_uploads.load(Ordering::SeqCst)
+    }
+
+    /// Stop starting background work, e.g. automatic retries, before exiting
+    pub fn begin_shutdown(&self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    /// Lock held while the replay cleanup runs, None if it already runs
+    pub fn try_start_cleanup(&self) -> Option<MutexGuard<'_, ()>> {
+        self.cleanup_running.try_lock().ok()
+    }
+
+    /// Run a future in the background
+    pub fn spawn<F>(&self, future: F) -> tokio::task::JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.runtime.spawn(future)
+    }
+
+    /// Run blocking work (file scans, database writes) in the background
+    pub fn spawn_blocking<F, R>(&self, f: F) -> tokio::task::JoinHandle<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        self.runtime.spawn_blocking(f)
+    }
+}
+
+/// Test doubles for driving the core without a host
+#[cfg(test)]
+pub mod testing {
+    use super::*;
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    /// Event sink that keeps every event and notification, for driving the core
+    /// without a host
+    #[derive(Default)]
+    pub struct RecordingEvents {
+        events: Mutex<Vec<(String, Value)>>,
+        notifications: Mutex<Vec<(String, String)>>,
+    }
+
+    impl RecordingEvents {
+        /// Events delivered so far, oldest first
+        pub fn events(&self) -> Vec<(String, Value)> {
+            self.events.lock().unwrap().clone()
+        }
+
+        /// Payloads of the events with a given name
+        pub fn payloads(&self, event: &str) -> Vec<Value> {
+            self.events
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(name, _)| name == event)
+                .map(|(_, payload)| payload.clone())
+                .collect()
+        }
+
+        /// Notifications shown so far as (title, body)
+        pub fn notifications(&self) -> Vec<(String, String)> {
+            self.notifications.lock().unwrap().clone()
+        }
+    }
+
+    impl EventSink for RecordingEvents {
+        fn emit_value(&self, event: &str, payload: Value) {
+            self.events.lock().unwrap().push((event.to_string(), payload));
+        }
+
+        fn notify(&self, title: &str, body: &str) {
+            self.notifications
+                .lock()
+                .unwrap()
+                .push((title.to_string(), body.to_string()));
+        }
+    }
+
+    /// Storage kept in memory, databases go to `data_dir` when one is given
+    #[derive(Default)]
+    pub struct MemoryStorage {
+        files: Mutex<HashMap<String, HashMap<String, Value>>>,
+        data_dir: Option<PathBuf>,
+    }
+
+    impl MemoryStorage {
+        pub fn new(data_dir: Option<PathBuf>) -> Self {
+            Self {
+                files: Mutex::new(HashMap::new()),
+                data_dir,
+            }
+        }
+    }
+
+    impl Storage for MemoryStorage {
+        fn get(&self, file: &str, key: &str) -> Result<Option<Value>, String> {
+            Ok(self
+                .files
+                .lock()
+                .unwrap()
+                .get(file)
+                .and_then(|entries| entries.get(key))
+                .cloned())
+        }
+
+        fn set(&self, file: &str, key: &str, value: Value) -> Result<(), String> {
+            self.files
+                .lock()
+                .unwrap()
+                .entry(file.to_string())
+                .or_default()
+                .insert(key.to_string(), value);
+            Ok(())
+        }
+
+        fn delete(&self, file: &str, key: &str) -> Result<(), String> {
+            if let Some(entries) = self.files.lock().unwrap().get_mut(file) {
+                entries.remove(key);
+            }
+            Ok(())
+        }
+
+        fn update(
+            &self,
+            file: &str,
+            key: &str,
+            update: &mut dyn FnMut(Option<Value>) -> Result<Option<Value>, String>,
+        ) -> Result<(), String> {
+            let mut files = self.files.lock().unwrap();
+            let entries = files.entry(file.to_string()).or_default();
+            match update(entries.get(key).cloned())? {
+                Some(value) => entries.insert(key.to_string(), value),
+                None => entries.remove(key),
+            };
+            Ok(())
+        }
+
+        fn data_dir(&self) -> Result<PathBuf, String> {
+            self.data_dir
+                .clone()
+                .ok_or_else(|| "No data directory configured".to_string())
+        }
+    }
+
+    /// Agent over a temp dir removed on drop, recording its events on a manual clock
+    /// starting at `now()`
+    pub struct TestAgent {
+        pub ctx: AgentContext,
+        pub events: Arc<RecordingEvents>,
+        pub clock: Arc<ManualClock>,
+        pub dir: PathBuf,
+        /// Runtime of its own when not created in one
+        _runtime: Option<tokio::runtime::Runtime>,
+    }
+
+    impl TestAgent {
+        /// Stores kept in memory, databases under `dir/data`
+        pub fn new() -> Self {
+            Self::with_storage(|dir, _| Arc::new(MemoryStorage::new(Some(dir.join("data")))))
+        }
+
+        /// Stores written to files in `dir`, like the app's
+        pub fn with_file_storage() -> Self {
+            Self::with_storage(|dir, events| {
+                Arc::new(crate::store::FileStorage::new(dir.to_path_buf(), events))
+            })
+        }
+
+        fn with_storage(
+            storage: impl FnOnce(&Path, Arc<RecordingEvents>) -> Arc<dyn Storage>,
+        ) -> Self {
+            let dir = std::env::temp_dir().join(format!("ballcam-test-{}", uuid::Uuid::new_v4()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let (runtime, handle) = match tokio::runtime::Handle::try_current() {
+                Ok(handle) => (None, handle),
+                Err(_) => {
+                    let runtime = tokio::runtime::Runtime::new().unwrap();
+                    let handle = runtime.handle().clone();
+                    (Some(runtime), handle)
+                }
+            };
+            let events = Arc::new(RecordingEvents::default());
+            let clock = Arc::new(ManualClock::new(now()));
+            let ctx = AgentContext::new(
+                events.clone(),
+                storage(&dir, events.clone()),
+                clock.clone(),
+                handle,
+            );
+            Self {
+                ctx,
+                events,
+                clock,
+                dir,
+                _runtime: runtime,
+            }
+        }
+
+        /// Logged in with a password session of `user-1` expiring after `lifetime`
+        pub fn logged_in(self, lifetime: chrono::Duration) -> Self {
+            crate::config::add_account(&self.ctx, &session(now() + lifetime)).unwrap();
+            self
+        }
+
+        /// With the history and library opened up front, like the app's
+        pub fn with_stores(mut self) -> Self {
+            self.ctx = self.ctx.clone().open_stores();
+            self
+        }
+
+        pub fn with_config(self, config: &crate::types::AppConfig) -> Self {
+            crate::config::save_config(&self.ctx, config).unwrap();
+            self
+        }
+
+        /// Write a file under the temp dir, creating its folders
+        pub fn file(&self, name: &str, contents: impl AsRef<[u8]>) -> PathBuf {
+            let path = self.dir.join(name);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent).unwrap();
+            }
+            std::fs::write(&path, contents).unwrap();
+            path
+        }
+    }
+
+    impl Default for TestAgent {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl Drop for TestAgent {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    /// Where the clock of a `TestAgent` starts
+    pub fn now() -> DateTime<Utc> {
+        "2024-03-01T12:00:00Z".parse().unwrap()
+    }
+
+    /// Password session of `user-1` expiring at `expiry`
+    pub fn session(expiry: DateTime<Utc>) -> crate::types::AuthSession {
+        use crate::types::{AuthSession, SessionKind, User};
+
+        AuthSession {
+            access_token: "access".to_string(),
+            refresh_token: "refresh".to_string(),
+            access_token_expiry: expiry.to_rfc3339(),
+            refresh_token_expiry: (expiry + chrono::Duration::days(7)).to_rfc3339(),
+            user: User {
+                id: "user-1".to_string(),
+                username: "octane".to_string(),
+                email: "octane@example.com".to_string(),
+                email_verified: true,
+                avatar_url: None,
+            },
+            device_id: None,
+            kind: SessionKind::Password,
+        }
+    }
+
+    /// Clock that only moves when told to
+    pub struct ManualClock {
+        now: Mutex<DateTime<Utc>>,
+    }
+
+    impl ManualClock {
+        pub fn new(now: DateTime<Utc>) -> Self {
+            Self {
+                now: Mutex::new(now),
+            }
+        }
+
+        pub fn set(&self, now: DateTime<Utc>) {
+            *self.now.lock().unwrap() = now;
+        }
+
+        pub fn advance(&self, duration: chrono::Duration) {
+            *self.now.lock().unwrap() += duration;
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> DateTime<Utc> {
+            *self.now.lock().unwrap()
+        }
+    }
+}
@@ -0,0 +1,401 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::agent::AgentContext;
+use crate::library;
+use crate::replay;
+use crate::staging::{self, StagedGuard};
+use crate::types::BatchFileIssue;
+use crate::watcher::MAX_FILE_SIZE;
+
+/// Archives larger than this are not opened
+pub const MAX_ARCHIVE_SIZE: u64 = 1024 * 1024 * 1024; // 1GB
+/// Replays extracted from one archive at most
+const MAX_REPLAYS: usize = 1000;
+/// Total size of the replays extracted from one archive
+const MAX_EXTRACTED_SIZE: u64 = 2 * 1024 * 1024 * 1024; // 2GB
+/// Replays barely compress, entries shrunk more than this are zip bombs
+const MAX_COMPRESSION_RATIO: u64 = 100;
+
+/// Limits on what one archive extracts to
+struct Limits {
+    max_replays: usize,
+    max_extracted_size: u64,
+}
+
+const LIMITS: Limits = Limits {
+    max_replays: MAX_REPLAYS,
+    max_extracted_size: MAX_EXTRACTED_SIZE,
+};
+
+/// Replays extracted from an archive to the staging cache
+pub struct Extracted {
+    pub archive_name: String,
+    /// Modification time of the archive, standing for its replays'
+    pub modified: Option<SystemTime>,
+    pub files: Vec<ExtractedFile>,
+    /// Replay entries left out: too large, invalid, over the archive's limits
+    pub skipped: Vec<BatchFileIssue>,
+    /// Keeps the extracted replays in the cache until they're uploaded
+    pub guard: StagedGuard,
+}
+
+pub struct ExtractedFile {
+    /// Staged copy to upload
+    pub path: PathBuf,
+    /// Archive path and entry name, for messages ("pack.zip/match.replay")
+    pub entry_path: String,
+}
+
+/// Whether a file is a `.zip` archive (by extension)
+pub fn is_archive(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"))
+}
+
+/// Extract the replays of a `.zip` archive to a new folder of the staging cache
+///
+/// Entries that aren't replays are ignored. Replays over the upload size limit,
+/// compressed suspiciously well, beyond the archive's count or total size limits,
+/// or without a readable header are left out.
+pub fn extract(ctx: &AgentContext, path: &Path) -> Result<Extracted, String> {
+    extract_with(ctx, path, &LIMITS)
+}
+
+fn extract_with(ctx: &AgentContext, path: &Path, limits: &Limits) -> Result<Extracted, String> {
+    let metadata =
+        std::fs::metadata(path).map_err(|e| format!("Failed to read archive: {}", e))?;
+    if metadata.len() > MAX_ARCHIVE_SIZE {
+        return Err("Archive too large (over 1 GB)".to_string());
+    }
+
+    let file = File::open(path).map_err(|e| format!("Failed to read archive: {}", e))?;
+    let mut archive =
+        zip::ZipArchive::new(file).map_err(|e| format!("Not a valid zip archive: {}", e))?;
+
+    let archive_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| "archive.zip".to_string());
+    // Replays barely compress, they take about the archive's size once extracted
+    let guard = staging::new_folder(ctx, metadata.len())?;
+    let dir = guard.path().to_path_buf();
+
+    let mut files = Vec::new();
+    let mut skipped = Vec::new();
+    let mut names = HashSet::new();
+    let mut extracted_size = 0;
+
+    for index in 0..archive.len() {
+        let mut entry = match archive.by_index(index) {
+            Ok(entry) => entry,
+            Err(e) => {
+                skipped.push(BatchFileIssue {
+                    file_path: format!("{} (entry {})", path.display(), index + 1),
+                    reason: format!("Unreadable archive entry: {}", e),
+                });
+                continue;
+            }
+        };
+        if !entry.is_file() || !library::is_replay(Path::new(entry.name())) {
+            continue;
+        }
+
+        let entry_path = path.join(entry.name()).to_string_lossy().to_string();
+        let skip = |reason: &str| BatchFileIssue {
+            file_path: entry_path.clone(),
+            reason: reason.to_string(),
+        };
+
+        // Only the file name is used, entries are never written outside the staging folder
+        let Some(file_name) = entry
+            .enclosed_name()
+            .and_then(|name| name.file_name().map(|name| name.to_os_string()))
+        else {
+            skipped.push(skip("Unsafe path in archive"));
+            continue;
+        };
+
+        let size = entry.size();
+        if files.len() >= limits.max_replays {
+            skipped.push(skip(&format!("Archive has over {} replays", limits.max_replays)));
+            continue;
+        }
+        if size == 0 {
+            skipped.push(skip("Empty file"));
+            continue;
+        }
+        if size > MAX_FILE_SIZE {
+            skipped.push(skip("File too large (over 50 MB)"));
+            continue;
+        }
+        if size > entry.compressed_size().max(1) * MAX_COMPRESSION_RATIO {
+            skipped.push(skip("Compressed too well to be a replay"));
+            continue;
+        }
+        if extracted_size + size > limits.max_extracted_size {
+            let megabytes = limits.max_extracted_size / (1024 * 1024);
+            skipped.push(skip(&format!("Archive extracts to over {} MB", megabytes)));
+            continue;
+        }
+
+        // Same name in another folder of the archive, the uploaded name stays the same
+        let mut target = dir.join(&file_name);
+        if !names.insert(file_name.clone()) {
+            let subdir = dir.join(index.to_string());
+            if let Err(e) = std::fs::create_dir_all(&subdir) {
+                skipped.push(skip(&format!("Failed to extract: {}", e)));
+                continue;
+            }
+            target = subdir.join(&file_name);
+        }
+
+        let written = match write_entry(&mut entry, &target, size) {
+            Ok(written) => written,
+            Err(e) => {
+                let _ = std::fs::remove_file(&target);
+                skipped.push(skip(&format!("Failed to extract: {}", e)));
+                continue;
+            }
+        };
+        extracted_size += written;
+
+        if let Err(e) = replay::parse_header(&target) {
+            let _ = std::fs::remove_file(&target);
+            skipped.push(skip(&format!("Not a valid replay: {}", e)));
+            continue;
+        }
+
+        files.push(ExtractedFile {
+            path: target,
+            entry_path,
+        });
+    }
+
+    if files.is_empty() {
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    tracing::info!(
+        "Extracted {} replays from {} ({} left out)",
+        files.len(),
+        archive_name,
+        skipped.len()
+    );
+
+    Ok(Extracted {
+        archive_name,
+        modified: metadata.modified().ok(),
+        files,
+        skipped,
+        guard,
+    })
+}
+
+/// Write an entry to `target`, reading no more than its declared size
+fn write_entry(entry: &mut impl Read, target: &Path, size: u64) -> io::Result<u64> {
+    let mut out = File::create(target)?;
+    let written = io::copy(&mut entry.take(size + 1), &mut out)?;
+    if written > size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "entry is larger than its declared size",
+        ));
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::testing::TestAgent;
+    use crate::replay::tests::replay_with_guid;
+    use std::io::{Cursor, Write};
+    use zip::write::SimpleFileOptions;
+    use zip::CompressionMethod;
+
+    struct TestArchive {
+        agent: TestAgent,
+    }
+
+    impl TestArchive {
+        fn new() -> Self {
+            Self {
+                agent: TestAgent::new(),
+            }
+        }
+
+        /// Write a `.zip` archive of (entry name, contents), stored or deflated
+        fn zip(&self, entries: &[(&str, Vec<u8>)], method: CompressionMethod) -> PathBuf {
+            let path = self.agent.dir.join("pack.zip");
+            let mut writer = zip::ZipWriter::new(File::create(&path).unwrap());
+            let options = SimpleFileOptions::default().compression_method(method);
+            for (name, contents) in entries {
+                writer.start_file(*name, options).unwrap();
+                writer.write_all(contents).unwrap();
+            }
+            writer.finish().unwrap();
+            path
+        }
+
+        fn extract(&self, archive: &Path, limits: &Limits) -> Extracted {
+            extract_with(&self.agent.ctx, archive, limits).unwrap()
+        }
+    }
+
+    fn reasons(extracted: &Extracted) -> Vec<&str> {
+        extracted.skipped.iter().map(|issue| issue.reason.as_str()).collect()
+    }
+
+    fn names(extracted: &Extracted) -> Vec<String> {
+        extracted
+            .files
+            .iter()
+            .map(|file| file.entry_path.rsplit(['/', '\\']).next().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn extracts_replays_and_ignores_other_entries() {
+        let test = TestArchive::new();
+        let archive = test.zip(
+            &[
+                ("a.replay", replay_with_guid("a")),
+                ("readme.txt", b"hello".to_vec()),
+                ("broken.replay", b"not a replay".to_vec()),
+                ("empty.replay", Vec::new()),
+            ],
+            CompressionMethod::Stored,
+        );
+
+        let extracted = test.extract(&archive, &LIMITS);
+        assert_eq!(extracted.archive_name, "pack.zip");
+        assert_eq!(names(&extracted), vec!["a.replay"]);
+        assert!(extracted.files[0].path.starts_with(extracted.guard.path()));
+        assert_eq!(std::fs::read(&extracted.files[0].path).unwrap(), replay_with_guid("a"));
+
+        let reasons = reasons(&extracted);
+        assert_eq!(reasons.len(), 2);
+        assert!(reasons[0].starts_with("Not a valid replay"), "{}", reasons[0]);
+        assert_eq!(reasons[1], "Empty file");
+    }
+
+    #[test]
+    fn entries_compressed_too_well_are_left_out() {
+        let test = TestArchive::new();
+        // Zeros deflate far beyond what a replay does
+        let mut bomb = replay_with_guid("bomb");
+        bomb.resize(1024 * 1024, 0);
+        let archive = test.zip(
+            &[("bomb.replay", bomb), ("a.replay", replay_with_guid("a"))],
+            CompressionMethod::Deflated,
+        );
+
+        let extracted = test.extract(&archive, &LIMITS);
+        assert_eq!(names(&extracted), vec!["a.replay"]);
+        assert_eq!(reasons(&extracted), vec!["Compressed too well to be a replay"]);
+    }
+
+    #[test]
+    fn entries_are_not_read_past_their_declared_size() {
+        let agent = TestAgent::new();
+        let target = agent.dir.join("entry.replay");
+
+        assert_eq!(write_entry(&mut Cursor::new(vec![1; 10]), &target, 10).unwrap(), 10);
+        // A size that lies is caught, at most one byte past it is read
+        let mut longer = Cursor::new(vec![1; 1000]);
+        let error = write_entry(&mut longer, &target, 10).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(longer.position(), 11);
+    }
+
+    #[test]
+    fn stops_at_the_replay_count_limit() {
+        let test = TestArchive::new();
+        let entries: Vec<(String, Vec<u8>)> = (0..4)
+            .map(|n| (format!("{}.replay", n), replay_with_guid(&n.to_string())))
+            .collect();
+        let entries: Vec<(&str, Vec<u8>)> =
+            entries.iter().map(|(name, contents)| (name.as_str(), contents.clone())).collect();
+        let archive = test.zip(&entries, CompressionMethod::Stored);
+
+        let limits = Limits {
+            max_replays: 3,
+            ..LIMITS
+        };
+        let extracted = test.extract(&archive, &limits);
+        assert_eq!(names(&extracted), vec!["0.replay", "1.replay", "2.replay"]);
+        assert_eq!(reasons(&extracted), vec!["Archive has over 3 replays"]);
+    }
+
+    #[test]
+    fn stops_at_the_extracted_size_limit() {
+        let test = TestArchive::new();
+        let mut large = replay_with_guid("large");
+        large.resize(700 * 1024, 7);
+        let mut small = replay_with_guid("small");
+        small.resize(200 * 1024, 7);
+        let archive = test.zip(
+            &[
+                ("large.replay", large.clone()),
+                ("too-much.replay", large),
+                ("small.replay", small),
+            ],
+            CompressionMethod::Stored,
+        );
+
+        let limits = Limits {
+            max_extracted_size: 1024 * 1024,
+            ..LIMITS
+        };
+        let extracted = test.extract(&archive, &limits);
+        // Smaller replays after the one over the limit still fit
+        assert_eq!(names(&extracted), vec!["large.replay", "small.replay"]);
+        assert_eq!(reasons(&extracted), vec!["Archive extracts to over 1 MB"]);
+    }
+
+    #[test]
+    fn entries_escaping_the_archive_are_left_out() {
+        let test = TestArchive::new();
+        let archive = test.zip(
+            &[
+                ("../escape.replay", replay_with_guid("escape")),
+                ("/absolute.replay", replay_with_guid("absolute")),
+                ("nested/../../up.replay", replay_with_guid("up")),
+                ("folder/ok.replay", replay_with_guid("ok")),
+            ],
+            CompressionMethod::Stored,
+        );
+
+        let extracted = test.extract(&archive, &LIMITS);
+        assert_eq!(names(&extracted), vec!["ok.replay"]);
+        assert_eq!(extracted.files[0].path.parent(), Some(extracted.guard.path()));
+        assert_eq!(reasons(&extracted), vec!["Unsafe path in archive"; 3]);
+        assert!(!test.agent.dir.join("escape.replay").exists());
+        assert!(!test.agent.dir.join("up.replay").exists());
+    }
+
+    #[test]
+    fn replays_with_the_same_name_are_all_extracted() {
+        let test = TestArchive::new();
+        let archive = test.zip(
+            &[
+                ("season 1/match.replay", replay_with_guid("first")),
+                ("season 2/match.replay", replay_with_guid("second")),
+            ],
+            CompressionMethod::Stored,
+        );
+
+        let extracted = test.extract(&archive, &LIMITS);
+        assert_eq!(names(&extracted), vec!["match.replay", "match.replay"]);
+        let (first, second) = (&extracted.files[0].path, &extracted.files[1].path);
+        assert_ne!(first, second);
+        assert_eq!(first.file_name(), second.file_name());
+        assert_eq!(std::fs::read(first).unwrap(), replay_with_guid("first"));
+        assert_eq!(std::fs::read(second).unwrap(), replay_with_guid("second"));
+    }
+}
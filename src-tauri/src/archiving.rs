@@ -0,0 +1,309 @@
+use std::path::{Path, PathBuf};
+
+use crate::agent::AgentContext;
+use crate::staging;
+use crate::types::{ArchiveAction, LocalArchivePolicy, UploadRecord};
+
+/// Placeholders of the archive name template
+pub const PLACEHOLDERS: &[&str] = &["date", "time", "playlist", "map", "score", "player", "name"];
+
+/// Placeholders in a name template that aren't known
+pub fn unknown_placeholders(template: &str) -> Vec<String> {
+    let mut unknown = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}') else {
+            break;
+        };
+        let name = &rest[start + 1..start + end];
+        if !PLACEHOLDERS.contains(&name) {
+            unknown.push(name.to_string());
+        }
+        rest = &rest[start + end + 1..];
+    }
+    unknown
+}
+
+/// Path of an archived replay under the archive folder, from the name template
+/// Placeholder values can't add folders, empty folder names are dropped
+pub fn archive_path(policy: &LocalArchivePolicy, record: &UploadRecord) -> PathBuf {
+    let metadata = record.metadata.clone().unwrap_or_default();
+
+    // The game writes dates as "2024-01-15 20-30-12", fall back to the record's
+    let (date, time) = match metadata.date.as_deref().and_then(|d| d.split_once(' ')) {
+        Some((date, time)) => (date.to_string(), time.to_string()),
+        None => {
+            let created = chrono::DateTime::parse_from_rfc3339(&record.created_at)
+                .map(|dt| dt.with_timezone(&chrono::Local))
+                .unwrap_or_default();
+            (
+                created.format("%Y-%m-%d").to_string(),
+                created.format("%H-%M-%S").to_string(),
+            )
+        }
+    };
+    let playlist = match (metadata.team_size, metadata.match_type.as_deref()) {
+        (Some(size), Some(kind)) => format!("{}v{} {}", size, size, kind),
+        (Some(size), None) => format!("{}v{}", size, size),
+        (None, Some(kind)) => kind.to_string(),
+        (None, None) => "Unknown".to_string(),
+    };
+    let name = Path::new(&record.filename)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let values = [
+        ("date", date),
+        ("time", time),
+        ("playlist", playlist),
+        (
+            "map",
+            metadata.map_name.unwrap_or_else(|| "Unknown".to_string()),
+        ),
+        (
+            "score",
+            format!("{}-{}", metadata.team0_score, metadata.team1_score),
+        ),
+        (
+            "player",
+            metadata
+                .player_name
+                .unwrap_or_else(|| "Unknown".to_string()),
+        ),
+        ("name", name.clone()),
+    ];
+
+    let mut path = PathBuf::from(&policy.folder);
+    let parts: Vec<String> = policy
+        .name_template
+        .split('/')
+        .map(|part| {
+            let rendered = values.iter().fold(part.to_string(), |part, (key, value)| {
+                part.replace(&format!("{{{}}}", key), &sanitize(value))
+            });
+            sanitize(&rendered)
+        })
+        .filter(|part| !part.is_empty() && part != "." && part != "..")
+        .collect();
+
+    match parts.split_last() {
+        Some((file, folders)) => {
+            path.extend(folders);
+            path.push(format!("{}.replay", file));
+        }
+        None => path.push(format!("{}.replay", sanitize(&name))),
+    }
+    path
+}
+
+/// Replace characters that can't be used in file names, and trim the result
+fn sanitize(value: &str) -> String {
+    let cleaned: String = value
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    // Windows drops trailing dots and spaces from file names
+    cleaned
+        .trim_start()
+        .trim_end_matches(|c: char| c == '.' || c.is_whitespace())
+        .to_string()
+}
+
+/// Next free path: "name.replay", "name (2).replay", ...
+fn free_path(path: PathBuf) -> PathBuf {
+    if !path.exists() {
+        return path;
+    }
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    (2..)
+        .map(|n| path.with_file_name(format!("{} ({}).replay", stem, n)))
+        .find(|candidate| !candidate.exists())
+        .unwrap_or(path)
+}
+
+/// Move or copy an uploaded replay to the local archive, returns where it went
+///
+/// `upload_path` is the file that was uploaded (the staged copy), used when the
+/// original is gone. Replays only in the staging cache are always copied.
+pub fn archive_replay(
+    ctx: &AgentContext,
+    policy: &LocalArchivePolicy,
+    record: &UploadRecord,
+    upload_path: &Path,
+) -> Result<Option<String>, String> {
+    if policy.action == ArchiveAction::None || policy.folder.is_empty() {
+        return Ok(None);
+    }
+
+    let original = Path::new(&record.file_path);
+    let (source, action) = if original.is_file() && !staging::is_staged(ctx, original) {
+        (original, policy.action)
+    } else {
+        (upload_path, ArchiveAction::Copy)
+    };
+
+    let target = free_path(archive_path(policy, record));
+    if let Some(dir) = target.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create archive folder: {}", e))?;
+    }
+
+    let result = match action {
+        // Renaming fails across drives, copy and delete instead
+        ArchiveAction::Move => std::fs::rename(source, &target)
+            .or_else(|_| std::fs::copy(source, &target).and_then(|_| std::fs::remove_file(source))),
+        _ => std::fs::copy(source, &target).map(|_| ()),
+    };
+    result.map_err(|e| format!("Failed to archive {}: {}", record.filename, e))?;
+
+    tracing::info!("Archived {} to {}", record.filename, target.display());
+    Ok(Some(target.to_string_lossy().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ReplayMetadata;
+
+    fn policy(name_template: &str) -> LocalArchivePolicy {
+        LocalArchivePolicy {
+            action: ArchiveAction::Copy,
+            folder: "/archive".to_string(),
+            name_template: name_template.to_string(),
+        }
+    }
+
+    fn record(metadata: Option<ReplayMetadata>) -> UploadRecord {
+        let mut record: UploadRecord = serde_json::from_value(serde_json::json!({
+            "id": "1",
+            "filename": "ABCD.replay",
+            "filePath": "/replays/ABCD.replay",
+            "status": "completed",
+            "attempts": 1,
+            "createdAt": "2024-01-15T20:30:12Z",
+        }))
+        .unwrap();
+        record.metadata = metadata;
+        record
+    }
+
+    fn metadata() -> ReplayMetadata {
+        ReplayMetadata {
+            map_name: Some("DFH Stadium".to_string()),
+            match_type: Some("Online".to_string()),
+            team_size: Some(2),
+            team0_score: 3,
+            team1_score: 1,
+            player_name: Some("Player".to_string()),
+            date: Some("2024-01-15 20-30-12".to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn path(template: &str, metadata: Option<ReplayMetadata>) -> PathBuf {
+        archive_path(&policy(template), &record(metadata))
+    }
+
+    #[test]
+    fn renders_every_placeholder() {
+        assert_eq!(
+            path("{playlist}/{date} {time} {map} {score} {player} {name}", Some(metadata())),
+            Path::new("/archive/2v2 Online/2024-01-15 20-30-12 DFH Stadium 3-1 Player ABCD.replay")
+        );
+
+        // Without metadata: the upload's local date and placeholders for the rest
+        let created = chrono::DateTime::parse_from_rfc3339("2024-01-15T20:30:12Z")
+            .unwrap()
+            .with_timezone(&chrono::Local);
+        assert_eq!(
+            path("{date} {time} {playlist} {map} {score} {player} {name}", None),
+            Path::new("/archive").join(format!(
+                "{} Unknown Unknown 0-0 Unknown ABCD.replay",
+                created.format("%Y-%m-%d %H-%M-%S")
+            ))
+        );
+
+        let playlist = |team_size, match_type: Option<&str>| {
+            path(
+                "{playlist}",
+                Some(ReplayMetadata {
+                    team_size,
+                    match_type: match_type.map(str::to_string),
+                    ..Default::default()
+                }),
+            )
+        };
+        assert_eq!(playlist(Some(3), None), Path::new("/archive/3v3.replay"));
+        assert_eq!(playlist(None, Some("Private")), Path::new("/archive/Private.replay"));
+        assert_eq!(playlist(None, None), Path::new("/archive/Unknown.replay"));
+    }
+
+    #[test]
+    fn values_and_templates_stay_in_the_archive_folder() {
+        assert_eq!(
+            path("../../{map}/../{name}", Some(metadata())),
+            Path::new("/archive/DFH Stadium/ABCD.replay")
+        );
+
+        // Values can't add folders or climb out of one
+        let map = |map_name: &str| {
+            path(
+                "{map}/{name}",
+                Some(ReplayMetadata {
+                    map_name: Some(map_name.to_string()),
+                    ..Default::default()
+                }),
+            )
+        };
+        assert_eq!(map("../../etc"), Path::new("/archive/.._.._etc/ABCD.replay"));
+        assert_eq!(map(".."), Path::new("/archive/ABCD.replay"));
+        assert_eq!(map("C:\\Windows"), Path::new("/archive/C__Windows/ABCD.replay"));
+    }
+
+    #[test]
+    fn drops_empty_parts() {
+        assert_eq!(
+            path("//{playlist}///{name}/", Some(metadata())),
+            Path::new("/archive/2v2 Online/ABCD.replay")
+        );
+        assert_eq!(
+            path("{player}/ . /{name}", Some(ReplayMetadata {
+                player_name: Some("   ".to_string()),
+                ..Default::default()
+            })),
+            Path::new("/archive/ABCD.replay")
+        );
+
+        // Nothing left of the template: the replay's own name
+        assert_eq!(path("", None), Path::new("/archive/ABCD.replay"));
+        assert_eq!(path("/./..", None), Path::new("/archive/ABCD.replay"));
+    }
+
+    #[test]
+    fn sanitizes_file_names() {
+        assert_eq!(sanitize("a/b\\c:d*e?f\"g<h>i|j"), "a_b_c_d_e_f_g_h_i_j");
+        assert_eq!(sanitize("tab\there\n"), "tab_here_");
+        assert_eq!(sanitize("  name. . "), "name");
+        assert_eq!(sanitize("..."), "");
+    }
+
+    #[test]
+    fn finds_unknown_placeholders() {
+        for placeholder in PLACEHOLDERS {
+            assert!(unknown_placeholders(&format!("{{{}}}", placeholder)).is_empty());
+        }
+        assert_eq!(
+            unknown_placeholders("{date} {Map} {}/{goals} {time"),
+            vec!["Map", "", "goals"]
+        );
+        assert!(unknown_placeholders("no placeholders").is_empty());
+    }
+}
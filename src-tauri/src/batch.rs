@@ -0,0 +1,572 @@
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::agent::AgentContext;
+use crate::archive;
+use crate::history;
+use crate::library;
+use crate::replay;
+use crate::staging::{self, StagedGuard};
+use crate::types::{
+    BatchFileIssue, BatchProgress, BatchRequest, BatchStarted, BatchSummary, UploadRecord,
+    UploadSource, UploadStatus, Visibility,
+};
+use crate::uploader::{self, Uploader, UPLOAD_HELD_ERROR};
+use crate::watcher::MAX_FILE_SIZE;
+
+/// Subfolders nested deeper than this are not scanned
+const MAX_DEPTH: usize = 32;
+
+/// Files of a batch request to upload, oldest first, and the ones left out
+pub struct BatchFiles {
+    pub files: Vec<BatchFile>,
+    pub skipped: Vec<BatchFileIssue>,
+    /// Keeps replays extracted from archives in the staging cache until the batch is done
+    pub staged: Vec<StagedGuard>,
+}
+
+/// A replay to upload in a batch
+#[derive(Debug, Clone)]
+pub struct BatchFile {
+    pub file_path: String,
+    /// Path reported in progress and failures: the file, or the archive entry it came from
+    pub display_path: String,
+    /// `.zip` archive the replay was extracted from
+    pub archive_name: Option<String>,
+}
+
+impl BatchFile {
+    fn new(path: &Path) -> Self {
+        let file_path = path.to_string_lossy().to_string();
+        Self {
+            display_path: file_path.clone(),
+            file_path,
+            archive_name: None,
+        }
+    }
+}
+
+/// Validate and deduplicate the replays of a batch request
+///
+/// Folders are searched for replays (recursively if requested) and the replays
+/// of `.zip` archives are extracted to the staging cache. Files are left out
+/// when they aren't readable replays, are outside the date range, are listed twice,
+/// are the same replay as another file, or were already uploaded.
+pub fn collect(ctx: &AgentContext, request: &BatchRequest) -> Result<BatchFiles, String> {
+    let after = parse_bound(request.modified_after.as_deref())?;
+    let before = parse_bound(request.modified_before.as_deref())?;
+
+    let mut candidates = Vec::new();
+    let mut extracted_files = Vec::new();
+    let mut archive_times = HashMap::new();
+    let mut staged = Vec::new();
+    let mut skipped = Vec::new();
+    for path in &request.paths {
+        let path = PathBuf::from(path);
+        if path.is_dir() {
+            find_replays(&path, request.recursive, 0, &mut candidates);
+        } else if !path.is_file() {
+            skipped.push(issue(&path, "File not found"));
+        } else if archive::is_archive(&path) {
+            match archive::extract(ctx, &path) {
+                Ok(extracted) => {
+                    skipped.extend(extracted.skipped);
+                    staged.push(extracted.guard);
+                    for file in extracted.files {
+                        extracted_files.push(file.path.clone());
+                        if let Some(modified) = extracted.modified {
+                            archive_times.insert(file.path.clone(), modified);
+                        }
+                        candidates.push(BatchFile {
+                            file_path: file.path.to_string_lossy().to_string(),
+                            display_path: file.entry_path,
+                            archive_name: Some(extracted.archive_name.clone()),
+                        });
+                    }
+                }
+                Err(e) => skipped.push(issue(&path, &e)),
+            }
+        } else if !library::is_replay(&path) {
+            skipped.push(issue(&path, "Not a replay file"));
+        } else {
+            candidates.push(BatchFile::new(&path));
+        }
+    }
+
+    let history = history::store(ctx)?;
+    let mut seen_paths = HashSet::new();
+    let mut seen_replays: HashMap<String, String> = HashMap::new();
+    let mut files = Vec::new();
+
+    for file in candidates {
+        let path = PathBuf::from(&file.file_path);
+        let mut skip = |reason: &str| {
+            skipped.push(BatchFileIssue {
+                file_path: file.display_path.clone(),
+                reason: reason.to_string(),
+            })
+        };
+        if !seen_paths.insert(file.file_path.clone()) {
+            skip("Listed more than once");
+            continue;
+        }
+
+        let Ok(metadata) = std::fs::metadata(&path) else {
+            skip("File not found");
+            continue;
+        };
+        if metadata.len() == 0 {
+            skip("Empty file");
+            continue;
+        }
+        if metadata.len() > MAX_FILE_SIZE {
+            skip("File too large (over 50 MB)");
+            continue;
+        }
+
+        // Replays extracted from an archive are as old as the archive
+        let modified: DateTime<Utc> = archive_times
+            .get(&path)
+            .copied()
+            .map(Ok)
+            .unwrap_or_else(|| metadata.modified())
+            .map(DateTime::from)
+            .unwrap_or_default();
+        if after.is_some_and(|after| modified < after)
+            || before.is_some_and(|before| modified > before)
+        {
+            skip("Modified outside the date range");
+            continue;
+        }
+
+        let uploaded = history
+            .latest_for_path(&file.file_path)?
+            .is_some_and(|record| matches!(record.status, UploadStatus::Completed));
+        if uploaded {
+            skip("Already uploaded");
+            continue;
+        }
+
+        let replay_guid = match replay::parse_header(&path) {
+            Ok(header) => header.replay_guid,
+            Err(e) => {
+                skip(&format!("Not a valid replay: {}", e));
+                continue;
+            }
+        };
+        if let Some(guid) = replay_guid {
+            // Renamed or moved since it was uploaded
+            if let Some(record) = history.completed_for_replay(&guid)? {
+                skip(&format!("Already uploaded as {}", record.filename));
+                continue;
+            }
+            if let Some(original) = seen_replays.get(&guid) {
+                skip(&format!("Same replay as {}", original));
+                continue;
+            }
+            seen_replays.insert(guid, file.display_path.clone());
+        }
+
+        files.push((modified, file));
+    }
+
+    // Extracted replays left out are not needed anymore
+    let kept: HashSet<&str> = files
+        .iter()
+        .map(|(_, file)| file.file_path.as_str())
+        .collect();
+    for path in &extracted_files {
+        if !kept.contains(path.to_string_lossy().as_ref()) {
+            staging::remove(ctx, path);
+        }
+    }
+
+    files.sort_by(|(a_modified, a), (b_modified, b)| {
+        (a_modified, &a.file_path).cmp(&(b_modified, &b.file_path))
+    });
+    Ok(BatchFiles {
+        files: files.into_iter().map(|(_, file)| file).collect(),
+        skipped,
+        staged,
+    })
+}
+
+/// Upload one file of a batch, tagged with the batch and its archive
+pub async fn upload_file(
+    ctx: &AgentContext,
+    uploader: &Uploader,
+    file: &BatchFile,
+    batch_id: &str,
+    visibility: Option<Visibility>,
+) -> Result<UploadRecord, String> {
+    let source = UploadSource {
+        batch_id: Some(batch_id.to_string()),
+        archive_name: file.archive_name.clone(),
+    };
+    uploader
+        .upload_replay_from(ctx, &file.file_path, visibility, source)
+        .await
+}
+
+/// Validate a batch request and upload its replays in the background
+/// Progress is reported with `batch_progress` events and the outcome with `batch_completed`.
+pub async fn start(ctx: &AgentContext, request: BatchRequest) -> Result<BatchStarted, String> {
+    let visibility = request.visibility.clone();
+    let collect_ctx = ctx.clone();
+    let BatchFiles {
+        files,
+        skipped,
+        staged,
+    } = ctx
+        .spawn_blocking(move || collect(&collect_ctx, &request))
+        .await
+        .map_err(|e| format!("Failed to collect replays: {}", e))??;
+
+    let started = BatchStarted {
+        batch_id: uuid::Uuid::new_v4().to_string(),
+        total: files.len() as u32,
+        skipped,
+    };
+    tracing::info!(
+        "Batch {}: uploading {} replays, {} skipped",
+        started.batch_id,
+        started.total,
+        started.skipped.len()
+    );
+    ctx.emit("batch_started", &started);
+
+    let task_ctx = ctx.clone();
+    let batch_id = started.batch_id.clone();
+    let skipped = started.skipped.len() as u32;
+    ctx.spawn(async move {
+        run(&task_ctx, batch_id, files, visibility, skipped).await;
+        drop(staged);
+    });
+
+    Ok(started)
+}
+
+/// Upload a batch's files one after another
+async fn run(
+    ctx: &AgentContext,
+    batch_id: String,
+    files: Vec<BatchFile>,
+    visibility: Option<Visibility>,
+    skipped: u32,
+) {
+    let started_at = ctx.now().to_rfc3339();
+    let uploader = Uploader::new();
+    let mut progress = BatchProgress {
+        batch_id: batch_id.clone(),
+        total: files.len() as u32,
+        ..Default::default()
+    };
+    let mut failures = Vec::new();
+
+    for file in files {
+        // Leave the rest for another time when the agent is stopping
+        if ctx.is_shutting_down() {
+            break;
+        }
+
+        progress.current_file = Some(file.display_path.clone());
+        ctx.emit("batch_progress", &progress);
+
+        match upload_file(ctx, &uploader, &file, &batch_id, visibility.clone()).await {
+            Ok(record) if matches!(record.status, UploadStatus::Skipped) => progress.skipped += 1,
+            Ok(_) => progress.completed += 1,
+            Err(e) if e == UPLOAD_HELD_ERROR => progress.held += 1,
+            Err(e) => {
+                progress.failed += 1;
+                failures.push(BatchFileIssue {
+                    file_path: file.display_path,
+                    reason: e,
+                });
+            }
+        }
+
+        let done = progress.completed + progress.failed + progress.held + progress.skipped;
+        progress.percentage = (done * 100 / progress.total.max(1)) as u8;
+    }
+
+    progress.current_file = None;
+    ctx.emit("batch_progress", &progress);
+
+    let summary = BatchSummary {
+        batch_id,
+        total: progress.total,
+        completed: progress.completed,
+        failed: progress.failed,
+        held: progress.held,
+        skipped: skipped + progress.skipped,
+        failures,
+        started_at,
+        finished_at: ctx.now().to_rfc3339(),
+    };
+    tracing::info!(
+        "Batch {} finished: {} uploaded, {} failed, {} held, {} skipped",
+        summary.batch_id,
+        summary.completed,
+        summary.failed,
+        summary.held,
+        summary.skipped
+    );
+    ctx.emit("batch_completed", &summary);
+
+    uploader::notify(
+        ctx,
+        "Batch upload finished",
+        &format!(
+            "{} uploaded, {} failed, {} skipped",
+            summary.completed, summary.failed, summary.skipped
+        ),
+    );
+}
+
+/// Add the replays in a folder, and in its subfolders if `recursive`
+/// Symlinked folders are not followed.
+fn find_replays(dir: &Path, recursive: bool, depth: usize, found: &mut Vec<BatchFile>) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            tracing::warn!("Failed to read {}: {}", dir.display(), e);
+            return;
+        }
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+
+        if file_type.is_dir() {
+            if recursive && depth < MAX_DEPTH {
+                find_replays(&path, recursive, depth + 1, found);
+            }
+        } else if library::is_replay(&path) {
+            found.push(BatchFile::new(&path));
+        }
+    }
+}
+
+fn parse_bound(value: Option<&str>) -> Result<Option<DateTime<Utc>>, String> {
+    value
+        .map(|value| {
+            DateTime::parse_from_rfc3339(value)
+                .map(|date| date.with_timezone(&Utc))
+                .map_err(|e| format!("Invalid date {}: {}", value, e))
+        })
+        .transpose()
+}
+
+fn issue(path: &Path, reason: &str) -> BatchFileIssue {
+    BatchFileIssue {
+        file_path: path.to_string_lossy().to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::testing::TestAgent;
+    use crate::replay::tests::replay_with_guid;
+    use crate::types::ReplayMetadata;
+    use std::time::{Duration, SystemTime};
+
+    struct TestBatch {
+        agent: TestAgent,
+    }
+
+    impl TestBatch {
+        fn new() -> Self {
+            Self {
+                agent: TestAgent::new(),
+            }
+        }
+
+        /// Write a replay under the replays folder, named after its GUID
+        fn replay(&self, folder: &str, guid: &str) -> String {
+            let dir = self.agent.dir.join("replays").join(folder);
+            std::fs::create_dir_all(&dir).unwrap();
+            let path = dir.join(format!("{}.replay", guid));
+            std::fs::write(&path, replay_with_guid(guid)).unwrap();
+            path.to_string_lossy().to_string()
+        }
+
+        fn folder(&self) -> String {
+            self.agent.dir.join("replays").to_string_lossy().to_string()
+        }
+
+        fn collect(&self, request: BatchRequest) -> BatchFiles {
+            collect(&self.agent.ctx, &request).unwrap()
+        }
+    }
+
+    fn paths(files: &BatchFiles) -> Vec<&str> {
+        files.files.iter().map(|file| file.file_path.as_str()).collect()
+    }
+
+    fn reasons(files: &BatchFiles) -> Vec<(&str, &str)> {
+        files
+            .skipped
+            .iter()
+            .map(|issue| (issue.file_path.as_str(), issue.reason.as_str()))
+            .collect()
+    }
+
+    fn set_modified(path: &str, time: SystemTime) {
+        std::fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(time)
+            .unwrap();
+    }
+
+    #[test]
+    fn folders_are_searched_down_to_the_maximum_depth() {
+        let batch = TestBatch::new();
+        let top = batch.replay("", "top");
+        let nested = batch.replay("a/b", "nested");
+        let deepest_folder = vec!["d"; MAX_DEPTH].join("/");
+        let deepest = batch.replay(&deepest_folder, "deepest");
+        batch.replay(&format!("{}/d", deepest_folder), "too-deep");
+
+        let flat = batch.collect(BatchRequest {
+            paths: vec![batch.folder()],
+            ..Default::default()
+        });
+        assert_eq!(paths(&flat), vec![top.as_str()]);
+
+        let recursive = batch.collect(BatchRequest {
+            paths: vec![batch.folder()],
+            recursive: true,
+            ..Default::default()
+        });
+        let mut found = paths(&recursive);
+        found.sort();
+        let mut expected = vec![top.as_str(), nested.as_str(), deepest.as_str()];
+        expected.sort();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn date_range_bounds_are_inclusive() {
+        let batch = TestBatch::new();
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let end = start + Duration::from_secs(3600);
+        let files: Vec<String> = ["before", "start", "end", "after"]
+            .iter()
+            .map(|guid| batch.replay("", guid))
+            .collect();
+        for (path, time) in files.iter().zip([
+            start - Duration::from_secs(1),
+            start,
+            end,
+            end + Duration::from_secs(1),
+        ]) {
+            set_modified(path, time);
+        }
+
+        let request = BatchRequest {
+            paths: vec![batch.folder()],
+            modified_after: Some(DateTime::<Utc>::from(start).to_rfc3339()),
+            modified_before: Some(DateTime::<Utc>::from(end).to_rfc3339()),
+            ..Default::default()
+        };
+        let collected = batch.collect(request.clone());
+
+        // Oldest first
+        assert_eq!(paths(&collected), vec![files[1].as_str(), files[2].as_str()]);
+        let mut skipped = reasons(&collected);
+        skipped.sort();
+        assert_eq!(
+            skipped,
+            vec![
+                (files[3].as_str(), "Modified outside the date range"),
+                (files[0].as_str(), "Modified outside the date range"),
+            ]
+        );
+
+        let invalid = BatchRequest {
+            modified_after: Some("last week".to_string()),
+            ..request
+        };
+        assert!(collect(&batch.agent.ctx, &invalid).is_err());
+    }
+
+    #[test]
+    fn files_listed_twice_are_kept_once() {
+        let batch = TestBatch::new();
+        let replay = batch.replay("", "only");
+
+        let collected = batch.collect(BatchRequest {
+            paths: vec![replay.clone(), replay.clone(), batch.folder()],
+            ..Default::default()
+        });
+        assert_eq!(paths(&collected), vec![replay.as_str()]);
+        assert_eq!(
+            reasons(&collected),
+            vec![
+                (replay.as_str(), "Listed more than once"),
+                (replay.as_str(), "Listed more than once"),
+            ]
+        );
+    }
+
+    #[test]
+    fn replays_are_deduplicated_by_guid() {
+        let batch = TestBatch::new();
+        let original = batch.replay("", "match");
+        let copy = batch.agent.dir.join("replays").join("copy of match.replay");
+        std::fs::copy(&original, &copy).unwrap();
+        let copy = copy.to_string_lossy().to_string();
+
+        // Uploaded under another name, and failed under yet another
+        let uploaded = batch.replay("", "uploaded");
+        let failed = batch.replay("", "failed");
+        let history = history::store(&batch.agent.ctx).unwrap();
+        for (guid, status) in [("uploaded", "completed"), ("failed", "failed")] {
+            let mut record: UploadRecord = serde_json::from_value(serde_json::json!({
+                "id": guid,
+                "filename": format!("renamed-{}.replay", guid),
+                "filePath": format!("/elsewhere/renamed-{}.replay", guid),
+                "status": status,
+                "attempts": 1,
+                "createdAt": "2024-01-15T20:30:12Z",
+            }))
+            .unwrap();
+            record.metadata = Some(ReplayMetadata {
+                replay_guid: Some(guid.to_string()),
+                ..Default::default()
+            });
+            history.save(&record).unwrap();
+        }
+
+        // The first one listed is kept
+        let collected = batch.collect(BatchRequest {
+            paths: vec![original.clone(), copy.clone(), uploaded.clone(), failed.clone()],
+            ..Default::default()
+        });
+
+        let mut kept = paths(&collected);
+        kept.sort();
+        let mut expected = vec![original.as_str(), failed.as_str()];
+        expected.sort();
+        assert_eq!(kept, expected);
+
+        let mut skipped = reasons(&collected);
+        skipped.sort();
+        let same = format!("Same replay as {}", original);
+        assert_eq!(
+            skipped,
+            vec![
+                (copy.as_str(), same.as_str()),
+                (uploaded.as_str(), "Already uploaded as renamed-uploaded.replay"),
+            ]
+        );
+    }
+}
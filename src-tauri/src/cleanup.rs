@@ -0,0 +1,267 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::agent::AgentContext;
+use crate::config;
+use crate::history;
+use crate::library;
+use crate::replay;
+use crate::types::{CleanupPolicy, CleanupResult, UploadRecord, UploadStatus};
+
+/// Apply the cleanup policy in the background
+pub fn spawn(ctx: &AgentContext) {
+    let task_ctx = ctx.clone();
+    ctx.spawn_blocking(move || {
+        if let Err(e) = run(&task_ctx) {
+            tracing::warn!("Replay cleanup failed: {}", e);
+        }
+    });
+}
+
+/// Delete uploaded replays from the watched folders as the cleanup policy says
+/// Does nothing while the policy is disabled, in a dry run or while another cleanup runs
+pub fn run(ctx: &AgentContext) -> Result<CleanupResult, String> {
+    // Uploads finishing together clean up once
+    let Some(_running) = ctx.try_start_cleanup() else {
+        return Ok(CleanupResult::default());
+    };
+
+    let config = config::load_config(ctx)?;
+    if !config.cleanup.enabled || config::is_dry_run(&config) {
+        return Ok(CleanupResult::default());
+    }
+
+    let mut result = CleanupResult::default();
+    for folder in config.watched_folders() {
+        clean_folder(ctx, &config.cleanup, Path::new(&folder), &mut result)?;
+    }
+
+    if result.deleted > 0 {
+        tracing::info!(
+            "Cleaned up {} uploaded replays ({} MB)",
+            result.deleted,
+            result.freed_bytes / (1024 * 1024)
+        );
+        ctx.emit("replays_cleaned_up", &result);
+    }
+    Ok(result)
+}
+
+/// Delete a folder's uploaded replays that are too old or beyond the count to keep
+/// Every replay counts towards `max_count`, only uploaded ones are deleted
+fn clean_folder(
+    ctx: &AgentContext,
+    policy: &CleanupPolicy,
+    folder: &Path,
+    result: &mut CleanupResult,
+) -> Result<(), String> {
+    let Ok(entries) = std::fs::read_dir(folder) else {
+        return Ok(());
+    };
+
+    let mut replays: Vec<(PathBuf, u64, SystemTime)> = entries
+        .flatten()
+        .filter(|entry| library::is_replay(&entry.path()))
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok().filter(|m| m.is_file())?;
+            let modified = metadata.modified().ok()?;
+            Some((entry.path(), metadata.len(), modified))
+        })
+        .collect();
+
+    // Newest first, the ones past `max_count` are beyond the count to keep
+    replays.sort_by_key(|(_, _, modified)| std::cmp::Reverse(*modified));
+
+    let expires = policy.max_age_days.map(|days| {
+        SystemTime::from(ctx.now())
+            .checked_sub(Duration::from_secs(days as u64 * 24 * 60 * 60))
+            .unwrap_or(SystemTime::UNIX_EPOCH)
+    });
+    let history = history::store(ctx)?;
+
+    for (index, (path, size, modified)) in replays.into_iter().enumerate() {
+        let too_old = expires.is_some_and(|expires| modified < expires);
+        let beyond_count = policy
+            .max_count
+            .is_some_and(|max_count| index >= max_count as usize);
+        if !too_old && !beyond_count {
+            continue;
+        }
+
+        // Only replays whose latest upload is confirmed, and still the file that was uploaded
+        let uploaded = history
+            .latest_for_path(&path.to_string_lossy())?
+            .is_some_and(|record| {
+                matches!(record.status, UploadStatus::Completed)
+                    && record.replay_id.is_some()
+                    && is_uploaded_file(&path, size, &record)
+            });
+        if !uploaded {
+            continue;
+        }
+
+        match std::fs::remove_file(&path) {
+            Ok(()) => {
+                tracing::debug!("Deleted uploaded replay {}", path.display());
+                library::remove_file(ctx, &path);
+                result.deleted += 1;
+                result.freed_bytes += size;
+            }
+            Err(e) => tracing::warn!("Failed to delete {}: {}", path.display(), e),
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether a file is the replay a record uploaded, by the GUID in its header,
+/// or by its size for records without a GUID
+/// Another replay saved under the same name since then doesn't match
+fn is_uploaded_file(path: &Path, size: u64, record: &UploadRecord) -> bool {
+    match record.metadata.as_ref().and_then(|m| m.replay_guid.as_deref()) {
+        Some(guid) => replay::parse_header(path)
+            .is_ok_and(|header| header.replay_guid.as_deref() == Some(guid)),
+        None => record.file_size == Some(size),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::testing::TestAgent;
+    use crate::replay::tests::replay_with_guid;
+    use crate::types::{AppConfig, ReplayMetadata};
+    use chrono::{DateTime, Utc};
+
+    struct TestCleanup {
+        agent: TestAgent,
+        folder: PathBuf,
+    }
+
+    impl TestCleanup {
+        fn new(policy: CleanupPolicy) -> Self {
+            let agent = TestAgent::new();
+            let folder = agent.dir.join("replays");
+            std::fs::create_dir_all(&folder).unwrap();
+            let config = AppConfig {
+                replay_folder: folder.to_string_lossy().to_string(),
+                cleanup: policy,
+                ..Default::default()
+            };
+            Self {
+                agent: agent.with_config(&config),
+                folder,
+            }
+        }
+
+        /// Write a replay with a GUID, last modified at `modified`
+        fn replay(&self, name: &str, guid: &str, modified: &str) -> PathBuf {
+            let path = self.folder.join(name);
+            std::fs::write(&path, replay_with_guid(guid)).unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(date(modified).into())
+                .unwrap();
+            path
+        }
+
+        /// Record a completed upload of a file, with its size and GUID when given
+        fn uploaded(&self, path: &Path, file_size: Option<u64>, guid: Option<&str>) {
+            let mut record: UploadRecord = serde_json::from_value(serde_json::json!({
+                "id": uuid::Uuid::new_v4().to_string(),
+                "filename": path.file_name().unwrap().to_string_lossy(),
+                "filePath": path.to_string_lossy(),
+                "status": "completed",
+                "replayId": "replay-1",
+                "attempts": 1,
+                "createdAt": "2024-01-15T20:00:00Z",
+            }))
+            .unwrap();
+            record.file_size = file_size;
+            record.metadata = guid.map(|guid| ReplayMetadata {
+                replay_guid: Some(guid.to_string()),
+                ..Default::default()
+            });
+            history::store(&self.agent.ctx).unwrap().save(&record).unwrap();
+        }
+    }
+
+    fn date(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc)
+    }
+
+    fn size(path: &Path) -> u64 {
+        std::fs::metadata(path).unwrap().len()
+    }
+
+    #[test]
+    fn deletes_uploaded_replays_older_than_the_clock_allows() {
+        let test = TestCleanup::new(CleanupPolicy {
+            enabled: true,
+            max_age_days: Some(30),
+            max_count: None,
+        });
+        let old = test.replay("old.replay", "AAAA", "2024-01-20T12:00:00Z");
+        let recent = test.replay("recent.replay", "BBBB", "2024-02-20T12:00:00Z");
+        let not_uploaded = test.replay("not-uploaded.replay", "CCCC", "2024-01-20T12:00:00Z");
+        test.uploaded(&old, Some(size(&old)), None);
+        test.uploaded(&recent, Some(size(&recent)), None);
+
+        let result = run(&test.agent.ctx).unwrap();
+
+        assert_eq!(result.deleted, 1);
+        assert_eq!(result.freed_bytes, replay_with_guid("AAAA").len() as u64);
+        assert!(!old.exists());
+        assert!(recent.exists());
+        assert!(not_uploaded.exists());
+    }
+
+    #[test]
+    fn keeps_another_replay_saved_under_an_uploaded_path() {
+        let test = TestCleanup::new(CleanupPolicy {
+            enabled: true,
+            max_age_days: None,
+            max_count: Some(0),
+        });
+
+        // Matched by the GUID, or by size when no GUID was recorded
+        let same_guid = test.replay("same-guid.replay", "BBBB", "2024-02-01T12:00:00Z");
+        test.uploaded(&same_guid, Some(size(&same_guid) + 1), Some("BBBB"));
+        let same_size = test.replay("same-size.replay", "AAAA", "2024-02-01T12:00:00Z");
+        test.uploaded(&same_size, Some(size(&same_size)), None);
+
+        // Replaced by another replay since the upload, replays of the same size included
+        let replaced = test.replay("replaced.replay", "CCCC", "2024-02-01T12:00:00Z");
+        test.uploaded(&replaced, Some(size(&replaced)), Some("DDDD"));
+        let unknown = test.replay("unknown.replay", "EEEE", "2024-02-01T12:00:00Z");
+        test.uploaded(&unknown, None, None);
+
+        let result = run(&test.agent.ctx).unwrap();
+
+        assert_eq!(result.deleted, 2);
+        assert!(!same_guid.exists());
+        assert!(!same_size.exists());
+        assert!(replaced.exists());
+        assert!(unknown.exists());
+    }
+
+    #[test]
+    fn one_cleanup_runs_at_a_time() {
+        let test = TestCleanup::new(CleanupPolicy {
+            enabled: true,
+            max_age_days: None,
+            max_count: Some(0),
+        });
+        let path = test.replay("a.replay", "AAAA", "2024-02-01T12:00:00Z");
+        test.uploaded(&path, Some(size(&path)), None);
+
+        let running = test.agent.ctx.try_start_cleanup().unwrap();
+        assert_eq!(run(&test.agent.ctx).unwrap().deleted, 0);
+        assert!(path.exists());
+
+        drop(running);
+        assert_eq!(run(&test.agent.ctx).unwrap().deleted, 1);
+    }
+}
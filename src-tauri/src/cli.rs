@@ -0,0 +1,498 @@
+use clap::{CommandFactory, Parser, Subcommand};
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::agent::AgentContext;
+use crate::commands;
+use crate::config;
+use crate::control::{self, Client};
+use crate::headless;
+use crate::qr;
+use crate::types::{
+    AgentStatus, DevicePollResult, HistoryPage, UploadOutcome, UploadRecord, Visibility,
+};
+
+/// Command line of the agent binary
+#[derive(Parser)]
+#[command(
+    name = "ballcam-agent",
+    version,
+    about = "Uploads Rocket League replays to BallCam"
+)]
+pub struct Cli {
+    /// Run the watcher and uploader without a window, e.g. as a service
+    #[arg(long)]
+    pub headless: bool,
+
+    /// Install a systemd user service running the agent headless (Linux)
+    #[arg(long)]
+    pub install_service: bool,
+
+    /// Process replays without uploading them, recording them as skipped
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+
+    /// Start hidden in the tray (passed by autostart)
+    #[arg(long, hide = true)]
+    pub minimized: bool,
+
+    /// Replay files to upload or a ballcam:// link to open in the app
+    #[arg(value_name = "FILE|LINK")]
+    pub open: Vec<String>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Upload replay files and .zip archives of replays
+    Upload {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// public or unlisted (default: the configured visibility)
+        #[arg(long, value_parser = parse_visibility)]
+        visibility: Option<Visibility>,
+    },
+    /// Log in with a code shown in the terminal
+    Login,
+    /// Log out the active account
+    Logout,
+    /// Show the watcher, upload queue and account
+    Status {
+        /// Print JSON instead of text
+        #[arg(long)]
+        json: bool,
+    },
+    /// List recent uploads
+    History {
+        /// Only failed uploads
+        #[arg(long)]
+        failed: bool,
+        /// Print JSON instead of text
+        #[arg(long)]
+        json: bool,
+        /// Number of uploads to list
+        #[arg(long, default_value_t = 20)]
+        limit: u32,
+    },
+    /// Retry a failed upload by its history ID
+    Retry { id: String },
+}
+
+fn parse_visibility(value: &str) -> Result<Visibility, String> {
+    match value.to_lowercase().as_str() {
+        "public" => Ok(Visibility::Public),
+        "unlisted" => Ok(Visibility::Unlisted),
+        _ => Err("expected public or unlisted".to_string()),
+    }
+}
+
+/// Parse the command line, printing help, the version or errors to the terminal and exiting
+pub fn parse() -> Cli {
+    parse_from(std::env::args_os()).unwrap_or_else(|e| {
+        attach_console();
+        e.exit()
+    })
+}
+
+fn parse_from<I, T>(args: I) -> Result<Cli, clap::Error>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let cli = Cli::try_parse_from(args)?;
+
+    // Only `--dry-run` goes with a subcommand
+    if cli.command.is_some()
+        && (cli.headless || cli.install_service || cli.minimized || !cli.open.is_empty())
+    {
+        return Err(Cli::command().error(
+            clap::error::ErrorKind::ArgumentConflict,
+            "subcommands can't be combined with --headless, --install-service or files to open",
+        ));
+    }
+    // Without a window there's nothing to open them in
+    if (cli.headless || cli.install_service) && !cli.open.is_empty() {
+        return Err(Cli::command().error(
+            clap::error::ErrorKind::ArgumentConflict,
+            "files and links can't be opened with --headless or --install-service, use `upload`",
+        ));
+    }
+    Ok(cli)
+}
+
+/// Print to the terminal the agent was started from
+/// Release builds are GUI programs on Windows, without a console of their own
+#[cfg(windows)]
+fn attach_console() {
+    const ATTACH_PARENT_PROCESS: u32 = u32::MAX;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn AttachConsole(process_id: u32) -> i32;
+    }
+
+    // Fails when not started from a terminal, there's nothing to print to then
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+#[cfg(not(windows))]
+fn attach_console() {}
+
+/// Where commands run: in the running agent, or in this process when none is running
+enum Target {
+    Remote(Client),
+    Local(AgentContext),
+}
+
+impl Target {
+    /// The running agent, or a context for reading the config and history
+    async fn open() -> Result<Self, String> {
+        if let Some(client) = Client::connect().await {
+            return Ok(Target::Remote(client));
+        }
+
+        Ok(Target::Local(headless::build_context()?))
+    }
+
+    /// Like `open`, logged in with the configured access token when there is one, for uploads
+    async fn open_for_uploads() -> Result<Self, String> {
+        let target = Self::open().await?;
+        if let Target::Local(ctx) = &target {
+            commands::login_with_configured_token(ctx).await;
+        }
+        Ok(target)
+    }
+
+    async fn call(&mut self, method: &str, params: Value) -> Result<Value, String> {
+        match self {
+            Target::Remote(client) => client.call(method, params).await,
+            Target::Local(ctx) => control::dispatch(ctx, method, params)
+                .await
+                .map_err(|e| e.message),
+        }
+    }
+}
+
+/// Run a subcommand and return the process exit code
+/// `dry_run` also applies to a running agent the command is sent to
+pub fn run(command: Command, dry_run: bool) -> i32 {
+    attach_console();
+
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::from_default_env()
+                .add_directive(tracing::Level::WARN.into()),
+        )
+        .with_writer(std::io::stderr)
+        .init();
+
+    let runtime = match tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("Failed to start runtime: {}", e);
+            return 1;
+        }
+    };
+
+    match runtime.block_on(execute(command, dry_run)) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+async fn execute(command: Command, dry_run: bool) -> Result<i32, String> {
+    match command {
+        Command::Upload { files, visibility } => upload(files, visibility, dry_run).await,
+        Command::Login => login().await.map(|_| 0),
+        Command::Logout => logout().await.map(|_| 0),
+        Command::Status { json } => status(json).await.map(|_| 0),
+        Command::History {
+            failed,
+            json,
+            limit,
+        } => history(failed, json, limit).await.map(|_| 0),
+        // A dry run would turn the failed record into a skipped one
+        Command::Retry { .. } if dry_run => Err("Retries are disabled in dry-run mode".to_string()),
+        Command::Retry { id } => retry(&id).await.map(|_| 0),
+    }
+}
+
+async fn upload(
+    files: Vec<PathBuf>,
+    visibility: Option<Visibility>,
+    dry_run: bool,
+) -> Result<i32, String> {
+    // The running agent may have another working directory
+    let paths: Vec<String> = files
+        .iter()
+        .map(|file| {
+            std::path::absolute(file)
+                .unwrap_or_else(|_| file.clone())
+                .to_string_lossy()
+                .to_string()
+        })
+        .collect();
+
+    let mut target = Target::open_for_uploads().await?;
+    let result = target
+        .call(
+            "upload.files",
+            json!({ "paths": paths, "visibility": visibility, "dryRun": dry_run }),
+        )
+        .await?;
+    let outcomes: Vec<UploadOutcome> = from_value(result)?;
+
+    let mut failed = 0;
+    for outcome in &outcomes {
+        let skip_reason = outcome
+            .record
+            .as_ref()
+            .and_then(|record| record.skip_reason.as_deref());
+        match (&outcome.error, skip_reason) {
+            (None, Some(reason)) => println!("Skipped {}: {}", outcome.file_path, reason),
+            (None, None) => println!(
+                "Uploaded {}: {}",
+                outcome.file_path,
+                outcome
+                    .record
+                    .as_ref()
+                    .and_then(|record| record.replay_url.as_deref())
+                    .unwrap_or("-")
+            ),
+            (Some(error), _) => {
+                failed += 1;
+                eprintln!("Failed {}: {}", outcome.file_path, error);
+            }
+        }
+    }
+
+    Ok(if failed > 0 { 1 } else { 0 })
+}
+
+async fn login() -> Result<(), String> {
+    let ctx = headless::build_context()?;
+    let device = commands::request_device_code().await?;
+
+    println!(
+        "Open {} and enter the code: {}",
+        device.verification_url, device.user_code
+    );
+    if let Some(url) = &device.verification_url_complete {
+        match qr::render_terminal(url) {
+            Ok(code) => println!("\nOr scan this code with your phone:\n\n{}", code),
+            Err(e) => tracing::warn!("{}", e),
+        }
+    }
+    println!("Waiting for authorization...");
+
+    let deadline = Instant::now() + Duration::from_secs(device.expires_in.into());
+    let mut interval = Duration::from_secs(device.interval.max(1).into());
+    loop {
+        if Instant::now() >= deadline {
+            return Err("The code expired, run login again".to_string());
+        }
+        tokio::time::sleep(interval).await;
+
+        match commands::poll_device(&ctx, &device.device_code).await? {
+            DevicePollResult::Pending => {}
+            DevicePollResult::SlowDown => interval += Duration::from_secs(5),
+            DevicePollResult::Success(_) => break,
+            DevicePollResult::Expired => {
+                return Err("The code expired, run login again".to_string())
+            }
+            DevicePollResult::Denied => return Err("Access was denied".to_string()),
+        }
+    }
+
+    if let Some(session) = config::load_session(&ctx)? {
+        println!("Logged in as {}", session.user.username);
+    }
+    reload_running_agent().await;
+    Ok(())
+}
+
+async fn logout() -> Result<(), String> {
+    let ctx = headless::build_context()?;
+    let Some(session) = config::load_session(&ctx)? else {
+        println!("Not logged in");
+        return Ok(());
+    };
+
+    commands::logout_active(&ctx).await?;
+    println!("Logged out {}", session.user.username);
+    reload_running_agent().await;
+    Ok(())
+}
+
+/// Let a running agent pick up the session changed by this process
+async fn reload_running_agent() {
+    if let Some(mut client) = Client::connect().await {
+        if let Err(e) = client.call("session.reload", Value::Null).await {
+            eprintln!("The running agent didn't pick up the change: {}", e);
+        }
+    }
+}
+
+async fn status(json: bool) -> Result<(), String> {
+    let mut target = Target::open().await?;
+    let running = matches!(target, Target::Remote(_));
+    let status: AgentStatus = from_value(target.call("status", Value::Null).await?)?;
+
+    if json {
+        let output = json!({ "running": running, "status": status });
+        println!("{}", serde_json::to_string_pretty(&output).unwrap_or_default());
+        return Ok(());
+    }
+
+    println!("Agent:    {}", if running { "running" } else { "not running" });
+    if status.dry_run {
+        println!("Mode:     dry run, replays are not uploaded");
+    }
+    match &status.account {
+        Some(user) => println!("Account:  {} ({})", user.username, user.email),
+        None => println!("Account:  not logged in"),
+    }
+
+    let watcher = if !status.watcher.is_watching {
+        "stopped"
+    } else if status.watcher.is_paused {
+        "paused"
+    } else {
+        "watching"
+    };
+    println!("Watcher:  {}", watcher);
+    for folder in &status.watched_folders {
+        println!("          {}", folder);
+    }
+
+    println!("Uploads:  {} in progress", status.active_uploads);
+    if status.queue.paused_for_auth {
+        println!(
+            "          {} held until you log in again",
+            status.queue.held_jobs.len()
+        );
+    }
+    for file in &status.watcher.pending_files {
+        println!("          pending: {}", file);
+    }
+
+    Ok(())
+}
+
+async fn history(failed: bool, json: bool, limit: u32) -> Result<(), String> {
+    let mut target = Target::open().await?;
+    let result = target
+        .call("history.list", json!({ "failed": failed, "limit": limit }))
+        .await?;
+    let records = from_value::<HistoryPage>(result)?.records;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&records).unwrap_or_default());
+        return Ok(());
+    }
+
+    if records.is_empty() {
+        println!("No uploads");
+    }
+    for record in &records {
+        print_record(record);
+    }
+    Ok(())
+}
+
+async fn retry(id: &str) -> Result<(), String> {
+    let mut target = Target::open_for_uploads().await?;
+    let record: UploadRecord =
+        from_value(target.call("history.retry", json!({ "id": id })).await?)?;
+
+    print_record(&record);
+    Ok(())
+}
+
+/// One line per record: ID, status, date, file and URL, error or skip reason
+fn print_record(record: &UploadRecord) {
+    let detail = record
+        .replay_url
+        .as_deref()
+        .or(record.error_message.as_deref())
+        .or(record.skip_reason.as_deref())
+        .unwrap_or("");
+    println!(
+        "{}  {:<10}  {}  {}  {}",
+        record.id,
+        record.status.to_string(),
+        record.created_at.get(..19).unwrap_or(&record.created_at),
+        record.filename,
+        detail
+    );
+}
+
+fn from_value<T: serde::de::DeserializeOwned>(value: Value) -> Result<T, String> {
+    serde_json::from_value(value).map_err(|e| format!("Unexpected response: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::error::ErrorKind;
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        parse_from(std::iter::once("ballcam-agent").chain(args.iter().copied()))
+    }
+
+    fn upload_files(cli: &Cli) -> Vec<PathBuf> {
+        match &cli.command {
+            Some(Command::Upload { files, .. }) => files.clone(),
+            _ => panic!("not an upload"),
+        }
+    }
+
+    #[test]
+    fn dry_run_goes_before_or_after_the_subcommand() {
+        for args in [
+            ["--dry-run", "upload", "x.replay"],
+            ["upload", "--dry-run", "x.replay"],
+            ["upload", "x.replay", "--dry-run"],
+        ] {
+            let cli = parse(&args).unwrap();
+            assert!(cli.dry_run);
+            assert_eq!(upload_files(&cli), vec![PathBuf::from("x.replay")]);
+        }
+
+        let cli = parse(&["upload", "x.replay"]).unwrap();
+        assert!(!cli.dry_run);
+    }
+
+    #[test]
+    fn files_and_links_open_the_app() {
+        let cli = parse(&["--minimized"]).unwrap();
+        assert!(cli.minimized && cli.command.is_none());
+
+        let cli = parse(&["--dry-run", "x.replay", "ballcam://history/1"]).unwrap();
+        assert!(cli.dry_run && cli.command.is_none());
+        assert_eq!(cli.open, vec!["x.replay", "ballcam://history/1"]);
+    }
+
+    #[test]
+    fn rejects_unknown_and_conflicting_arguments() {
+        let error = |args: &[&str]| parse(args).err().map(|e| e.kind());
+
+        assert_eq!(error(&["--nope"]), Some(ErrorKind::UnknownArgument));
+        assert_eq!(error(&["upload", "x.replay", "--nope"]), Some(ErrorKind::UnknownArgument));
+        assert_eq!(error(&["upload"]), Some(ErrorKind::MissingRequiredArgument));
+        assert_eq!(error(&["--headless", "status"]), Some(ErrorKind::ArgumentConflict));
+        assert_eq!(error(&["--install-service", "login"]), Some(ErrorKind::ArgumentConflict));
+        assert_eq!(error(&["--headless", "x.replay"]), Some(ErrorKind::ArgumentConflict));
+    }
+}
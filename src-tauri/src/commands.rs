@@ -1,12 +1,30 @@
+use crate::agent::AgentContext;
+use crate::archive;
+use crate::batch;
+use crate::cleanup;
 use crate::config;
+use crate::desktop::PendingHistoryItem;
+use crate::export;
+use crate::history;
+use crate::library;
+use crate::oauth::{self, OAuthConfig};
+use crate::qr;
+use crate::queue;
+use crate::retry;
+use crate::stats;
 use crate::types::{
-    AppConfig, AuthSession, DeviceCodeResponse, DevicePollResult, DeviceTokenResponse,
-    FolderInfo, UploadRecord, UploadStats, UploadStatus, User, Visibility, WatcherState,
+    AccountInfo, AppConfig, AuthSession, BatchRequest, BatchStarted, BulkRetryResult,
+    ConfigChanged, ConfigFieldError,
+    DeviceCodeResponse, DevicePollResult, DeviceTokenResponse, FolderInfo, HistoryExportFormat,
+    HistoryImportResult, HistoryPage, HistoryQuery, LibraryPage, LibraryQuery, LibraryScanResult,
+    LibraryStatus, SaveConfigError, SessionKind, StatsQuery, UploadQueueState, UploadRecord,
+    UploadReport, UploadStats, User, Visibility, WatcherState,
 };
 use crate::uploader::Uploader;
-use crate::AppState;
-use std::path::PathBuf;
+use crate::watcher;
+use std::path::{Path, PathBuf};
 use tauri::{AppHandle, State};
+use tauri_plugin_autostart::ManagerExt;
 
 // Use localhost in dev mode, production URL otherwise
 #[cfg(dev)]
@@ -16,26 +34,102 @@ const API_BASE_URL: &str = "https://ballcam.tv/api";
 
 /// Get the current app configuration
 #[tauri::command]
-pub fn get_config(app: AppHandle) -> Result<AppConfig, String> {
-    config::load_config(&app)
+pub fn get_config(ctx: State<'_, AgentContext>) -> Result<AppConfig, String> {
+    config::load_config(&ctx)
 }
 
-/// Save app configuration
+/// Validate, save and apply app configuration
+/// Changed settings take effect immediately and `config_changed` is emitted
 #[tauri::command]
-pub fn save_config(app: AppHandle, new_config: AppConfig) -> Result<(), String> {
-    config::save_config(&app, &new_config)
+pub fn save_config(
+    app: AppHandle,
+    ctx: State<'_, AgentContext>,
+    mut new_config: AppConfig,
+) -> Result<(), SaveConfigError> {
+    let accounts = config::load_accounts(&ctx)?;
+    for folder in config::drop_stale_routes(&mut new_config, &accounts) {
+        tracing::warn!("Dropped route of {} to a removed account", folder);
+    }
+
+    let fields = config::validate_config(&new_config);
+    if !fields.is_empty() {
+        return Err(SaveConfigError {
+            message: "Invalid settings".to_string(),
+            fields,
+        });
+    }
+
+    let old_config = config::load_config(&ctx)?;
+    let changed = config::changed_fields(&old_config, &new_config);
+
+    // Sync autostart first so a failure leaves the saved config untouched
+    sync_autostart(&app, new_config.auto_start).map_err(|message| SaveConfigError {
+        message: message.clone(),
+        fields: vec![ConfigFieldError {
+            field: "autoStart".to_string(),
+            message,
+        }],
+    })?;
+
+    config::save_config(&ctx, &new_config)?;
+
+    if changed.is_empty() {
+        return Ok(());
+    }
+
+    tracing::info!("Config changed: {}", changed.join(", "));
+
+    if new_config.watched_folders() != old_config.watched_folders()
+        || new_config.setup_complete != old_config.setup_complete
+    {
+        watcher::restart(&ctx, &new_config)?;
+        library::spawn_scan(&ctx);
+    }
+
+    if new_config.history_retention_days != old_config.history_retention_days {
+        history::apply_retention(&ctx, history::store(&ctx)?)?;
+    }
+
+    if new_config.cleanup != old_config.cleanup {
+        cleanup::spawn(&ctx);
+    }
+
+    ctx.emit(
+        "config_changed",
+        &ConfigChanged {
+            changed,
+            config: new_config,
+        },
+    );
+
+    Ok(())
+}
+
+/// Enable or disable launching at login to match the config
+fn sync_autostart(app: &AppHandle, enabled: bool) -> Result<(), String> {
+    let autolaunch = app.autolaunch();
+    if autolaunch.is_enabled().ok() == Some(enabled) {
+        return Ok(());
+    }
+
+    if enabled {
+        autolaunch.enable()
+    } else {
+        autolaunch.disable()
+    }
+    .map_err(|e| format!("Failed to update launch at login: {}", e))
 }
 
 /// Get the current auth session
 #[tauri::command]
-pub fn get_session(app: AppHandle) -> Result<Option<AuthSession>, String> {
-    config::load_session(&app)
+pub fn get_session(ctx: State<'_, AgentContext>) -> Result<Option<AuthSession>, String> {
+    config::load_session(&ctx)
 }
 
 /// Login with email and password
 #[tauri::command]
 pub async fn login(
-    app: AppHandle,
+    ctx: State<'_, AgentContext>,
     email: String,
     password: String,
 ) -> Result<User, String> {
@@ -120,36 +214,258 @@ pub async fn login(
         refresh_token_expiry: refresh_expiry.to_rfc3339(),
         user: user.clone(),
         device_id: None,
+        kind: SessionKind::Password,
     };
 
-    config::save_session(&app, &session)?;
+    config::add_account(&ctx, &session)?;
+    queue::resume_held_uploads(&ctx);
 
     tracing::info!("Login successful for: {}", user.username);
 
     Ok(user)
 }
 
-/// Logout and clear session
+/// Call the logout endpoint for a session (errors are ignored)
+/// Access tokens are managed on the website and are left untouched
+async fn revoke_session(session: &AuthSession) {
+    let client = reqwest::Client::new();
+    let request = client.post(format!("{}/auth/logout", API_BASE_URL));
+    let request = match session.kind {
+        SessionKind::Token => return,
+        SessionKind::OAuth => request.bearer_auth(&session.access_token),
+        _ => request.header("Cookie", format!("refresh_token={}", session.refresh_token)),
+    };
+    let _ = request.send().await;
+}
+
+/// Logout the active account and clear its session
 #[tauri::command]
-pub async fn logout(app: AppHandle) -> Result<(), String> {
-    // Try to call the logout endpoint (ignore errors)
-    if let Ok(Some(session)) = config::load_session(&app) {
-        let client = reqwest::Client::new();
-        let _ = client
-            .post(format!("{}/auth/logout", API_BASE_URL))
-            .header("Cookie", format!("refresh_token={}", session.refresh_token))
-            .send()
-            .await;
+pub async fn logout(ctx: State<'_, AgentContext>) -> Result<(), String> {
+    logout_active(&ctx).await
+}
+
+/// Revoke and forget the active account's session
+pub async fn logout_active(ctx: &AgentContext) -> Result<(), String> {
+    if let Ok(Some(session)) = config::load_session(ctx) {
+        revoke_session(&session).await;
     }
 
     // Clear local session
-    config::clear_session(&app)?;
+    config::clear_session(ctx)?;
 
     tracing::info!("Logged out successfully");
 
     Ok(())
 }
 
+// ============================================================================
+// Access Token Login
+// ============================================================================
+
+/// Environment variable holding a personal access token
+const API_TOKEN_ENV: &str = "BALLCAM_API_TOKEN";
+
+/// Validate an access token against the user profile endpoint
+async fn fetch_token_user(token: &str) -> Result<User, String> {
+    let client = reqwest::Client::new();
+    let response = client
+        .get(format!("{}/auth/me", API_BASE_URL))
+        .bearer_auth(token)
+        .send()
+        .await
+        .map_err(|e| format!("Network error: {}", e))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let error_text = response.text().await.unwrap_or_default();
+        return Err(match status.as_u16() {
+            401 | 403 => "Invalid or revoked access token".to_string(),
+            _ => format!("Failed to validate access token: {}", error_text),
+        });
+    }
+
+    let body: serde_json::Value = response
+        .json()
+        .await
+        .map_err(|e| format!("Failed to parse response: {}", e))?;
+
+    // The profile may be wrapped in a `user` object
+    let user_data = body.get("user").unwrap_or(&body);
+
+    serde_json::from_value(user_data.clone())
+        .map_err(|e| format!("Failed to parse user: {}", e))
+}
+
+/// Validate an access token and store it as an account
+/// With `activate` it becomes the active account, otherwise only when none is active
+async fn add_token_account(ctx: &AgentContext, token: &str, activate: bool) -> Result<User, String> {
+    let user = fetch_token_user(token).await?;
+
+    let session = AuthSession {
+        access_token: token.to_string(),
+        refresh_token: String::new(),
+        access_token_expiry: String::new(),
+        refresh_token_expiry: String::new(),
+        user: user.clone(),
+        device_id: None,
+        kind: SessionKind::Token,
+    };
+
+    let active = if activate {
+        config::add_account(ctx, &session)?;
+        true
+    } else {
+        config::add_fallback_account(ctx, &session)?
+    };
+    if active {
+        queue::resume_held_uploads(ctx);
+    }
+
+    Ok(user)
+}
+
+/// Login with a personal access token
+#[tauri::command]
+pub async fn login_with_token(ctx: State<'_, AgentContext>, token: String) -> Result<User, String> {
+    let token = token.trim();
+    if token.is_empty() {
+        return Err("Access token is empty".to_string());
+    }
+
+    let user = add_token_account(&ctx, token, true).await?;
+
+    tracing::info!("Access token login successful for: {}", user.username);
+
+    Ok(user)
+}
+
+/// Login with the access token from the environment or config file, if any
+/// Called on startup for headless setups
+pub async fn login_with_configured_token(ctx: &AgentContext) {
+    let token = std::env::var(API_TOKEN_ENV)
+        .ok()
+        .or_else(|| config::load_config(ctx).ok().and_then(|c| c.api_token))
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty());
+
+    let Some(token) = token else {
+        return;
+    };
+
+    // The account the user picked stays active
+    match add_token_account(ctx, &token, false).await {
+        Ok(user) => tracing::info!("Logged in with configured access token as: {}", user.username),
+        Err(e) => tracing::error!("Configured access token rejected: {}", e),
+    }
+}
+
+// ============================================================================
+// Browser Login (Authorization Code + PKCE)
+// ============================================================================
+
+/// Login through the system browser with a loopback redirect
+#[tauri::command]
+pub async fn login_with_browser(
+    app: AppHandle,
+    ctx: State<'_, AgentContext>,
+) -> Result<User, String> {
+    use tauri_plugin_shell::ShellExt;
+
+    let open_app = app.clone();
+    let tokens = oauth::authorize(
+        &OAuthConfig::default(),
+        move |url| {
+            #[allow(deprecated)]
+            open_app
+                .shell()
+                .open(url, None)
+                .map_err(|e| format!("Failed to open browser: {}", e))
+        },
+        oauth::DEFAULT_LOGIN_TIMEOUT,
+    )
+    .await?;
+
+    let user = match tokens.user {
+        Some(user) => user,
+        None => fetch_token_user(&tokens.access_token).await?,
+    };
+
+    let now = chrono::Utc::now();
+    let session = AuthSession {
+        access_token: tokens.access_token,
+        refresh_token: tokens.refresh_token.unwrap_or_default(),
+        access_token_expiry: (now + chrono::Duration::seconds(tokens.expires_in as i64))
+            .to_rfc3339(),
+        refresh_token_expiry: tokens
+            .refresh_token_expires_in
+            .map(|secs| (now + chrono::Duration::seconds(secs as i64)).to_rfc3339())
+            .unwrap_or_default(),
+        user: user.clone(),
+        device_id: None,
+        kind: SessionKind::OAuth,
+    };
+
+    config::add_account(&ctx, &session)?;
+    queue::resume_held_uploads(&ctx);
+
+    tracing::info!("Browser login successful for: {}", user.username);
+
+    Ok(user)
+}
+
+// ============================================================================
+// Account Commands
+// ============================================================================
+
+/// List all stored accounts
+#[tauri::command]
+pub fn list_accounts(ctx: State<'_, AgentContext>) -> Result<Vec<AccountInfo>, String> {
+    let accounts = config::load_accounts(&ctx)?;
+    Ok(accounts
+        .accounts
+        .iter()
+        .map(|session| AccountInfo {
+            user: session.user.clone(),
+            is_active: accounts.active_account_id.as_deref() == Some(session.user.id.as_str()),
+        })
+        .collect())
+}
+
+/// Switch the active account
+#[tauri::command]
+pub fn switch_account(ctx: State<'_, AgentContext>, account_id: String) -> Result<User, String> {
+    let session = config::set_active_account(&ctx, &account_id)?;
+    ctx.emit("account_changed", &session.user);
+    // The account's session may have expired too
+    queue::resume_if_session_valid(&ctx);
+
+    tracing::info!("Switched active account to: {}", session.user.username);
+
+    Ok(session.user)
+}
+
+/// Logout a specific account and remove it from the stored accounts
+#[tauri::command]
+pub async fn remove_account(ctx: State<'_, AgentContext>, account_id: String) -> Result<(), String> {
+    let accounts = config::load_accounts(&ctx)?;
+    let was_active = accounts.active_account_id.as_deref() == Some(account_id.as_str());
+
+    if let Some(session) = accounts.get(&account_id) {
+        revoke_session(session).await;
+    }
+
+    config::remove_account(&ctx, &account_id)?;
+
+    if was_active {
+        let active = config::load_session(&ctx)?.map(|s| s.user);
+        ctx.emit("account_changed", &active);
+    }
+
+    tracing::info!("Removed account: {}", account_id);
+
+    Ok(())
+}
+
 // ============================================================================
 // Device Flow Commands
 // ============================================================================
@@ -197,9 +513,23 @@ pub async fn request_device_code() -> Result<DeviceCodeResponse, String> {
 
     tracing::debug!("Device code response body: {}", body_text);
 
-    let device_code: DeviceCodeResponse = serde_json::from_str(&body_text)
+    let mut device_code: DeviceCodeResponse = serde_json::from_str(&body_text)
         .map_err(|e| format!("Failed to parse response: {}. Body: {}", e, body_text))?;
 
+    // Generate QR codes so the user can scan instead of retyping the code
+    let complete_url = qr::complete_verification_url(
+        &device_code.verification_url,
+        &device_code.user_code,
+        device_code.verification_url_complete.as_deref(),
+    );
+    device_code.qr_code_svg = qr::render_svg(&complete_url)
+        .map_err(|e| tracing::warn!("{}", e))
+        .ok();
+    device_code.qr_code_png = qr::render_png_data_url(&complete_url)
+        .map_err(|e| tracing::warn!("{}", e))
+        .ok();
+    device_code.verification_url_complete = Some(complete_url);
+
     tracing::info!("Device code received: {}", device_code.user_code);
 
     Ok(device_code)
@@ -208,8 +538,16 @@ pub async fn request_device_code() -> Result<DeviceCodeResponse, String> {
 /// Poll for device token after user authorizes
 #[tauri::command]
 pub async fn poll_device_token(
-    app: AppHandle,
+    ctx: State<'_, AgentContext>,
     device_code: String,
+) -> Result<DevicePollResult, String> {
+    poll_device(&ctx, &device_code).await
+}
+
+/// Poll the device flow once, storing the account when the user has authorized
+pub async fn poll_device(
+    ctx: &AgentContext,
+    device_code: &str,
 ) -> Result<DevicePollResult, String> {
     let client = reqwest::Client::new();
     let response = client
@@ -269,9 +607,11 @@ pub async fn poll_device_token(
         refresh_token_expiry: String::new(),
         user: token_response.user.clone(),
         device_id: Some(token_response.device_id.clone()),
+        kind: SessionKind::Device,
     };
 
-    config::save_session(&app, &session)?;
+    config::add_account(ctx, &session)?;
+    queue::resume_held_uploads(ctx);
 
     tracing::info!(
         "Device authorized successfully for user: {}",
@@ -283,8 +623,8 @@ pub async fn poll_device_token(
 
 /// Refresh device token
 #[tauri::command]
-pub async fn refresh_device_token(app: AppHandle) -> Result<User, String> {
-    let session = config::load_session(&app)?
+pub async fn refresh_device_token(ctx: State<'_, AgentContext>) -> Result<User, String> {
+    let session = config::load_session(&ctx)?
         .ok_or("No session found")?;
 
     let device_id = session
@@ -307,8 +647,8 @@ pub async fn refresh_device_token(app: AppHandle) -> Result<User, String> {
     let status = response.status();
 
     if status.as_u16() == 401 {
-        // Device revoked - clear session
-        config::clear_session(&app)?;
+        // Device revoked - remove this account
+        config::remove_account(&ctx, &session.user.id)?;
         return Err("Device has been revoked. Please re-authorize.".to_string());
     }
 
@@ -339,7 +679,7 @@ pub async fn refresh_device_token(app: AppHandle) -> Result<User, String> {
         ..session
     };
 
-    config::save_session(&app, &updated_session)?;
+    config::save_session(&ctx, &updated_session)?;
 
     tracing::info!("Device token refreshed successfully");
 
@@ -373,87 +713,197 @@ pub fn show_window(app: AppHandle) -> Result<(), String> {
 
 /// Start the file watcher
 #[tauri::command]
-pub fn start_watcher(app: AppHandle, state: State<'_, AppState>) -> Result<(), String> {
-    let config = config::load_config(&app)?;
-    if config.replay_folder.is_empty() {
+pub fn start_watcher(ctx: State<'_, AgentContext>) -> Result<(), String> {
+    let config = config::load_config(&ctx)?;
+    let folders = config.watched_folders();
+    if folders.is_empty() {
         return Err("No replay folder configured".to_string());
     }
 
-    let mut watcher = state.watcher.lock().unwrap();
-    watcher.start(app, PathBuf::from(&config.replay_folder))
+    let mut watcher = ctx.watcher.lock().unwrap();
+    watcher.start(ctx.inner().clone(), folders.iter().map(PathBuf::from).collect())
 }
 
 /// Pause the file watcher
 #[tauri::command]
-pub fn pause_watcher(state: State<'_, AppState>) -> Result<(), String> {
-    let watcher = state.watcher.lock().unwrap();
+pub fn pause_watcher(ctx: State<'_, AgentContext>) -> Result<(), String> {
+    let watcher = ctx.watcher.lock().unwrap();
     watcher.pause()
 }
 
 /// Resume the file watcher
 #[tauri::command]
-pub fn resume_watcher(state: State<'_, AppState>) -> Result<(), String> {
-    let watcher = state.watcher.lock().unwrap();
+pub fn resume_watcher(ctx: State<'_, AgentContext>) -> Result<(), String> {
+    let watcher = ctx.watcher.lock().unwrap();
     watcher.resume()
 }
 
 /// Get watcher status
 #[tauri::command]
-pub fn get_watcher_status(state: State<'_, AppState>) -> Result<WatcherState, String> {
-    let watcher = state.watcher.lock().unwrap();
+pub fn get_watcher_status(ctx: State<'_, AgentContext>) -> Result<WatcherState, String> {
+    let watcher = ctx.watcher.lock().unwrap();
     Ok(watcher.get_state())
 }
 
 /// Upload a replay file manually
 #[tauri::command]
 pub async fn upload_replay(
-    app: AppHandle,
+    ctx: State<'_, AgentContext>,
     file_path: String,
     visibility: Option<Visibility>,
 ) -> Result<UploadRecord, String> {
+    // An archive holds many replays, each gets its own record
+    if archive::is_archive(Path::new(&file_path)) {
+        return Err("Upload .zip archives with upload_batch".to_string());
+    }
+
     let uploader = Uploader::new();
-    uploader.upload_replay(&app, &file_path, visibility).await
+    uploader.upload_replay(&ctx, &file_path, visibility).await
 }
 
-/// Get upload history
+/// Browse the replay library, newest files first
 #[tauri::command]
-pub fn get_history(app: AppHandle) -> Result<Vec<UploadRecord>, String> {
-    let history = config::load_history(&app)?;
-    Ok(history.records)
+pub fn query_library(ctx: State<'_, AgentContext>, query: LibraryQuery) -> Result<LibraryPage, String> {
+    library::store(&ctx)?.query(&query)
+}
+
+/// Rescan the watched folders for replays
+#[tauri::command]
+pub async fn rescan_library(ctx: State<'_, AgentContext>) -> Result<LibraryScanResult, String> {
+    let ctx = ctx.inner().clone();
+    tauri::async_runtime::spawn_blocking(move || library::scan(&ctx))
+        .await
+        .map_err(|e| format!("Library scan failed: {}", e))?
+}
+
+/// Upload replays picked from the library, one after another in the background
+/// Replays already uploaded or in progress are skipped; returns how many were queued
+#[tauri::command]
+pub fn upload_library_replays(
+    ctx: State<'_, AgentContext>,
+    paths: Vec<String>,
+    visibility: Option<Visibility>,
+) -> Result<usize, String> {
+    let library = library::store(&ctx)?;
+    let mut queued = Vec::new();
+    for path in paths {
+        let entry = library
+            .get(&path)?
+            .ok_or_else(|| format!("Replay not in library: {}", path))?;
+        if !matches!(entry.status, LibraryStatus::Uploaded | LibraryStatus::Pending) {
+            queued.push(path);
+        }
+    }
+
+    let count = queued.len();
+    let task_ctx = ctx.inner().clone();
+    ctx.spawn(async move {
+        let uploader = Uploader::new();
+        for path in queued {
+            if let Err(e) = uploader.upload_replay(&task_ctx, &path, visibility.clone()).await {
+                tracing::error!("Library upload failed for {}: {}", path, e);
+            }
+        }
+    });
+
+    Ok(count)
+}
+
+/// Upload many replays at once: files, and folders of replays
+/// Returns the batch ID and the files left out, uploads continue in the background
+#[tauri::command]
+pub async fn upload_batch(
+    ctx: State<'_, AgentContext>,
+    request: BatchRequest,
+) -> Result<BatchStarted, String> {
+    batch::start(&ctx, request).await
+}
+
+/// Get the upload queue state (uploads held until re-login)
+#[tauri::command]
+pub fn get_upload_queue(ctx: State<'_, AgentContext>) -> Result<UploadQueueState, String> {
+    Ok(ctx.upload_queue.get_state())
+}
+
+/// Get the most recent upload history
+#[tauri::command]
+pub fn get_history(ctx: State<'_, AgentContext>) -> Result<Vec<UploadRecord>, String> {
+    history::store(&ctx)?.recent(history::RECENT_LIMIT)
+}
+
+/// Query upload history with filters, sorting and pagination
+#[tauri::command]
+pub fn query_history(ctx: State<'_, AgentContext>, query: HistoryQuery) -> Result<HistoryPage, String> {
+    history::store(&ctx)?.query(&query)
+}
+
+/// Export history records matching the filters to a CSV or JSON Lines file
+/// Returns the number of exported records
+#[tauri::command]
+pub fn export_history(
+    ctx: State<'_, AgentContext>,
+    format: HistoryExportFormat,
+    path: String,
+    filters: Option<HistoryQuery>,
+) -> Result<usize, String> {
+    let records = history::store(&ctx)?.query_all(&filters.unwrap_or_default())?;
+    export::write_records(Path::new(&path), format, &records)?;
+    tracing::info!("Exported {} history records to {}", records.len(), path);
+    Ok(records.len())
+}
+
+/// Import history records exported on another machine
+/// Records already present (by record ID or replay ID) are skipped
+#[tauri::command]
+pub fn import_history(ctx: State<'_, AgentContext>, path: String) -> Result<HistoryImportResult, String> {
+    let records = export::read_records(Path::new(&path))?;
+    let result = history::store(&ctx)?.import(&records)?;
+    tracing::info!(
+        "Imported {} history records from {} ({} already present)",
+        result.imported,
+        path,
+        result.skipped
+    );
+    Ok(result)
 }
 
 /// Retry a failed upload by record ID
 #[tauri::command]
 pub async fn retry_upload(
-    app: AppHandle,
+    ctx: State<'_, AgentContext>,
     record_id: String,
 ) -> Result<UploadRecord, String> {
-    // Find the record in history
-    let history = config::load_history(&app)?;
-    let record = history
-        .records
-        .iter()
-        .find(|r| r.id == record_id)
-        .ok_or("Record not found")?;
+    retry::retry_record(&ctx, &record_id).await
+}
 
-    // Check if the file still exists
-    let file_path = record.file_path.clone();
-    if !std::path::Path::new(&file_path).exists() {
-        return Err("Replay file no longer exists".to_string());
-    }
+/// Retry every failed upload, skipping errors that retrying can't fix
+#[tauri::command]
+pub async fn retry_failed_uploads(ctx: State<'_, AgentContext>) -> Result<BulkRetryResult, String> {
+    let record_ids = retry::failed_record_ids(&ctx)?;
+    retry::retry_records(&ctx, &record_ids, true).await
+}
 
-    // Re-upload using the same file path
-    let uploader = Uploader::new();
-    let result = uploader.upload_replay(&app, &file_path, None).await;
+/// Retry the selected failed uploads
+#[tauri::command]
+pub async fn retry_uploads(
+    ctx: State<'_, AgentContext>,
+    record_ids: Vec<String>,
+) -> Result<BulkRetryResult, String> {
+    retry::retry_records(&ctx, &record_ids, false).await
+}
 
-    // If upload succeeded, remove the old failed record
-    if result.is_ok() {
-        let mut history = config::load_history(&app)?;
-        history.records.retain(|r| r.id != record_id);
-        let _ = config::save_history(&app, &history);
-    }
+/// Delete history records by ID, returning how many were deleted
+#[tauri::command]
+pub fn delete_history_records(ctx: State<'_, AgentContext>, record_ids: Vec<String>) -> Result<usize, String> {
+    history::store(&ctx)?.delete_many(&record_ids)
+}
 
-    result
+/// Delete the whole upload history, returning how many records were deleted
+#[tauri::command]
+pub fn clear_history(ctx: State<'_, AgentContext>) -> Result<usize, String> {
+    let cleared = history::store(&ctx)?.clear()?;
+    tracing::info!("Cleared {} history records", cleared);
+    Ok(cleared)
 }
 
 /// Detect the Rocket League replay folder
@@ -495,8 +945,8 @@ fn detect_platform(path: &str) -> String {
 
 /// Get information about the watched folder
 #[tauri::command]
-pub fn get_folder_info(app: AppHandle) -> Result<FolderInfo, String> {
-    let config = config::load_config(&app)?;
+pub fn get_folder_info(ctx: State<'_, AgentContext>) -> Result<FolderInfo, String> {
+    let config = config::load_config(&ctx)?;
     let path = config.replay_folder;
 
     if path.is_empty() {
@@ -570,27 +1020,17 @@ fn format_bytes(bytes: u64) -> String {
 
 /// Get aggregated upload statistics from history
 #[tauri::command]
-pub fn get_upload_stats(app: AppHandle) -> Result<UploadStats, String> {
-    let history = config::load_history(&app)?;
+pub fn get_upload_stats(ctx: State<'_, AgentContext>) -> Result<UploadStats, String> {
+    upload_stats(&ctx)
+}
 
-    let mut total_uploads: u32 = 0;
-    let mut total_failed: u32 = 0;
-    let mut total_bytes_uploaded: u64 = 0;
+/// Aggregate upload statistics of the whole history
+pub fn upload_stats(ctx: &AgentContext) -> Result<UploadStats, String> {
+    let totals = history::store(ctx)?.totals()?;
 
-    for record in &history.records {
-        match record.status {
-            UploadStatus::Completed => {
-                total_uploads += 1;
-                if let Some(size) = record.file_size {
-                    total_bytes_uploaded += size;
-                }
-            }
-            UploadStatus::Failed => {
-                total_failed += 1;
-            }
-            _ => {}
-        }
-    }
+    let total_uploads = totals.completed;
+    let total_failed = totals.failed;
+    let total_bytes_uploaded = totals.completed_bytes;
 
     let total_attempts = total_uploads + total_failed;
     let success_rate = if total_attempts > 0 {
@@ -607,3 +1047,25 @@ pub fn get_upload_stats(app: AppHandle) -> Result<UploadStats, String> {
         total_bytes_formatted: format_bytes(total_bytes_uploaded),
     })
 }
+
+/// Get upload statistics over a date range, bucketed by day or week
+#[tauri::command]
+pub fn get_upload_report(ctx: State<'_, AgentContext>, query: StatsQuery) -> Result<UploadReport, String> {
+    upload_report(&ctx, &query)
+}
+
+/// Upload statistics over a date range
+pub fn upload_report(ctx: &AgentContext, query: &StatsQuery) -> Result<UploadReport, String> {
+    let records = history::store(ctx)?.query_all(&HistoryQuery {
+        from: query.from.clone(),
+        to: query.to.clone(),
+        ..Default::default()
+    })?;
+    stats::build_report(&records, query, ctx.now())
+}
+
+/// Take the history item a `ballcam://history/<id>` link asked to show, if any
+#[tauri::command]
+pub fn take_pending_history_item(pending: State<'_, PendingHistoryItem>) -> Option<String> {
+    pending.0.lock().unwrap().take()
+}
@@ -1,6 +1,12 @@
-use crate::types::{AppConfig, AuthSession, UploadHistory};
-use tauri::AppHandle;
-use tauri_plugin_store::StoreExt;
+use crate::agent::AgentContext;
+use crate::archiving;
+use crate::migrations;
+use crate::types::{
+    AccountStore, AppConfig, ArchiveAction, AuthSession, ConfigFieldError, StoreRecovered,
+    UploadHistory,
+};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 const CONFIG_FILE: &str = "config.json";
 const SESSION_FILE: &str = "session.json";
@@ -82,119 +88,521 @@ pub fn detect_all_replay_folders() -> Vec<DetectedFolder> {
 }
 
 /// Load app configuration from store
-pub fn load_config(app: &AppHandle) -> Result<AppConfig, String> {
-    let store = app
-        .store(CONFIG_FILE)
-        .map_err(|e| format!("Failed to open config store: {}", e))?;
-
-    if let Some(config) = store.get("config") {
-        serde_json::from_value(config.clone())
-            .map_err(|e| format!("Failed to parse config: {}", e))
-    } else {
-        // Return default config with detected replay folder
-        let mut config = AppConfig::default();
-        if let Some(folder) = get_default_replay_folder() {
-            config.replay_folder = folder;
+/// Configs saved by older versions are migrated and written back in the current format
+/// A config that can't be read falls back to the store's backup, then to defaults
+/// and is set aside as `config.corrupt-<timestamp>`, so it's recovered once
+pub fn load_config(ctx: &AgentContext) -> Result<AppConfig, String> {
+    if let Some(stored) = ctx.storage.get(CONFIG_FILE, "config")? {
+        match migrations::load_config(stored.clone()) {
+            Ok(loaded) => {
+                if loaded.needs_save() {
+                    tracing::info!(
+                        "Upgrading config from version {} to {}",
+                        loaded.stored_version,
+                        migrations::CONFIG_VERSION
+                    );
+                    if let Err(e) = save_config(ctx, &loaded.config) {
+                        tracing::error!("Failed to save migrated config: {}", e);
+                    }
+                }
+                return Ok(loaded.config);
+            }
+            Err(e) => {
+                tracing::error!("Stored config can't be loaded: {}", e);
+                // Keep the app usable instead of failing every upload
+                let backup = ctx
+                    .storage
+                    .backup(CONFIG_FILE, "config")
+                    .unwrap_or_else(|e| {
+                        tracing::error!("Failed to read config backup: {}", e);
+                        None
+                    })
+                    .and_then(|backup| migrations::load_config(backup).ok());
+                let source = if backup.is_some() { "backup" } else { "defaults" };
+                tracing::warn!("Recovered config from {}", source);
+                ctx.emit(
+                    "store_recovered",
+                    &StoreRecovered {
+                        store: CONFIG_FILE.to_string(),
+                        source: source.to_string(),
+                    },
+                );
+
+                if let Some(loaded) = backup {
+                    if let Err(e) = save_config(ctx, &loaded.config) {
+                        tracing::error!("Failed to save recovered config: {}", e);
+                    }
+                    return Ok(loaded.config);
+                }
+
+                // Kept for inspection, out of the way of the next load
+                let corrupt_key = format!("config.corrupt-{}", ctx.now().timestamp());
+                let moved = ctx
+                    .storage
+                    .set(CONFIG_FILE, &corrupt_key, stored)
+                    .and_then(|_| ctx.storage.delete(CONFIG_FILE, "config"));
+                if let Err(e) = moved {
+                    tracing::error!("Failed to set the unreadable config aside: {}", e);
+                }
+            }
         }
-        Ok(config)
     }
+
+    // Return default config with detected replay folder
+    let mut config = AppConfig::default();
+    if let Some(folder) = get_default_replay_folder() {
+        config.replay_folder = folder;
+    }
+    Ok(config)
 }
 
 /// Save app configuration to store
-pub fn save_config(app: &AppHandle, config: &AppConfig) -> Result<(), String> {
-    let store = app
-        .store(CONFIG_FILE)
-        .map_err(|e| format!("Failed to open config store: {}", e))?;
+pub fn save_config(ctx: &AgentContext, config: &AppConfig) -> Result<(), String> {
+    ctx.storage.set(CONFIG_FILE, "config", migrations::wrap_config(config)?)
+}
 
-    let value = serde_json::to_value(config)
-        .map_err(|e| format!("Failed to serialize config: {}", e))?;
+/// Dry run forced for this process by `--dry-run`, whatever the config says
+static DRY_RUN_FORCED: AtomicBool = AtomicBool::new(false);
 
-    store
-        .set("config", value);
+/// Process replays without uploading them until the agent exits
+pub fn force_dry_run() {
+    DRY_RUN_FORCED.store(true, Ordering::SeqCst);
+}
 
-    store
-        .save()
-        .map_err(|e| format!("Failed to save config: {}", e))?;
+/// Whether replays are processed without being uploaded, by config or `--dry-run`
+pub fn is_dry_run(config: &AppConfig) -> bool {
+    config.dry_run || DRY_RUN_FORCED.load(Ordering::SeqCst)
+}
 
-    Ok(())
+/// Remove account routes whose account has been removed, e.g. while the settings were open
+/// Returns the folders that were routed to them
+pub fn drop_stale_routes(config: &mut AppConfig, accounts: &AccountStore) -> Vec<String> {
+    let mut dropped = Vec::new();
+    config.account_routes.retain(|route| {
+        let known = accounts.get(&route.account_id).is_some();
+        if !known {
+            dropped.push(route.folder.clone());
+        }
+        known
+    });
+    dropped
 }
 
-/// Load auth session from store
-pub fn load_session(app: &AppHandle) -> Result<Option<AuthSession>, String> {
-    let store = app
-        .store(SESSION_FILE)
-        .map_err(|e| format!("Failed to open session store: {}", e))?;
+/// Check a config before saving it
+/// Returns one error per invalid field, empty if the config is valid
+pub fn validate_config(config: &AppConfig) -> Vec<ConfigFieldError> {
+    let mut errors = Vec::new();
+    let mut error = |field: &str, message: String| {
+        errors.push(ConfigFieldError {
+            field: field.to_string(),
+            message,
+        })
+    };
+
+    if config.replay_folder.is_empty() {
+        if config.setup_complete {
+            error("replayFolder", "Replay folder is required".to_string());
+        }
+    } else if !Path::new(&config.replay_folder).is_dir() {
+        error(
+            "replayFolder",
+            format!("Folder not found: {}", config.replay_folder),
+        );
+    }
 
-    if let Some(session) = store.get("session") {
-        let session: AuthSession = serde_json::from_value(session.clone())
+    for (index, route) in config.account_routes.iter().enumerate() {
+        if !Path::new(&route.folder).is_dir() {
+            error("accountRoutes", format!("Folder not found: {}", route.folder));
+        } else if config.account_routes[..index]
+            .iter()
+            .any(|other| other.folder == route.folder)
+        {
+            error(
+                "accountRoutes",
+                format!("Folder is routed more than once: {}", route.folder),
+            );
+        }
+    }
+
+    if config
+        .api_token
+        .as_deref()
+        .is_some_and(|token| token.trim().is_empty())
+    {
+        error("apiToken", "Access token can't be empty".to_string());
+    }
+
+    if config.history_retention_days == Some(0) {
+        error(
+            "historyRetentionDays",
+            "Retention must be at least one day".to_string(),
+        );
+    }
+
+    let retry = &config.auto_retry;
+    if retry.initial_delay_minutes == 0 {
+        error("autoRetry", "Retry delay must be at least one minute".to_string());
+    } else if retry.max_delay_minutes < retry.initial_delay_minutes {
+        error(
+            "autoRetry",
+            "Maximum retry delay can't be shorter than the first delay".to_string(),
+        );
+    }
+    if retry.max_age_hours == 0 {
+        error("autoRetry", "Retry window must be at least one hour".to_string());
+    }
+
+    if config.staging.max_size_mb == 0 {
+        error("staging", "Staging cache must be at least 1 MB".to_string());
+    }
+    if config.staging.max_age_days == 0 {
+        error("staging", "Staged replays must be kept at least one day".to_string());
+    }
+
+    let archive = &config.local_archive;
+    if archive.action != ArchiveAction::None {
+        if archive.folder.trim().is_empty() {
+            error("localArchive", "Choose a folder to archive replays to".to_string());
+        } else if config
+            .watched_folders()
+            .iter()
+            .any(|folder| Path::new(folder) == Path::new(&archive.folder))
+        {
+            error(
+                "localArchive",
+                "The archive folder can't be a watched folder".to_string(),
+            );
+        }
+        if archive.name_template.trim().is_empty() {
+            error("localArchive", "Name template can't be empty".to_string());
+        }
+        let unknown = archiving::unknown_placeholders(&archive.name_template);
+        if !unknown.is_empty() {
+            error(
+                "localArchive",
+                format!("Unknown placeholders: {}", unknown.join(", ")),
+            );
+        }
+    }
+
+    let cleanup = &config.cleanup;
+    if cleanup.enabled && cleanup.max_age_days.is_none() && cleanup.max_count.is_none() {
+        error("cleanup", "Set an age or a number of replays to keep".to_string());
+    }
+    if cleanup.max_age_days == Some(0) {
+        error("cleanup", "Replays must be kept at least one day".to_string());
+    }
+
+    errors
+}
+
+/// Names of the fields that differ between two configs (camelCase, as stored)
+pub fn changed_fields(old: &AppConfig, new: &AppConfig) -> Vec<String> {
+    let (Ok(serde_json::Value::Object(old)), Ok(serde_json::Value::Object(new))) =
+        (serde_json::to_value(old), serde_json::to_value(new))
+    else {
+        return Vec::new();
+    };
+
+    let mut changed: Vec<String> = new
+        .iter()
+        .filter(|(key, value)| old.get(*key) != Some(*value))
+        .map(|(key, _)| key.clone())
+        .collect();
+    // Fields skipped when unset (e.g. apiToken being cleared)
+    changed.extend(old.keys().filter(|key| !new.contains_key(*key)).cloned());
+    changed
+}
+
+/// Load all stored accounts from store
+/// Migrates the legacy single `session` key into the account list on first load
+pub fn load_accounts(ctx: &AgentContext) -> Result<AccountStore, String> {
+    if let Some(accounts) = ctx.storage.get(SESSION_FILE, "accounts")? {
+        return serde_json::from_value(accounts)
+            .map_err(|e| format!("Failed to parse accounts: {}", e));
+    }
+
+    let mut accounts = AccountStore::default();
+    if let Some(session) = ctx.storage.get(SESSION_FILE, "session")? {
+        let session: AuthSession = serde_json::from_value(session)
             .map_err(|e| format!("Failed to parse session: {}", e))?;
-        Ok(Some(session))
-    } else {
-        Ok(None)
+        accounts.upsert(session);
+
+        save_accounts(ctx, &accounts)?;
+        ctx.storage.delete(SESSION_FILE, "session")?;
+
+        tracing::info!("Migrated legacy session to account list");
     }
+
+    Ok(accounts)
+}
+
+/// Save all stored accounts to store
+pub fn save_accounts(ctx: &AgentContext, accounts: &AccountStore) -> Result<(), String> {
+    let value = serde_json::to_value(accounts)
+        .map_err(|e| format!("Failed to serialize accounts: {}", e))?;
+
+    ctx.storage.set(SESSION_FILE, "accounts", value)
 }
 
-/// Save auth session to store
-pub fn save_session(app: &AppHandle, session: &AuthSession) -> Result<(), String> {
-    let store = app
-        .store(SESSION_FILE)
-        .map_err(|e| format!("Failed to open session store: {}", e))?;
+/// Load the active account's auth session from store
+pub fn load_session(ctx: &AgentContext) -> Result<Option<AuthSession>, String> {
+    Ok(load_accounts(ctx)?.active().cloned())
+}
 
-    let value = serde_json::to_value(session)
-        .map_err(|e| format!("Failed to serialize session: {}", e))?;
+/// Load the auth session of a specific account
+pub fn load_session_for(ctx: &AgentContext, account_id: &str) -> Result<Option<AuthSession>, String> {
+    Ok(load_accounts(ctx)?.get(account_id).cloned())
+}
 
-    store.set("session", value);
+/// Load the accounts, change them and save them, with the session store locked in between
+/// so concurrent logins, switches and removals don't undo each other
+fn update_accounts<R>(
+    ctx: &AgentContext,
+    change: impl FnOnce(&mut AccountStore) -> R,
+) -> Result<R, String> {
+    // Moves a legacy session into the account list first
+    load_accounts(ctx)?;
+
+    let mut change = Some(change);
+    let mut result = None;
+    ctx.storage.update(SESSION_FILE, "accounts", &mut |stored| {
+        let mut accounts: AccountStore = match stored {
+            Some(accounts) => serde_json::from_value(accounts)
+                .map_err(|e| format!("Failed to parse accounts: {}", e))?,
+            None => AccountStore::default(),
+        };
+        if let Some(change) = change.take() {
+            result = Some(change(&mut accounts));
+        }
+        serde_json::to_value(&accounts)
+            .map(Some)
+            .map_err(|e| format!("Failed to serialize accounts: {}", e))
+    })?;
 
-    store
-        .save()
-        .map_err(|e| format!("Failed to save session: {}", e))?;
+    result.ok_or_else(|| "Accounts were not updated".to_string())
+}
 
-    Ok(())
+/// Save auth session to store, replacing any existing session for the same account
+pub fn save_session(ctx: &AgentContext, session: &AuthSession) -> Result<(), String> {
+    update_accounts(ctx, |accounts| accounts.upsert(session.clone()))
 }
 
-/// Clear auth session from store
-pub fn clear_session(app: &AppHandle) -> Result<(), String> {
-    let store = app
-        .store(SESSION_FILE)
-        .map_err(|e| format!("Failed to open session store: {}", e))?;
+/// Save auth session and make it the active account
+pub fn add_account(ctx: &AgentContext, session: &AuthSession) -> Result<(), String> {
+    update_accounts(ctx, |accounts| {
+        accounts.upsert(session.clone());
+        accounts.active_account_id = Some(session.user.id.clone());
+    })
+}
 
-    store.delete("session");
+/// Save auth session, making it the active account only when none is active
+/// Returns whether it became the active account
+pub fn add_fallback_account(ctx: &AgentContext, session: &AuthSession) -> Result<bool, String> {
+    update_accounts(ctx, |accounts| {
+        accounts.upsert(session.clone());
+        let activate = accounts.active().is_none();
+        if activate {
+            accounts.active_account_id = Some(session.user.id.clone());
+        }
+        activate
+    })
+}
 
-    store
-        .save()
-        .map_err(|e| format!("Failed to save session store: {}", e))?;
+/// Make a stored account the active one
+pub fn set_active_account(ctx: &AgentContext, account_id: &str) -> Result<AuthSession, String> {
+    update_accounts(ctx, |accounts| {
+        let session = accounts.get(account_id).cloned()?;
+        accounts.active_account_id = Some(account_id.to_string());
+        Some(session)
+    })?
+    .ok_or_else(|| "Account not found".to_string())
+}
 
-    Ok(())
+/// Remove a stored account
+pub fn remove_account(ctx: &AgentContext, account_id: &str) -> Result<(), String> {
+    update_accounts(ctx, |accounts| accounts.remove(account_id))
 }
 
-/// Load upload history from store
-pub fn load_history(app: &AppHandle) -> Result<UploadHistory, String> {
-    let store = app
-        .store(HISTORY_FILE)
-        .map_err(|e| format!("Failed to open history store: {}", e))?;
+/// Clear the active account's auth session from store
+pub fn clear_session(ctx: &AgentContext) -> Result<(), String> {
+    update_accounts(ctx, |accounts| {
+        if let Some(id) = accounts.active_account_id.clone() {
+            accounts.remove(&id);
+        }
+    })
+}
 
-    if let Some(history) = store.get("history") {
-        serde_json::from_value(history.clone())
+/// Load the legacy upload history from history.json
+/// History now lives in history.db, this is only read to migrate old installs
+pub fn load_legacy_history(ctx: &AgentContext) -> Result<UploadHistory, String> {
+    if let Some(history) = ctx.storage.get(HISTORY_FILE, "history")? {
+        serde_json::from_value(history)
             .map_err(|e| format!("Failed to parse history: {}", e))
     } else {
         Ok(UploadHistory::default())
     }
 }
 
-/// Save upload history to store
-pub fn save_history(app: &AppHandle, history: &UploadHistory) -> Result<(), String> {
-    let store = app
-        .store(HISTORY_FILE)
-        .map_err(|e| format!("Failed to open history store: {}", e))?;
+/// Remove the legacy upload history once it has been migrated
+pub fn clear_legacy_history(ctx: &AgentContext) -> Result<(), String> {
+    ctx.storage.delete(HISTORY_FILE, "history")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::testing::TestAgent;
+    use crate::types::AccountRoute;
+    use serde_json::json;
+
+    fn session(id: &str) -> AuthSession {
+        serde_json::from_value(json!({
+            "accessToken": format!("token-{}", id),
+            "accessTokenExpiry": "2030-01-01T00:00:00Z",
+            "user": {
+                "id": id,
+                "username": id,
+                "email": format!("{}@example.com", id),
+                "emailVerified": true,
+                "avatarUrl": null,
+            },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn config_with_the_wrong_shape_recovers_the_backup() {
+        let agent = TestAgent::with_file_storage();
+        let config = AppConfig {
+            replay_folder: "/replays".to_string(),
+            ..Default::default()
+        };
+        save_config(&agent.ctx, &config).unwrap();
+
+        // Valid JSON the config can't be parsed from
+        agent
+            .ctx
+            .storage
+            .set(CONFIG_FILE, "config", json!({ "version": 2, "config": { "replayFolder": 42 } }))
+            .unwrap();
+
+        assert_eq!(load_config(&agent.ctx).unwrap().replay_folder, "/replays");
+        let recovered = agent.events.payloads("store_recovered");
+        assert_eq!(recovered.len(), 1);
+        assert_eq!(recovered[0]["source"], "backup");
+
+        // Saved back, so the next load doesn't recover again
+        assert_eq!(load_config(&agent.ctx).unwrap().replay_folder, "/replays");
+        assert_eq!(agent.events.payloads("store_recovered").len(), 1);
+    }
+
+    #[test]
+    fn unreadable_config_without_backup_uses_defaults() {
+        let agent = TestAgent::with_file_storage();
+        agent
+            .ctx
+            .storage
+            .set(CONFIG_FILE, "config", json!({ "replayFolder": ["not", "a", "path"] }))
+            .unwrap();
+
+        let config = load_config(&agent.ctx).unwrap();
+        assert!(!config.setup_complete);
+        assert_eq!(agent.events.payloads("store_recovered")[0]["source"], "defaults");
+
+        // Set aside, so the next load starts from the defaults without recovering again
+        assert!(!load_config(&agent.ctx).unwrap().setup_complete);
+        assert_eq!(agent.events.payloads("store_recovered").len(), 1);
+        let corrupt_key = format!("config.corrupt-{}", agent.ctx.now().timestamp());
+        assert_eq!(
+            agent.ctx.storage.get(CONFIG_FILE, &corrupt_key).unwrap(),
+            Some(json!({ "replayFolder": ["not", "a", "path"] }))
+        );
+    }
 
-    let value = serde_json::to_value(history)
-        .map_err(|e| format!("Failed to serialize history: {}", e))?;
+    #[test]
+    fn routes_to_removed_accounts_are_dropped() {
+        let mut accounts = AccountStore::default();
+        accounts.upsert(session("kept"));
+        let dir = std::env::temp_dir();
+        let route = |folder: &str, account_id: &str| AccountRoute {
+            folder: dir.join(folder).to_string_lossy().to_string(),
+            account_id: account_id.to_string(),
+        };
+        let mut config = AppConfig {
+            account_routes: vec![route("a", "kept"), route("b", "removed"), route("c", "kept")],
+            ..Default::default()
+        };
+
+        let dropped = drop_stale_routes(&mut config, &accounts);
+        assert_eq!(dropped, vec![route("b", "removed").folder]);
+        assert_eq!(config.account_routes.len(), 2);
+        assert!(config.account_routes.iter().all(|route| route.account_id == "kept"));
+    }
 
-    store.set("history", value);
+    #[test]
+    fn validation_reports_each_invalid_field() {
+        let dir = std::env::temp_dir().to_string_lossy().to_string();
+        let route = AccountRoute {
+            folder: dir.clone(),
+            account_id: "anyone".to_string(),
+        };
+        let valid = AppConfig {
+            replay_folder: dir.clone(),
+            setup_complete: true,
+            account_routes: vec![route.clone()],
+            ..Default::default()
+        };
+        assert!(validate_config(&valid).is_empty());
+
+        let invalid = AppConfig {
+            replay_folder: String::new(),
+            account_routes: vec![route.clone(), route],
+            api_token: Some(" ".to_string()),
+            history_retention_days: Some(0),
+            ..valid
+        };
+        let fields: Vec<String> = validate_config(&invalid)
+            .into_iter()
+            .map(|error| error.field)
+            .collect();
+        assert_eq!(
+            fields,
+            vec!["replayFolder", "accountRoutes", "apiToken", "historyRetentionDays"]
+        );
+    }
 
-    store
-        .save()
-        .map_err(|e| format!("Failed to save history: {}", e))?;
+    #[test]
+    fn concurrent_account_changes_are_all_kept() {
+        const ACCOUNTS: usize = 8;
+
+        let agent = TestAgent::with_file_storage();
+        add_account(&agent.ctx, &session("first")).unwrap();
+
+        let threads: Vec<_> = (0..ACCOUNTS)
+            .map(|n| {
+                let ctx = agent.ctx.clone();
+                std::thread::spawn(move || {
+                    let id = format!("account-{}", n);
+                    add_fallback_account(&ctx, &session(&id)).unwrap();
+                    if n % 2 == 0 {
+                        remove_account(&ctx, &id).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
 
-    Ok(())
+        let accounts = load_accounts(&agent.ctx).unwrap();
+        assert_eq!(accounts.active().unwrap().user.id, "first");
+        for n in 0..ACCOUNTS {
+            let id = format!("account-{}", n);
+            assert_eq!(accounts.get(&id).is_some(), n % 2 == 1, "{}", id);
+        }
+        assert_eq!(set_active_account(&agent.ctx, "account-1").unwrap().user.id, "account-1");
+        assert!(set_active_account(&agent.ctx, "account-0").is_err());
+        assert_eq!(load_session(&agent.ctx).unwrap().unwrap().user.id, "account-1");
+    }
 }
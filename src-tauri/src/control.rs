@@ -0,0 +1,959 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::path::Path;
+
+use crate::agent::AgentContext;
+use crate::archive;
+use crate::batch::{self, BatchFiles};
+use crate::commands;
+use crate::config;
+use crate::history;
+use crate::queue;
+use crate::retry;
+use crate::types::{
+    AgentStatus, BatchRequest, HistoryQuery, StatsQuery, UploadOutcome, UploadStatus, Visibility,
+};
+use crate::uploader::Uploader;
+use crate::watcher;
+
+/// File name of the control socket
+#[cfg(unix)]
+const SOCKET_NAME: &str = "ballcam-agent.sock";
+/// Name of the control pipe, followed by the user name
+#[cfg(windows)]
+const PIPE_NAME: &str = r"\\.\pipe\ballcam-agent";
+
+/// Version of the methods, parameters and results below
+/// Bumped on incompatible changes; additions keep the version
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Methods served on the socket
+pub const METHODS: &[&str] = &[
+    "agent.info",
+    "status",
+    "watcher.pause",
+    "watcher.resume",
+    "queue.get",
+    "upload.enqueue",
+    "upload.files",
+    "upload.batch",
+    "history.list",
+    "history.retry",
+    "stats.get",
+    "stats.report",
+    "session.reload",
+    "events.subscribe",
+    "events.unsubscribe",
+];
+
+/// Events subscribers can receive, the same the desktop frontend listens to
+pub const EVENTS: &[&str] = &[
+    "file_detected",
+    "upload_started",
+    "upload_progress",
+    "upload_completed",
+    "upload_failed",
+    "upload_held",
+    "upload_skipped",
+    "session_expired",
+    "uploads_resumed",
+    "account_changed",
+    "config_changed",
+    "watcher_paused",
+    "watcher_resumed",
+    "library_updated",
+    "library_entry_updated",
+    "library_entry_removed",
+    "batch_started",
+    "batch_progress",
+    "batch_completed",
+    "replays_cleaned_up",
+    "store_recovered",
+];
+
+/// JSON-RPC 2.0 error codes
+pub const PARSE_ERROR: i32 = -32700;
+pub const METHOD_NOT_FOUND: i32 = -32601;
+pub const INVALID_PARAMS: i32 = -32602;
+/// The method ran and failed
+pub const AGENT_ERROR: i32 = -32000;
+
+/// Error returned by a control method
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i32,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new(code: i32, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<String> for RpcError {
+    fn from(message: String) -> Self {
+        Self::new(AGENT_ERROR, message)
+    }
+}
+
+/// A request line sent to the socket
+#[derive(Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+/// A response line written back
+#[derive(Serialize)]
+struct Response {
+    jsonrpc: &'static str,
+    id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+}
+
+impl Response {
+    fn new(id: Value, outcome: Result<Value, RpcError>) -> Self {
+        let (result, error) = match outcome {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+        Self {
+            jsonrpc: "2.0",
+            id,
+            result,
+            error,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UploadParams {
+    paths: Vec<String>,
+    visibility: Option<Visibility>,
+    /// Record the replays as skipped instead of uploading them
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct HistoryParams {
+    /// Shorthand for `statuses: ["failed"]`
+    failed: bool,
+    #[serde(flatten)]
+    query: HistoryQuery,
+}
+
+#[derive(Deserialize)]
+struct RetryParams {
+    id: String,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct SubscribeParams {
+    /// Event names to receive, all when unset
+    events: Option<Vec<String>>,
+}
+
+/// Path of the control socket, in the runtime directory when there is one
+#[cfg(unix)]
+pub fn socket_path() -> Result<std::path::PathBuf, String> {
+    match dirs::runtime_dir() {
+        Some(dir) => Ok(dir.join(SOCKET_NAME)),
+        None => Ok(crate::headless::data_dir()?.join(SOCKET_NAME)),
+    }
+}
+
+/// Name of the control pipe, one per user
+#[cfg(windows)]
+pub fn pipe_name() -> String {
+    let user = std::env::var("USERNAME").unwrap_or_default();
+    format!("{}-{}", PIPE_NAME, user)
+}
+
+/// Run a control method against the agent
+/// `events.subscribe` and `events.unsubscribe` belong to a connection and are handled there.
+pub async fn dispatch(ctx: &AgentContext, method: &str, params: Value) -> Result<Value, RpcError> {
+    match method {
+        "agent.info" => Ok(json!({
+            "protocolVersion": PROTOCOL_VERSION,
+            "agentVersion": env!("CARGO_PKG_VERSION"),
+            "methods": METHODS,
+            "events": EVENTS,
+        })),
+        "status" => to_value(status(ctx)?),
+        "watcher.pause" => {
+            watcher::set_paused(ctx, true)?;
+            to_value(ctx.watcher.lock().unwrap().get_state())
+        }
+        "watcher.resume" => {
+            watcher::set_paused(ctx, false)?;
+            to_value(ctx.watcher.lock().unwrap().get_state())
+        }
+        "queue.get" => to_value(ctx.upload_queue.get_state()),
+        "upload.enqueue" => {
+            let params: UploadParams = parse_params(params)?;
+            let queued = enqueue_files(ctx, params.paths, params.visibility, params.dry_run)?;
+            Ok(json!({ "queued": queued }))
+        }
+        // Answers once every file is uploaded, requests sent meanwhile on the same
+        // connection wait for it; `upload.enqueue` answers right away
+        "upload.files" => {
+            let params: UploadParams = parse_params(params)?;
+            to_value(upload_files(ctx, params.paths, params.visibility, params.dry_run).await)
+        }
+        "upload.batch" => {
+            let request: BatchRequest = parse_params(params)?;
+            to_value(batch::start(ctx, request).await?)
+        }
+        "history.list" => {
+            let mut params: HistoryParams = parse_params(params)?;
+            if params.failed {
+                params.query.statuses = vec![UploadStatus::Failed];
+            }
+            to_value(history::store(ctx)?.query(&params.query)?)
+        }
+        "history.retry" => {
+            let params: RetryParams = parse_params(params)?;
+            to_value(retry::retry_record(ctx, &params.id).await?)
+        }
+        "stats.get" => to_value(commands::upload_stats(ctx)?),
+        "stats.report" => {
+            let query: StatsQuery = parse_params(params)?;
+            to_value(commands::upload_report(ctx, &query)?)
+        }
+        "session.reload" => {
+            reload_session(ctx)?;
+            Ok(Value::Null)
+        }
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("Unknown method: {}", method),
+        )),
+    }
+}
+
+fn parse_params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    // Methods without required parameters may be called without any
+    let params = if params.is_null() { json!({}) } else { params };
+    serde_json::from_value(params)
+        .map_err(|e| RpcError::new(INVALID_PARAMS, format!("Invalid params: {}", e)))
+}
+
+fn to_value<T: Serialize>(value: T) -> Result<Value, RpcError> {
+    serde_json::to_value(value).map_err(|e| RpcError::from(format!("Failed to serialize result: {}", e)))
+}
+
+/// Current state of the agent
+pub fn status(ctx: &AgentContext) -> Result<AgentStatus, String> {
+    let config = config::load_config(ctx)?;
+    Ok(AgentStatus {
+        watcher: ctx.watcher.lock().unwrap().get_state(),
+        watched_folders: config.watched_folders(),
+        queue: ctx.upload_queue.get_state(),
+        active_uploads: ctx.active_uploads(),
+        account: config::load_session(ctx)?.map(|session| session.user),
+        dry_run: config::is_dry_run(&config),
+    })
+}
+
+/// Upload files one after another and report how each went
+/// A dry run records them as skipped, whatever the config says
+pub async fn upload_files(
+    ctx: &AgentContext,
+    paths: Vec<String>,
+    visibility: Option<Visibility>,
+    dry_run: bool,
+) -> Vec<UploadOutcome> {
+    let uploader = Uploader::new().with_dry_run(dry_run);
+    let mut outcomes = Vec::with_capacity(paths.len());
+
+    for file_path in paths {
+        // Don't record typos as failed uploads
+        if !Path::new(&file_path).is_file() {
+            outcomes.push(UploadOutcome {
+                error: Some(format!("File not found: {}", file_path)),
+                file_path,
+                record: None,
+            });
+            continue;
+        }
+
+        if archive::is_archive(Path::new(&file_path)) {
+            outcomes.extend(upload_archive(ctx, &uploader, file_path, visibility.clone()).await);
+            continue;
+        }
+
+        let outcome = match uploader.upload_replay(ctx, &file_path, visibility.clone()).await {
+            Ok(record) => UploadOutcome {
+                file_path,
+                record: Some(record),
+                error: None,
+            },
+            Err(e) => UploadOutcome {
+                record: history::store(ctx)
+                    .and_then(|store| store.latest_for_path(&file_path))
+                    .ok()
+                    .flatten(),
+                file_path,
+                error: Some(e),
+            },
+        };
+        outcomes.push(outcome);
+    }
+
+    outcomes
+}
+
+/// Upload the replays of a `.zip` archive as a batch, one outcome per replay
+async fn upload_archive(
+    ctx: &AgentContext,
+    uploader: &Uploader,
+    file_path: String,
+    visibility: Option<Visibility>,
+) -> Vec<UploadOutcome> {
+    let request = BatchRequest {
+        paths: vec![file_path.clone()],
+        ..Default::default()
+    };
+    let collect_ctx = ctx.clone();
+    let collected = ctx
+        .spawn_blocking(move || batch::collect(&collect_ctx, &request))
+        .await
+        .map_err(|e| format!("Failed to extract archive: {}", e))
+        .and_then(|result| result);
+    // Extracted replays stay in the staging cache until all are uploaded
+    let BatchFiles {
+        files,
+        skipped,
+        staged: _staged,
+    } = match collected {
+        Ok(collected) => collected,
+        Err(e) => {
+            return vec![UploadOutcome {
+                file_path,
+                record: None,
+                error: Some(e),
+            }]
+        }
+    };
+
+    let batch_id = uuid::Uuid::new_v4().to_string();
+    let mut outcomes: Vec<UploadOutcome> = skipped
+        .into_iter()
+        .map(|issue| UploadOutcome {
+            file_path: issue.file_path,
+            record: None,
+            error: Some(issue.reason),
+        })
+        .collect();
+
+    for file in files {
+        let uploaded =
+            batch::upload_file(ctx, uploader, &file, &batch_id, visibility.clone()).await;
+        let outcome = match uploaded {
+            Ok(record) => UploadOutcome {
+                file_path: file.display_path,
+                record: Some(record),
+                error: None,
+            },
+            Err(e) => UploadOutcome {
+                record: history::store(ctx)
+                    .and_then(|store| store.latest_for_path(&file.file_path))
+                    .ok()
+                    .flatten(),
+                file_path: file.display_path,
+                error: Some(e),
+            },
+        };
+        outcomes.push(outcome);
+    }
+
+    outcomes
+}
+
+/// Upload files in the background, progress is reported through events
+/// Returns how many files were queued; nothing is queued if a file is missing
+pub fn enqueue_files(
+    ctx: &AgentContext,
+    paths: Vec<String>,
+    visibility: Option<Visibility>,
+    dry_run: bool,
+) -> Result<usize, RpcError> {
+    if let Some(missing) = paths.iter().find(|path| !Path::new(path).is_file()) {
+        return Err(RpcError::new(
+            INVALID_PARAMS,
+            format!("File not found: {}", missing),
+        ));
+    }
+
+    let queued = paths.len();
+    spawn_uploads(ctx, paths, visibility, dry_run);
+    Ok(queued)
+}
+
+/// Upload files one after another in the background, failures are logged
+pub fn spawn_uploads(
+    ctx: &AgentContext,
+    paths: Vec<String>,
+    visibility: Option<Visibility>,
+    dry_run: bool,
+) {
+    let task_ctx = ctx.clone();
+    ctx.spawn(async move {
+        for outcome in upload_files(&task_ctx, paths, visibility, dry_run).await {
+            if let Some(error) = outcome.error {
+                tracing::error!("Upload of {} failed: {}", outcome.file_path, error);
+            }
+        }
+    });
+}
+
+/// Tell the app about a session changed by another process
+/// and release uploads held for login once there is one
+pub fn reload_session(ctx: &AgentContext) -> Result<(), String> {
+    let active = config::load_session(ctx)?.map(|session| session.user);
+    let logged_in = active.is_some();
+    ctx.emit("account_changed", &active);
+
+    if logged_in {
+        queue::resume_held_uploads(ctx);
+    }
+    Ok(())
+}
+
+/// Serve control requests on the socket (a named pipe on Windows) in the background
+/// Nothing is served if another agent already listens on it
+pub fn start_server(ctx: &AgentContext) {
+    let task_ctx = ctx.clone();
+    ctx.spawn(async move {
+        if let Err(e) = server::serve(task_ctx).await {
+            tracing::error!("Control socket stopped: {}", e);
+        }
+    });
+}
+
+mod server {
+    use serde::Serialize;
+    use serde_json::{json, Value};
+    use std::collections::HashSet;
+    use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+    use tokio::sync::{broadcast, mpsc};
+    use tokio::task::JoinHandle;
+
+    use super::{dispatch, parse_params, Request, Response, RpcError, SubscribeParams, PARSE_ERROR};
+    use crate::agent::{AgentContext, AgentEvent};
+
+    /// Messages queued for a connection before event forwarding waits for the client
+    const OUTGOING_CAPACITY: usize = 64;
+
+    #[cfg(unix)]
+    pub(super) async fn serve(ctx: AgentContext) -> Result<(), String> {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+        use tokio::net::{UnixListener, UnixStream};
+
+        let path = super::socket_path()?;
+        if path.exists() {
+            if UnixStream::connect(&path).await.is_ok() {
+                tracing::warn!("Another agent is listening on {}", path.display());
+                return Ok(());
+            }
+            // Left behind by an agent that didn't exit cleanly
+            let _ = std::fs::remove_file(&path);
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        }
+
+        let listener = UnixListener::bind(&path)
+            .map_err(|e| format!("Failed to listen on {}: {}", path.display(), e))?;
+        // Only the current user may control the agent
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))
+            .map_err(|e| format!("Failed to restrict {}: {}", path.display(), e))?;
+        let owner = std::fs::metadata(&path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?
+            .uid();
+
+        tracing::info!("Control socket listening on {}", path.display());
+
+        loop {
+            let (stream, _) = listener
+                .accept()
+                .await
+                .map_err(|e| format!("Failed to accept connection: {}", e))?;
+
+            // The file mode already keeps others out, check the peer as well
+            match stream.peer_cred() {
+                Ok(cred) if cred.uid() == owner => {}
+                _ => {
+                    tracing::warn!("Rejected control connection from another user");
+                    continue;
+                }
+            }
+
+            spawn_connection(&ctx, stream);
+        }
+    }
+
+    /// Serve on a named pipe; each client gets its own instance of it
+    /// Only the current user can write to it, others' requests never reach the agent
+    #[cfg(windows)]
+    pub(super) async fn serve(ctx: AgentContext) -> Result<(), String> {
+        use tokio::net::windows::named_pipe::ServerOptions;
+
+        let name = super::pipe_name();
+        // Fails when another agent created the pipe first
+        let mut pipe = match ServerOptions::new()
+            .first_pipe_instance(true)
+            .reject_remote_clients(true)
+            .create(&name)
+        {
+            Ok(pipe) => pipe,
+            Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+                tracing::warn!("Another agent is listening on {}", name);
+                return Ok(());
+            }
+            Err(e) => return Err(format!("Failed to listen on {}: {}", name, e)),
+        };
+
+        tracing::info!("Control pipe listening on {}", name);
+
+        loop {
+            pipe.connect()
+                .await
+                .map_err(|e| format!("Failed to accept connection: {}", e))?;
+
+            // Open the next instance before serving this client, so others can connect
+            let next = ServerOptions::new()
+                .reject_remote_clients(true)
+                .create(&name)
+                .map_err(|e| format!("Failed to listen on {}: {}", name, e))?;
+            spawn_connection(&ctx, std::mem::replace(&mut pipe, next));
+        }
+    }
+
+    /// Serve a connected client in the background
+    pub(super) fn spawn_connection<S>(ctx: &AgentContext, stream: S)
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let conn_ctx = ctx.clone();
+        ctx.spawn(async move {
+            if let Err(e) = Connection::run(conn_ctx, stream).await {
+                tracing::debug!("Control connection closed: {}", e);
+            }
+        });
+    }
+
+    /// One client: requests are answered in order, events are pushed as they happen
+    struct Connection {
+        ctx: AgentContext,
+        outgoing: mpsc::Sender<String>,
+        subscription: Option<JoinHandle<()>>,
+    }
+
+    impl Connection {
+        async fn run<S>(ctx: AgentContext, stream: S) -> std::io::Result<()>
+        where
+            S: AsyncRead + AsyncWrite + Send + 'static,
+        {
+            let (reader, mut writer) = tokio::io::split(stream);
+            let (outgoing, mut outgoing_rx) = mpsc::channel::<String>(OUTGOING_CAPACITY);
+
+            let writer_task = ctx.spawn(async move {
+                while let Some(line) = outgoing_rx.recv().await {
+                    if writer.write_all(line.as_bytes()).await.is_err() {
+                        break;
+                    }
+                }
+            });
+
+            let mut connection = Connection {
+                ctx,
+                outgoing,
+                subscription: None,
+            };
+            let result = connection.serve(BufReader::new(reader).lines()).await;
+
+            if let Some(subscription) = connection.subscription.take() {
+                subscription.abort();
+            }
+            // The writer stops once the queued responses are sent
+            drop(connection);
+            let _ = writer_task.await;
+            result
+        }
+
+        async fn serve<R: AsyncRead + Unpin>(
+            &mut self,
+            mut lines: tokio::io::Lines<BufReader<R>>,
+        ) -> std::io::Result<()> {
+            while let Some(line) = lines.next_line().await? {
+                if line.trim().is_empty() {
+                    continue;
+                }
+
+                let response = self.handle_line(&line).await;
+                if self.outgoing.send(encode(&response)).await.is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        }
+
+        async fn handle_line(&mut self, line: &str) -> Response {
+            let request: Request = match serde_json::from_str(line) {
+                Ok(request) => request,
+                Err(e) => {
+                    return Response::new(
+                        Value::Null,
+                        Err(RpcError::new(PARSE_ERROR, format!("Invalid request: {}", e))),
+                    )
+                }
+            };
+
+            let outcome = match request.method.as_str() {
+                "events.subscribe" => self.subscribe(request.params),
+                "events.unsubscribe" => {
+                    if let Some(subscription) = self.subscription.take() {
+                        subscription.abort();
+                    }
+                    Ok(Value::Null)
+                }
+                method => dispatch(&self.ctx, method, request.params).await,
+            };
+            Response::new(request.id, outcome)
+        }
+
+        /// Forward events to this client, replacing an earlier subscription
+        fn subscribe(&mut self, params: Value) -> Result<Value, RpcError> {
+            let params: SubscribeParams = parse_params(params)?;
+            if let Some(subscription) = self.subscription.take() {
+                subscription.abort();
+            }
+
+            let filter: Option<HashSet<String>> =
+                params.events.clone().map(|events| events.into_iter().collect());
+            let events = self.ctx.subscribe();
+            let outgoing = self.outgoing.clone();
+            self.subscription = Some(self.ctx.spawn(forward_events(events, filter, outgoing)));
+
+            Ok(json!({ "events": params.events }))
+        }
+    }
+
+    /// Push events as `event` notifications until the client goes away
+    /// A client that can't keep up gets `events.lagged` with the number of missed events
+    async fn forward_events(
+        mut events: broadcast::Receiver<AgentEvent>,
+        filter: Option<HashSet<String>>,
+        outgoing: mpsc::Sender<String>,
+    ) {
+        loop {
+            let line = match events.recv().await {
+                Ok(event) => {
+                    if filter.as_ref().is_some_and(|names| !names.contains(&event.event)) {
+                        continue;
+                    }
+                    encode(&notification("event", json!(event)))
+                }
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    encode(&notification("events.lagged", json!({ "missed": missed })))
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            };
+
+            if outgoing.send(line).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    fn notification(method: &str, params: Value) -> Value {
+        json!({ "jsonrpc": "2.0", "method": method, "params": params })
+    }
+
+    /// A message as one line
+    fn encode<T: Serialize>(message: &T) -> String {
+        let mut line = serde_json::to_string(message).unwrap_or_default();
+        line.push('\n');
+        line
+    }
+}
+
+#[cfg(unix)]
+type Stream = tokio::net::UnixStream;
+#[cfg(windows)]
+type Stream = tokio::net::windows::named_pipe::NamedPipeClient;
+
+/// Connection to a running agent's control socket
+pub struct Client {
+    lines: tokio::io::Lines<tokio::io::BufReader<tokio::io::ReadHalf<Stream>>>,
+    writer: tokio::io::WriteHalf<Stream>,
+    next_id: u64,
+}
+
+impl Client {
+    /// Connect to the running agent
+    /// None if no agent is running or it speaks another protocol version
+    pub async fn connect() -> Option<Self> {
+        use tokio::io::{AsyncBufReadExt, BufReader};
+
+        let (reader, writer) = tokio::io::split(Self::open().await?);
+        let mut client = Self {
+            lines: BufReader::new(reader).lines(),
+            writer,
+            next_id: 0,
+        };
+
+        let info = client.call("agent.info", Value::Null).await.ok()?;
+        let version = info.get("protocolVersion").and_then(Value::as_u64);
+        if version != Some(PROTOCOL_VERSION.into()) {
+            tracing::warn!(
+                "Running agent speaks control protocol {:?}, expected {}",
+                version,
+                PROTOCOL_VERSION
+            );
+            return None;
+        }
+
+        Some(client)
+    }
+
+    #[cfg(unix)]
+    async fn open() -> Option<Stream> {
+        tokio::net::UnixStream::connect(socket_path().ok()?).await.ok()
+    }
+
+    #[cfg(windows)]
+    async fn open() -> Option<Stream> {
+        use tokio::net::windows::named_pipe::ClientOptions;
+        const ERROR_PIPE_BUSY: i32 = 231;
+
+        // Every instance is busy until the agent opens the next one
+        for _ in 0..20 {
+            match ClientOptions::new().open(pipe_name()) {
+                Ok(pipe) => return Some(pipe),
+                Err(e) if e.raw_os_error() == Some(ERROR_PIPE_BUSY) => {
+                    tokio::time::sleep(std::time::Duration::from_millis(50)).await
+                }
+                Err(_) => return None,
+            }
+        }
+        None
+    }
+
+    /// Call a method and wait for its result
+    /// Event notifications received in the meantime are skipped.
+    pub async fn call(&mut self, method: &str, params: Value) -> Result<Value, String> {
+        use tokio::io::AsyncWriteExt;
+
+        self.next_id += 1;
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": self.next_id,
+            "method": method,
+            "params": params,
+        });
+        let mut line = serde_json::to_string(&request)
+            .map_err(|e| format!("Failed to serialize request: {}", e))?;
+        line.push('\n');
+        self.writer
+            .write_all(line.as_bytes())
+            .await
+            .map_err(|e| format!("Failed to reach the agent: {}", e))?;
+
+        loop {
+            let line = self
+                .lines
+                .next_line()
+                .await
+                .map_err(|e| format!("Failed to read from the agent: {}", e))?
+                .ok_or("The agent closed the connection")?;
+            let mut message: Value = serde_json::from_str(&line)
+                .map_err(|e| format!("Invalid response from the agent: {}", e))?;
+
+            if message.get("id").and_then(Value::as_u64) != Some(self.next_id) {
+                continue;
+            }
+            if let Some(error) = message.get("error") {
+                let error: RpcError = serde_json::from_value(error.clone())
+                    .map_err(|e| format!("Invalid error from the agent: {}", e))?;
+                return Err(error.message);
+            }
+            return Ok(message
+                .get_mut("result")
+                .map(Value::take)
+                .unwrap_or(Value::Null));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::testing::TestAgent;
+    use std::time::Duration;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream, Lines, ReadHalf, WriteHalf};
+
+    /// Client end of a connection served by the agent
+    struct TestClient {
+        lines: Lines<BufReader<ReadHalf<DuplexStream>>>,
+        writer: WriteHalf<DuplexStream>,
+    }
+
+    impl TestClient {
+        fn connect(ctx: &AgentContext) -> Self {
+            let (client, agent) = tokio::io::duplex(64 * 1024);
+            server::spawn_connection(ctx, agent);
+            let (reader, writer) = tokio::io::split(client);
+            Self {
+                lines: BufReader::new(reader).lines(),
+                writer,
+            }
+        }
+
+        async fn send(&mut self, line: &str) {
+            self.writer.write_all(format!("{}\n", line).as_bytes()).await.unwrap();
+        }
+
+        async fn receive(&mut self) -> Value {
+            let line = tokio::time::timeout(Duration::from_secs(5), self.lines.next_line())
+                .await
+                .expect("no message from the agent")
+                .unwrap()
+                .expect("connection closed");
+            serde_json::from_str(&line).unwrap()
+        }
+
+        async fn subscribe(&mut self, events: Value) {
+            self.send(&json!({ "id": 1, "method": "events.subscribe", "params": { "events": events } }).to_string())
+                .await;
+            assert_eq!(self.receive().await["result"], json!({ "events": events }));
+        }
+    }
+
+    #[tokio::test]
+    async fn agent_info_describes_the_protocol() {
+        let agent = TestAgent::new();
+        let info = dispatch(&agent.ctx, "agent.info", Value::Null).await.unwrap();
+        assert_eq!(info["protocolVersion"], PROTOCOL_VERSION);
+        assert_eq!(info["agentVersion"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(info["methods"], json!(METHODS));
+        assert_eq!(info["events"], json!(EVENTS));
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_methods_and_invalid_params() {
+        let agent = TestAgent::new();
+        let ctx = &agent.ctx;
+        assert_eq!(dispatch(ctx, "replays.delete", Value::Null).await.unwrap_err().code, METHOD_NOT_FOUND);
+        // Subscriptions belong to a connection
+        assert_eq!(dispatch(ctx, "events.subscribe", Value::Null).await.unwrap_err().code, METHOD_NOT_FOUND);
+
+        for (method, params) in [
+            ("history.retry", json!({})),
+            ("history.list", json!({ "limit": "ten" })),
+            ("upload.files", json!({ "paths": "a.replay" })),
+            ("upload.enqueue", json!([1])),
+            ("upload.enqueue", json!({ "paths": ["/nonexistent/a.replay"] })),
+        ] {
+            let error = dispatch(ctx, method, params.clone()).await.unwrap_err();
+            assert_eq!(error.code, INVALID_PARAMS, "{} {}", method, params);
+        }
+    }
+
+    #[tokio::test]
+    async fn dry_run_uploads_are_recorded_as_skipped() {
+        let agent = TestAgent::new();
+        let path = agent.file("a.replay", crate::replay::tests::replay_with_guid("AAAA"));
+
+        let params = json!({ "paths": [path.to_string_lossy()], "dryRun": true });
+        let result = dispatch(&agent.ctx, "upload.files", params).await.unwrap();
+        let outcomes: Vec<UploadOutcome> = serde_json::from_value(result).unwrap();
+
+        let record = outcomes[0].record.as_ref().unwrap();
+        assert!(outcomes[0].error.is_none());
+        assert!(matches!(record.status, UploadStatus::Skipped));
+        assert_eq!(record.skip_reason.as_deref(), Some(crate::uploader::DRY_RUN_REASON));
+    }
+
+    #[tokio::test]
+    async fn answers_requests_on_a_connection() {
+        let agent = TestAgent::new();
+        let ctx = &agent.ctx;
+        let mut client = TestClient::connect(ctx);
+
+        client.send("not json").await;
+        let response = client.receive().await;
+        assert_eq!(response["error"]["code"], PARSE_ERROR);
+        assert_eq!(response["id"], Value::Null);
+
+        client.send(r#"{"jsonrpc": "2.0", "id": "a", "method": "nope"}"#).await;
+        let response = client.receive().await;
+        assert_eq!(response["id"], "a");
+        assert_eq!(response["error"]["code"], METHOD_NOT_FOUND);
+
+        client.send(r#"{"jsonrpc": "2.0", "id": 2, "method": "agent.info"}"#).await;
+        let response = client.receive().await;
+        assert_eq!(response["id"], 2);
+        assert_eq!(response["result"]["protocolVersion"], PROTOCOL_VERSION);
+    }
+
+    #[tokio::test]
+    async fn subscribers_only_get_the_events_they_asked_for() {
+        let agent = TestAgent::new();
+        let ctx = &agent.ctx;
+        let mut client = TestClient::connect(ctx);
+        client.subscribe(json!(["upload_completed"])).await;
+
+        ctx.emit("upload_started", &json!({ "id": "a" }));
+        ctx.emit("upload_completed", &json!({ "id": "a" }));
+        let message = client.receive().await;
+        assert_eq!(message["method"], "event");
+        assert_eq!(message["params"], json!({ "event": "upload_completed", "payload": { "id": "a" } }));
+
+        // Nothing is forwarded after unsubscribing
+        client.send(r#"{"id": 2, "method": "events.unsubscribe"}"#).await;
+        assert_eq!(client.receive().await["id"], 2);
+        ctx.emit("upload_completed", &json!({ "id": "b" }));
+        client.send(r#"{"id": 3, "method": "agent.info"}"#).await;
+        assert_eq!(client.receive().await["id"], 3);
+    }
+
+    #[tokio::test]
+    async fn slow_subscribers_are_told_how_many_events_they_missed() {
+        let agent = TestAgent::new();
+        let ctx = &agent.ctx;
+        let mut client = TestClient::connect(ctx);
+        client.subscribe(Value::Null).await;
+
+        // The forwarding task can't run in between, the bus drops the oldest events
+        for n in 0..1000 {
+            ctx.emit("upload_progress", &json!({ "n": n }));
+        }
+
+        let lagged = client.receive().await;
+        assert_eq!(lagged["method"], "events.lagged");
+        let missed = lagged["params"]["missed"].as_u64().unwrap();
+        assert!(missed > 0 && missed < 1000, "{}", missed);
+
+        // Then the events that are left, the newest last
+        let first = client.receive().await;
+        assert_eq!(first["params"]["payload"]["n"], missed);
+    }
+}
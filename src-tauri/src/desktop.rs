@@ -0,0 +1,247 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tauri::{AppHandle, Emitter, Listener, Manager, Url};
+
+use crate::agent::{AgentContext, EventSink, SystemClock};
+use crate::archive;
+use crate::control;
+use crate::library;
+use crate::store::FileStorage;
+
+/// Scheme of the app's links, e.g. `ballcam://history/<record id>`
+const LINK_SCHEME: &str = "ballcam";
+
+/// Event sink of the desktop app: events go to the frontend, notifications to the OS
+pub struct TauriEvents {
+    app: AppHandle,
+}
+
+impl TauriEvents {
+    pub fn new(app: &AppHandle) -> Self {
+        Self { app: app.clone() }
+    }
+}
+
+impl EventSink for TauriEvents {
+    fn emit_value(&self, event: &str, payload: serde_json::Value) {
+        let _ = self.app.emit(event, payload);
+    }
+
+    fn notify(&self, title: &str, body: &str) {
+        // Use native Windows toast notification
+        #[cfg(windows)]
+        {
+            use tauri_winrt_notification::{Duration, Toast};
+
+            // Use a known Windows AUMID for dev mode, or the app's own ID in production
+            // Microsoft.Windows.Explorer works as a fallback
+            let _ = Toast::new(Toast::POWERSHELL_APP_ID)
+                .title("BallCam Agent")
+                .text1(title)
+                .text2(body)
+                .duration(Duration::Short)
+                .show();
+        }
+
+        // Fallback for non-Windows platforms
+        #[cfg(not(windows))]
+        {
+            use tauri_plugin_notification::NotificationExt;
+
+            let _ = self
+                .app
+                .notification()
+                .builder()
+                .title(title)
+                .body(body)
+                .show();
+        }
+    }
+}
+
+/// Build the agent context of the desktop app
+/// Stores live in the app data directory and background tasks run on Tauri's runtime.
+/// The history and library are left out if they can't be opened.
+pub fn build_context(app: &AppHandle) -> Result<AgentContext, String> {
+    let data_dir = app
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to resolve app data directory: {}", e))?;
+
+    let events: Arc<dyn EventSink> = Arc::new(TauriEvents::new(app));
+    let storage = Arc::new(FileStorage::new(data_dir, events.clone()));
+    let runtime = tauri::async_runtime::handle().inner().clone();
+    Ok(AgentContext::new(events, storage, Arc::new(SystemClock), runtime).open_stores())
+}
+
+/// Show in the tray tooltip when uploads wait for the user to log in again
+pub fn track_login_state(app: &AppHandle) {
+    #[cfg(desktop)]
+    {
+        let tray_app = app.clone();
+        app.listen_any("session_expired", move |_| {
+            if let Some(tray) = tray_app.tray_by_id("main") {
+                let _ = tray.set_tooltip(Some("BallCam Agent - Login required"));
+            }
+        });
+
+        let tray_app = app.clone();
+        app.listen_any("uploads_resumed", move |_| {
+            if let Some(tray) = tray_app.tray_by_id("main") {
+                let _ = tray.set_tooltip(Some("BallCam Agent"));
+            }
+        });
+    }
+}
+
+/// Bring the main window to the front
+pub fn show_main_window(app: &AppHandle) {
+    if let Some(window) = app.get_webview_window("main") {
+        let _ = window.show();
+        let _ = window.unminimize();
+        let _ = window.set_focus();
+    }
+}
+
+/// History item of the last `ballcam://history/<id>` link, until the window takes it
+/// The window may not be loaded yet when the app is launched by a link.
+#[derive(Default)]
+pub struct PendingHistoryItem(pub Mutex<Option<String>>);
+
+/// Handle the replay files and links the app was launched with, and links opened later
+/// A second launch is handled by the single instance plugin, see `lib.rs`.
+pub fn track_opened_files(app: &AppHandle, replays: Vec<PathBuf>) {
+    use tauri_plugin_deep_link::DeepLinkExt;
+
+    // Installers register the scheme, this covers AppImages and development builds
+    #[cfg(any(windows, target_os = "linux"))]
+    if let Err(e) = app.deep_link().register_all() {
+        tracing::warn!("Failed to register {}:// links: {}", LINK_SCHEME, e);
+    }
+
+    let link_app = app.clone();
+    app.deep_link()
+        .on_open_url(move |event| open_urls(&link_app, event.urls()));
+
+    if let Ok(Some(urls)) = app.deep_link().get_current() {
+        open_urls(app, urls);
+    }
+
+    open_replays(app, replays);
+}
+
+/// Hand the replays the app was launched with to an agent already running, e.g. headless
+/// Returns whether one answered; the app exits then instead of watching the folders twice.
+/// Another desktop instance is caught earlier by the single instance plugin.
+pub fn forward_to_running_agent(app: &AppHandle, replays: &[PathBuf]) -> bool {
+    tauri::async_runtime::block_on(async {
+        let Some(mut client) = control::Client::connect().await else {
+            return false;
+        };
+
+        let paths: Vec<String> = replays
+            .iter()
+            .map(|path| path.to_string_lossy().to_string())
+            .collect();
+
+        let body = if paths.is_empty() {
+            "BallCam Agent is already running in the background".to_string()
+        } else {
+            match client.call("upload.enqueue", serde_json::json!({ "paths": paths })).await {
+                Ok(_) => format!("Uploading {} replays with the agent already running", paths.len()),
+                Err(e) => format!("The agent already running couldn't upload the replays: {}", e),
+            }
+        };
+        TauriEvents::new(app).notify("BallCam Agent", &body);
+        true
+    })
+}
+
+/// Replay files and `.zip` archives of replays among command line arguments, without the program
+/// (file association, "Open with", files dropped on the executable),
+/// relative paths resolved against `cwd`
+pub fn replay_args(args: &[String], cwd: &Path) -> Vec<PathBuf> {
+    args.iter()
+        .map(PathBuf::from)
+        .filter(|path| is_uploadable(path))
+        .map(|path| cwd.join(path))
+        .collect()
+}
+
+fn is_uploadable(path: &Path) -> bool {
+    library::is_replay(path) || archive::is_archive(path)
+}
+
+/// Upload replay files and archives opened with the app
+pub fn open_replays(app: &AppHandle, paths: Vec<PathBuf>) {
+    if paths.is_empty() {
+        return;
+    }
+    let Some(ctx) = app.try_state::<AgentContext>() else {
+        return;
+    };
+
+    tracing::info!("Uploading {} opened replays", paths.len());
+    show_main_window(app);
+
+    let paths = paths
+        .iter()
+        .map(|path| path.to_string_lossy().to_string())
+        .collect();
+    control::spawn_uploads(&ctx, paths, None, false);
+}
+
+/// Open `ballcam://` links, and on macOS replay files opened from Finder
+fn open_urls(app: &AppHandle, urls: Vec<Url>) {
+    let mut replays = Vec::new();
+
+    for url in urls {
+        match url.scheme() {
+            "file" => match url.to_file_path() {
+                Ok(path) if is_uploadable(&path) => replays.push(path),
+                _ => tracing::warn!("Ignoring opened file: {}", url),
+            },
+            LINK_SCHEME => open_link(app, &url),
+            _ => tracing::warn!("Ignoring unsupported link: {}", url),
+        }
+    }
+
+    open_replays(app, replays);
+}
+
+/// `ballcam://history/<record id>` shows the record in the upload history
+fn open_link(app: &AppHandle, url: &Url) {
+    let id = url.path().trim_matches('/');
+    if url.host_str() != Some("history") || id.is_empty() {
+        tracing::warn!("Ignoring unsupported link: {}", url);
+        return;
+    }
+
+    if let Some(pending) = app.try_state::<PendingHistoryItem>() {
+        *pending.0.lock().unwrap() = Some(id.to_string());
+    }
+    show_main_window(app);
+    let _ = app.emit("open_history_item", id);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replay_args_keep_replays_and_archives() {
+        let args: Vec<String> = ["a.replay", "--minimized", "b.ZIP", "notes.txt", "/tmp/c.zip"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+
+        assert_eq!(
+            replay_args(&args, Path::new("/home/user")),
+            vec![
+                PathBuf::from("/home/user/a.replay"),
+                PathBuf::from("/home/user/b.ZIP"),
+                PathBuf::from("/tmp/c.zip"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,311 @@
+use serde::{Deserialize, Serialize};
+use std::io::BufRead;
+use std::path::Path;
+
+use crate::store;
+use crate::types::{
+    ErrorClass, HistoryExportFormat, ReplayMetadata, UploadRecord, UploadSource, UploadStatus,
+    Visibility,
+};
+
+/// Flat CSV row for an upload record, metadata spread over its own columns
+#[derive(Debug, Serialize, Deserialize)]
+struct CsvRow {
+    id: String,
+    filename: String,
+    file_path: String,
+    status: UploadStatus,
+    replay_id: Option<String>,
+    replay_url: Option<String>,
+    error_message: Option<String>,
+    error_class: Option<ErrorClass>,
+    attempts: u32,
+    created_at: String,
+    completed_at: Option<String>,
+    file_size: Option<u64>,
+    account_id: Option<String>,
+    account_username: Option<String>,
+    visibility: Option<Visibility>,
+    replay_guid: Option<String>,
+    replay_name: Option<String>,
+    map_name: Option<String>,
+    match_type: Option<String>,
+    team_size: Option<u32>,
+    team0_score: Option<u32>,
+    team1_score: Option<u32>,
+    goals: Option<u32>,
+    player_name: Option<String>,
+    match_date: Option<String>,
+    duration_seconds: Option<f32>,
+    build_version: Option<String>,
+}
+
+impl From<&UploadRecord> for CsvRow {
+    fn from(record: &UploadRecord) -> Self {
+        let metadata = record.metadata.as_ref();
+        Self {
+            id: record.id.clone(),
+            filename: record.filename.clone(),
+            file_path: record.file_path.clone(),
+            status: record.status.clone(),
+            replay_id: record.replay_id.clone(),
+            replay_url: record.replay_url.clone(),
+            error_message: record.error_message.clone(),
+            error_class: record.error_class,
+            attempts: record.attempts,
+            created_at: record.created_at.clone(),
+            completed_at: record.completed_at.clone(),
+            file_size: record.file_size,
+            account_id: record.account_id.clone(),
+            account_username: record.account_username.clone(),
+            visibility: record.visibility.clone(),
+            replay_guid: metadata.and_then(|m| m.replay_guid.clone()),
+            replay_name: metadata.and_then(|m| m.replay_name.clone()),
+            map_name: metadata.and_then(|m| m.map_name.clone()),
+            match_type: metadata.and_then(|m| m.match_type.clone()),
+            team_size: metadata.and_then(|m| m.team_size),
+            team0_score: metadata.map(|m| m.team0_score),
+            team1_score: metadata.map(|m| m.team1_score),
+            goals: metadata.map(|m| m.goals),
+            player_name: metadata.and_then(|m| m.player_name.clone()),
+            match_date: metadata.and_then(|m| m.date.clone()),
+            duration_seconds: metadata.and_then(|m| m.duration_seconds),
+            build_version: metadata.and_then(|m| m.build_version.clone()),
+        }
+    }
+}
+
+impl From<CsvRow> for UploadRecord {
+    fn from(row: CsvRow) -> Self {
+        // Scores are written for every record with metadata, even 0-0 matches
+        let metadata = row.team0_score.is_some().then(|| ReplayMetadata {
+            replay_guid: row.replay_guid,
+            replay_name: row.replay_name,
+            map_name: row.map_name,
+            match_type: row.match_type,
+            team_size: row.team_size,
+            team0_score: row.team0_score.unwrap_or(0),
+            team1_score: row.team1_score.unwrap_or(0),
+            goals: row.goals.unwrap_or(0),
+            player_name: row.player_name,
+            date: row.match_date,
+            duration_seconds: row.duration_seconds,
+            build_version: row.build_version,
+            unfair_teams: false,
+        });
+
+        Self {
+            id: row.id,
+            filename: row.filename,
+            file_path: row.file_path,
+            status: row.status,
+            replay_id: row.replay_id,
+            replay_url: row.replay_url,
+            error_message: row.error_message,
+            attempts: row.attempts,
+            created_at: row.created_at,
+            completed_at: row.completed_at,
+            file_size: row.file_size,
+            account_id: row.account_id,
+            account_username: row.account_username,
+            visibility: row.visibility,
+            metadata,
+            error_class: row.error_class,
+            // Only JSON Lines exports carry the attempt log
+            attempt_log: Vec::new(),
+            source: UploadSource::default(),
+            archived_path: None,
+            skip_reason: None,
+        }
+    }
+}
+
+/// Write records to a file in the given format, replacing it atomically
+pub fn write_records(
+    path: &Path,
+    format: HistoryExportFormat,
+    records: &[UploadRecord],
+) -> Result<(), String> {
+    let contents = match format {
+        HistoryExportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            for record in records {
+                writer
+                    .serialize(CsvRow::from(record))
+                    .map_err(|e| format!("Failed to write CSV: {}", e))?;
+            }
+            writer
+                .into_inner()
+                .map_err(|e| format!("Failed to write CSV: {}", e))?
+        }
+        HistoryExportFormat::Jsonl => {
+            let mut contents = Vec::new();
+            for record in records {
+                serde_json::to_writer(&mut contents, record)
+                    .map_err(|e| format!("Failed to serialize history record: {}", e))?;
+                contents.push(b'\n');
+            }
+            contents
+        }
+    };
+
+    store::write_atomic(path, &contents)
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+/// Read records exported by `write_records`
+/// The format is picked from the extension: `.csv` for CSV, JSON Lines otherwise
+pub fn read_records(path: &Path) -> Result<Vec<UploadRecord>, String> {
+    let file = std::fs::File::open(path)
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+
+    let is_csv = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("csv"));
+
+    if is_csv {
+        csv::Reader::from_reader(file)
+            .deserialize::<CsvRow>()
+            .enumerate()
+            .map(|(index, row)| {
+                row.map(UploadRecord::from)
+                    .map_err(|e| format!("Invalid CSV row {}: {}", index + 1, e))
+            })
+            .collect()
+    } else {
+        std::io::BufReader::new(file)
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !matches!(line, Ok(line) if line.trim().is_empty()))
+            .map(|(index, line)| {
+                let line = line.map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+                serde_json::from_str(&line)
+                    .map_err(|e| format!("Invalid record on line {}: {}", index + 1, e))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn records() -> Vec<UploadRecord> {
+        vec![
+            serde_json::from_value(json!({
+                "id": "completed",
+                "filename": "Match, \"final\".replay",
+                "filePath": "/replays/Match, \"final\".replay",
+                "status": "completed",
+                "replayId": "r-1",
+                "replayUrl": "https://ballcam.tv/replays/r-1",
+                "errorMessage": null,
+                "attempts": 2,
+                "createdAt": "2024-01-15T20:30:12Z",
+                "completedAt": "2024-01-15T20:31:00Z",
+                "fileSize": 1_500_000,
+                "accountId": "user-1",
+                "accountUsername": "Zephyr",
+                "visibility": "unlisted",
+                "metadata": {
+                    "replayGuid": "8A1E2C4F",
+                    "replayName": "Overtime thriller",
+                    "mapName": "stadium_p",
+                    "matchType": "Online",
+                    "teamSize": 3,
+                    "team0Score": 0,
+                    "team1Score": 0,
+                    "goals": 0,
+                    "playerName": "プレイヤー",
+                    "date": "2024-01-15 20-30-12",
+                    "durationSeconds": 312.5,
+                    "buildVersion": "240112",
+                    "unfairTeams": true,
+                },
+                "attemptLog": [
+                    {
+                        "startedAt": "2024-01-15T20:30:12Z",
+                        "durationMs": 1200,
+                        "error": "Network error: timed out",
+                        "errorClass": "transient",
+                    },
+                    { "startedAt": "2024-01-15T20:30:58Z", "durationMs": 2000 },
+                ],
+                "batchId": "batch-1",
+                "archiveName": "season.zip",
+                "archivedPath": "/archive/final.replay",
+            }))
+            .unwrap(),
+            serde_json::from_value(json!({
+                "id": "failed",
+                "filename": "broken.replay",
+                "filePath": "/replays/broken.replay",
+                "status": "failed",
+                "replayId": null,
+                "replayUrl": null,
+                "errorMessage": "Upload failed (400): invalid replay\nline two",
+                "errorClass": "permanent",
+                "attempts": 1,
+                "createdAt": "2024-01-16T08:00:00Z",
+                "completedAt": null,
+            }))
+            .unwrap(),
+        ]
+    }
+
+    /// Export the records and read them back
+    fn round_trip(format: HistoryExportFormat, extension: &str) -> Vec<UploadRecord> {
+        let path = std::env::temp_dir().join(format!("ballcam-export-{}.{}", uuid::Uuid::new_v4(), extension));
+        write_records(&path, format, &records()).unwrap();
+        let read = read_records(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        read
+    }
+
+    fn values(records: &[UploadRecord]) -> Vec<Value> {
+        records
+            .iter()
+            .map(|record| serde_json::to_value(record).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn jsonl_round_trip_is_lossless() {
+        let read = round_trip(HistoryExportFormat::Jsonl, "jsonl");
+        assert_eq!(values(&read), values(&records()));
+    }
+
+    #[test]
+    fn csv_round_trip_keeps_the_columns() {
+        let read = round_trip(HistoryExportFormat::Csv, "csv");
+
+        // Only JSON Lines keeps the attempt log, source, archive path and unfair teams flag
+        let mut expected = values(&records());
+        for record in &mut expected {
+            let record = record.as_object_mut().unwrap();
+            for key in ["attemptLog", "batchId", "archiveName", "archivedPath"] {
+                record.remove(key);
+            }
+            if let Some(metadata) = record.get_mut("metadata") {
+                metadata["unfairTeams"] = json!(false);
+            }
+        }
+        assert_eq!(values(&read), expected);
+
+        // 0-0 matches keep their metadata
+        assert_eq!(read[0].metadata.as_ref().unwrap().team0_score, 0);
+        assert!(read[1].metadata.is_none());
+    }
+
+    #[test]
+    fn reports_the_invalid_line() {
+        let path = std::env::temp_dir().join(format!("ballcam-export-{}.jsonl", uuid::Uuid::new_v4()));
+        let valid = serde_json::to_string(&records()[1]).unwrap();
+        std::fs::write(&path, format!("{}\n\n{{\"id\": 1}}\n", valid)).unwrap();
+
+        let error = read_records(&path).unwrap_err();
+        assert!(error.starts_with("Invalid record on line 3"), "{}", error);
+        let _ = std::fs::remove_file(&path);
+    }
+}
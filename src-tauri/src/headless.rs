@@ -0,0 +1,328 @@
+use serde_json::Value;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::agent::{AgentContext, EventSink, SystemClock};
+use crate::cleanup;
+use crate::commands;
+use crate::config;
+use crate::control;
+use crate::history;
+use crate::library;
+use crate::queue;
+use crate::retry;
+use crate::staging;
+use crate::store::FileStorage;
+
+/// Same identifier as the desktop app, so both use the same config and session
+const APP_IDENTIFIER: &str = "tv.ballcam.agent";
+
+/// How long shutdown waits for uploads in progress
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Name of the generated systemd user unit
+const SERVICE_NAME: &str = "ballcam-agent.service";
+
+/// Event sink for running without a window: events and notifications go to the log
+pub struct LogEvents;
+
+impl EventSink for LogEvents {
+    fn emit_value(&self, event: &str, payload: Value) {
+        tracing::debug!("Event {}: {}", event, payload);
+    }
+
+    fn notify(&self, title: &str, body: &str) {
+        tracing::info!("{}: {}", title, body);
+    }
+}
+
+/// Data directory of the desktop app (`~/.local/share/tv.ballcam.agent` on Linux)
+pub fn data_dir() -> Result<PathBuf, String> {
+    dirs::data_dir()
+        .map(|dir| dir.join(APP_IDENTIFIER))
+        .ok_or_else(|| "Could not find the user data directory".to_string())
+}
+
+/// Build an agent context on the desktop app's data directory that logs its events
+/// Must be called from within a tokio runtime
+pub fn build_context() -> Result<AgentContext, String> {
+    let events: Arc<dyn EventSink> = Arc::new(LogEvents);
+    let storage = Arc::new(FileStorage::new(data_dir()?, events.clone()));
+    let runtime = tokio::runtime::Handle::current();
+    Ok(AgentContext::new(events, storage, Arc::new(SystemClock), runtime).open_stores())
+}
+
+/// Log to stdout; under systemd the journal adds its own timestamps
+fn init_logging() {
+    let journald = std::env::var_os("JOURNAL_STREAM").is_some();
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::from_default_env()
+                .add_directive(tracing::Level::INFO.into()),
+        )
+        .with_ansi(!journald);
+
+    if journald {
+        builder.without_time().init();
+    } else {
+        builder.init();
+    }
+}
+
+/// Run the watcher and uploader without the desktop app until SIGTERM or Ctrl+C
+/// Returns the process exit code
+pub fn run() -> i32 {
+    init_logging();
+
+    let runtime = match tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            tracing::error!("Failed to start runtime: {}", e);
+            return 1;
+        }
+    };
+
+    match runtime.block_on(serve()) {
+        Ok(()) => 0,
+        Err(e) => {
+            tracing::error!("{}", e);
+            1
+        }
+    }
+}
+
+async fn serve() -> Result<(), String> {
+    tracing::info!("BallCam Agent starting in headless mode...");
+
+    // Two agents would upload every replay twice
+    if control::Client::connect().await.is_some() {
+        return Err("Another BallCam Agent is already running (desktop app or headless)".to_string());
+    }
+
+    let ctx = build_context()?;
+
+    let config = config::load_config(&ctx)?;
+    let folders = config.watched_folders();
+    if folders.is_empty() {
+        return Err(
+            "No replay folder configured, set replayFolder in config.json or run the desktop app once"
+                .to_string(),
+        );
+    }
+
+    if config::is_dry_run(&config) {
+        tracing::warn!("Dry run, replays are recorded as skipped instead of uploaded");
+    }
+
+    queue::restore_held(&ctx);
+    commands::login_with_configured_token(&ctx).await;
+    if config::load_session(&ctx)?.is_none() {
+        tracing::warn!(
+            "Not logged in, uploads are held until BALLCAM_API_TOKEN or apiToken is set"
+        );
+    }
+
+    if ctx.library().is_ok() {
+        library::spawn_scan(&ctx);
+    }
+    retry::start_auto_retry(&ctx);
+    history::start_retention(&ctx);
+    staging::spawn_prune(&ctx);
+    cleanup::spawn(&ctx);
+    control::start_server(&ctx);
+
+    ctx.watcher
+        .lock()
+        .unwrap()
+        .start(ctx.clone(), folders.iter().map(PathBuf::from).collect())?;
+
+    shutdown_signal().await;
+    tracing::info!("Shutting down...");
+
+    ctx.begin_shutdown();
+    ctx.watcher.lock().unwrap().stop()?;
+    drain(&ctx, DRAIN_TIMEOUT).await;
+
+    tracing::info!("BallCam Agent stopped");
+    Ok(())
+}
+
+/// Wait for SIGTERM (systemd stop) or Ctrl+C
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = sigterm.recv() => {}
+                    _ = tokio::signal::ctrl_c() => {}
+                }
+                return;
+            }
+            Err(e) => tracing::warn!("Failed to listen for SIGTERM: {}", e),
+        }
+    }
+
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Wait for uploads in progress to finish, up to `timeout`
+async fn drain(ctx: &AgentContext, timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+    let mut logged = false;
+
+    while ctx.active_uploads() > 0 {
+        if Instant::now() >= deadline {
+            tracing::warn!(
+                "Stopping with {} uploads still in progress",
+                ctx.active_uploads()
+            );
+            return;
+        }
+        if !logged {
+            tracing::info!("Waiting for {} uploads to finish", ctx.active_uploads());
+            logged = true;
+        }
+        tokio::time::sleep(Duration::from_millis(250)).await;
+    }
+}
+
+/// systemd user unit running this executable in headless mode
+pub fn service_unit(exe: &std::path::Path) -> String {
+    format!(
+        "[Unit]
+Description=BallCam Agent (headless replay uploader)
+
+[Service]
+Type=simple
+ExecStart={exe} --headless
+# Environment=BALLCAM_API_TOKEN=your-access-token
+Restart=on-failure
+RestartSec=10
+# Leave time for uploads in progress to finish
+TimeoutStopSec={stop_timeout}
+
+[Install]
+WantedBy=default.target
+",
+        exe = quote_exec_path(exe),
+        stop_timeout = DRAIN_TIMEOUT.as_secs() + 30,
+    )
+}
+
+/// Quote a path for `ExecStart`, escaping what systemd would otherwise interpret
+fn quote_exec_path(path: &std::path::Path) -> String {
+    let escaped = path
+        .display()
+        .to_string()
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%")
+        .replace('$', "$$");
+    format!("\"{}\"", escaped)
+}
+
+/// Write the systemd user unit for this executable and return its path
+pub fn install_service() -> Result<PathBuf, String> {
+    if !cfg!(target_os = "linux") {
+        return Err("Installing a service is only supported on Linux (systemd)".to_string());
+    }
+
+    let exe = std::env::current_exe()
+        .map_err(|e| format!("Failed to resolve the agent executable: {}", e))?;
+    let unit_dir = dirs::config_dir()
+        .ok_or("Could not find the user config directory")?
+        .join("systemd")
+        .join("user");
+    std::fs::create_dir_all(&unit_dir)
+        .map_err(|e| format!("Failed to create {}: {}", unit_dir.display(), e))?;
+
+    let unit_path = unit_dir.join(SERVICE_NAME);
+    std::fs::write(&unit_path, service_unit(&exe))
+        .map_err(|e| format!("Failed to write {}: {}", unit_path.display(), e))?;
+
+    Ok(unit_path)
+}
+
+/// Handle `--install-service` from the command line
+/// Returns the process exit code
+pub fn run_install_service() -> i32 {
+    match install_service() {
+        Ok(path) => {
+            println!("Installed {}", path.display());
+            println!(
+                "Start it with: systemctl --user daemon-reload && systemctl --user enable --now {}",
+                SERVICE_NAME
+            );
+            0
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::testing::TestAgent;
+    use std::path::Path;
+
+    #[test]
+    fn service_runs_the_executable_headless() {
+        let unit = service_unit(Path::new("/opt/BallCam Agent/ballcam-agent"));
+
+        assert!(unit.contains("ExecStart=\"/opt/BallCam Agent/ballcam-agent\" --headless\n"));
+        assert!(!unit.contains("network-online"));
+    }
+
+    #[test]
+    fn service_quotes_what_systemd_would_interpret() {
+        let unit = service_unit(Path::new("/home/me/100% \"agent\"\\$HOME/ballcam-agent"));
+
+        assert!(unit.contains(
+            "ExecStart=\"/home/me/100%% \\\"agent\\\"\\\\$$HOME/ballcam-agent\" --headless\n"
+        ));
+    }
+
+    #[test]
+    fn service_leaves_time_to_drain_uploads() {
+        let unit = service_unit(Path::new("/usr/bin/ballcam-agent"));
+
+        let stop_timeout = format!("TimeoutStopSec={}\n", DRAIN_TIMEOUT.as_secs() + 30);
+        assert!(unit.contains(&stop_timeout));
+    }
+
+    #[tokio::test]
+    async fn drain_waits_for_uploads_in_progress() {
+        let agent = TestAgent::new();
+        let upload = agent.ctx.track_upload();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            drop(upload);
+        });
+
+        let started = Instant::now();
+        drain(&agent.ctx, Duration::from_secs(10)).await;
+
+        assert_eq!(agent.ctx.active_uploads(), 0);
+        assert!(started.elapsed() >= Duration::from_millis(300));
+    }
+
+    #[tokio::test]
+    async fn drain_gives_up_after_the_timeout() {
+        let agent = TestAgent::new();
+        let _upload = agent.ctx.track_upload();
+
+        drain(&agent.ctx, Duration::from_millis(100)).await;
+
+        assert_eq!(agent.ctx.active_uploads(), 1);
+    }
+}
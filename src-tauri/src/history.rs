@@ -0,0 +1,677 @@
+use chrono::{DateTime, Utc};
+use rusqlite::types::Value;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::agent::{AgentContext, Clock};
+use crate::config;
+use crate::sql::{self, open_database, timestamp_millis, Filter, Order};
+use crate::types::{
+    HistoryImportResult, HistoryPage, HistoryQuery, HistorySortField, SortOrder, StoreRecovered,
+    UploadRecord, UploadStatus,
+};
+
+const HISTORY_DB_FILE: &str = "history.db";
+const HISTORY_BACKUP_FILE: &str = "history.bak.db";
+
+/// Number of records returned by `get_history`
+pub const RECENT_LIMIT: u32 = 100;
+/// Default and maximum page sizes for `query_history`
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 500;
+
+/// How often the retention policy is applied while the agent runs
+const RETENTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Schema migrations, applied in order and tracked with `PRAGMA user_version`
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE uploads (
+        id TEXT PRIMARY KEY,
+        filename TEXT NOT NULL,
+        file_path TEXT NOT NULL,
+        status TEXT NOT NULL,
+        replay_id TEXT,
+        visibility TEXT,
+        account_id TEXT,
+        file_size INTEGER,
+        created_ts INTEGER NOT NULL,
+        data TEXT NOT NULL
+    );
+    CREATE INDEX idx_uploads_created ON uploads (created_ts, id);
+    CREATE INDEX idx_uploads_status ON uploads (status, created_ts);
+    CREATE INDEX idx_uploads_account ON uploads (account_id, created_ts);
+    CREATE INDEX idx_uploads_filename ON uploads (filename, id);
+    CREATE INDEX idx_uploads_replay ON uploads (replay_id);",
+    "CREATE INDEX idx_uploads_path ON uploads (file_path, created_ts);",
+    "ALTER TABLE uploads ADD COLUMN replay_guid TEXT;
+    UPDATE uploads SET replay_guid = json_extract(data, '$.metadata.replayGuid');
+    CREATE INDEX idx_uploads_guid ON uploads (replay_guid, status);",
+];
+
+/// Upload history stored in an embedded SQLite database (history.db)
+///
+/// Indexed columns are used for filtering and sorting, the full record is kept as JSON.
+/// The store is the single owner of history: every mutation runs under its connection
+/// lock in one statement or transaction, so concurrent uploads, retries and deletions
+/// never overwrite each other.
+pub struct HistoryStore {
+    conn: Mutex<Connection>,
+    /// Time records with an invalid creation date are sorted by
+    clock: Arc<dyn Clock>,
+}
+
+/// Totals per status used for upload statistics
+#[derive(Debug, Clone, Default)]
+pub struct HistoryTotals {
+    pub completed: u32,
+    pub failed: u32,
+    pub completed_bytes: u64,
+}
+
+fn db_error(e: rusqlite::Error) -> String {
+    format!("History database error: {}", e)
+}
+
+impl HistoryStore {
+    /// Open (or create) the history database and apply pending migrations
+    pub fn open(path: &Path, clock: Arc<dyn Clock>) -> Result<Self, String> {
+        let conn = open_database(path, MIGRATIONS).map_err(|e| format!("History {}", e))?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+            clock,
+        })
+    }
+
+    /// Write a consistent copy of the database to `path`
+    pub fn backup_to(&self, path: &Path) -> Result<(), String> {
+        let tmp_path = path.with_extension("db.tmp");
+        let _ = std::fs::remove_file(&tmp_path);
+
+        let conn = self.conn.lock().unwrap();
+        conn.execute("VACUUM INTO ?1", [tmp_path.to_string_lossy()])
+            .map_err(db_error)?;
+        drop(conn);
+
+        std::fs::rename(&tmp_path, path)
+            .map_err(|e| format!("Failed to save history backup: {}", e))
+    }
+
+    /// Insert a record, replacing any record with the same ID
+    pub fn save(&self, record: &UploadRecord) -> Result<(), String> {
+        let conn = self.conn.lock().unwrap();
+        Self::save_with(&conn, record, self.clock.now())
+    }
+
+    /// Insert many records in a single transaction
+    pub fn save_all(&self, records: &[UploadRecord]) -> Result<(), String> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(db_error)?;
+        let now = self.clock.now();
+        for record in records {
+            Self::save_with(&tx, record, now)?;
+        }
+        tx.commit().map_err(db_error)
+    }
+
+    /// `now` stands in for a creation date that can't be read, so retention doesn't
+    /// delete the record right away
+    fn save_with(conn: &Connection, record: &UploadRecord, now: DateTime<Utc>) -> Result<(), String> {
+        let data = serde_json::to_string(record)
+            .map_err(|e| format!("Failed to serialize history record: {}", e))?;
+        let created_ts = timestamp_millis(&record.created_at).unwrap_or_else(|e| {
+            tracing::warn!("History record {}: {}", record.id, e);
+            now.timestamp_millis()
+        });
+
+        conn.execute(
+            "INSERT OR REPLACE INTO uploads
+                (id, filename, file_path, status, replay_id, visibility, account_id, file_size, created_ts, data, replay_guid)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params![
+                record.id,
+                record.filename,
+                record.file_path,
+                record.status.to_string(),
+                record.replay_id,
+                record.visibility.as_ref().map(|v| v.to_string()),
+                record.account_id,
+                record.file_size.map(|s| s as i64),
+                created_ts,
+                data,
+                record.metadata.as_ref().and_then(|m| m.replay_guid.as_deref()),
+            ],
+        )
+        .map_err(db_error)?;
+
+        Ok(())
+    }
+
+    /// Get a record by ID
+    pub fn get(&self, id: &str) -> Result<Option<UploadRecord>, String> {
+        let conn = self.conn.lock().unwrap();
+        let data: Option<String> = conn
+            .query_row("SELECT data FROM uploads WHERE id = ?1", [id], |row| row.get(0))
+            .optional()
+            .map_err(db_error)?;
+
+        data.map(|d| Self::parse_record(&d)).transpose()
+    }
+
+    /// Newest record for a replay file
+    pub fn latest_for_path(&self, file_path: &str) -> Result<Option<UploadRecord>, String> {
+        let conn = self.conn.lock().unwrap();
+        let data: Option<String> = conn
+            .query_row(
+                "SELECT data FROM uploads WHERE file_path = ?1 ORDER BY created_ts DESC, id DESC LIMIT 1",
+                [file_path],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(db_error)?;
+
+        data.map(|d| Self::parse_record(&d)).transpose()
+    }
+
+    /// Newest completed upload of a replay, by the GUID the game gave it
+    /// Finds the replay under another name or path too
+    pub fn completed_for_replay(&self, replay_guid: &str) -> Result<Option<UploadRecord>, String> {
+        let conn = self.conn.lock().unwrap();
+        let data: Option<String> = conn
+            .query_row(
+                "SELECT data FROM uploads WHERE replay_guid = ?1 AND status = 'completed'
+                 ORDER BY created_ts DESC, id DESC LIMIT 1",
+                [replay_guid],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(db_error)?;
+
+        data.map(|d| Self::parse_record(&d)).transpose()
+    }
+
+    /// Delete a record by ID, returning whether it existed
+    pub fn delete(&self, id: &str) -> Result<bool, String> {
+        let conn = self.conn.lock().unwrap();
+        let deleted = conn
+            .execute("DELETE FROM uploads WHERE id = ?1", [id])
+            .map_err(db_error)?;
+        Ok(deleted > 0)
+    }
+
+    /// Delete records by ID in one transaction, returning how many existed
+    pub fn delete_many(&self, ids: &[String]) -> Result<usize, String> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(db_error)?;
+        let mut deleted = 0;
+        for id in ids {
+            deleted += tx
+                .execute("DELETE FROM uploads WHERE id = ?1", [id])
+                .map_err(db_error)?;
+        }
+        tx.commit().map_err(db_error)?;
+        Ok(deleted)
+    }
+
+    /// Delete every record, returning how many were removed
+    pub fn clear(&self) -> Result<usize, String> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM uploads", []).map_err(db_error)
+    }
+
+    /// Most recent records, newest first
+    pub fn recent(&self, limit: u32) -> Result<Vec<UploadRecord>, String> {
+        let query = HistoryQuery {
+            limit: Some(limit),
+            ..Default::default()
+        };
+        Ok(self.query(&query)?.records)
+    }
+
+    /// Query records with filters, sorting and cursor pagination
+    pub fn query(&self, query: &HistoryQuery) -> Result<HistoryPage, String> {
+        let order = Order {
+            sort: match query.sort_by {
+                HistorySortField::CreatedAt => "created_ts",
+                HistorySortField::Filename => "filename",
+                HistorySortField::FileSize => "COALESCE(file_size, 0)",
+            },
+            key: "id",
+            descending: matches!(query.sort_order, SortOrder::Desc),
+        };
+        let limit = query
+            .limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE);
+
+        let conn = self.conn.lock().unwrap();
+        let page = sql::page(
+            &conn,
+            "uploads",
+            Self::filter(query)?,
+            &order,
+            query.cursor.as_deref(),
+            limit,
+            db_error,
+        )?;
+
+        Ok(HistoryPage {
+            records: page
+                .rows
+                .iter()
+                .map(|data| Self::parse_record(data))
+                .collect::<Result<_, _>>()?,
+            next_cursor: page.next_cursor,
+            total: page.total,
+        })
+    }
+
+    /// Every record matching the query's filters, in the query's sort order
+    pub fn query_all(&self, query: &HistoryQuery) -> Result<Vec<UploadRecord>, String> {
+        let mut query = HistoryQuery {
+            cursor: None,
+            limit: Some(MAX_PAGE_SIZE),
+            ..query.clone()
+        };
+
+        let mut records = Vec::new();
+        loop {
+            let page = self.query(&query)?;
+            records.extend(page.records);
+            match page.next_cursor {
+                Some(cursor) => query.cursor = Some(cursor),
+                None => return Ok(records),
+            }
+        }
+    }
+
+    /// Merge records from another history, in one transaction
+    /// Records whose ID or replay ID is already known are skipped
+    pub fn import(&self, records: &[UploadRecord]) -> Result<HistoryImportResult, String> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(db_error)?;
+        let mut result = HistoryImportResult::default();
+        let now = self.clock.now();
+
+        for record in records {
+            let exists: bool = tx
+                .query_row(
+                    "SELECT EXISTS (SELECT 1 FROM uploads WHERE id = ?1 OR (?2 IS NOT NULL AND replay_id = ?2))",
+                    params![record.id, record.replay_id],
+                    |row| row.get(0),
+                )
+                .map_err(db_error)?;
+
+            if exists {
+                result.skipped += 1;
+            } else {
+                Self::save_with(&tx, record, now)?;
+                result.imported += 1;
+            }
+        }
+
+        tx.commit().map_err(db_error)?;
+        Ok(result)
+    }
+
+    /// Totals per status over the whole history
+    pub fn totals(&self) -> Result<HistoryTotals, String> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare("SELECT status, COUNT(*), COALESCE(SUM(file_size), 0) FROM uploads GROUP BY status")
+            .map_err(db_error)?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, i64>(2)?,
+                ))
+            })
+            .map_err(db_error)?;
+
+        let mut totals = HistoryTotals::default();
+        for row in rows {
+            let (status, count, bytes) = row.map_err(db_error)?;
+            if status == UploadStatus::Completed.to_string() {
+                totals.completed = count as u32;
+                totals.completed_bytes = bytes as u64;
+            } else if status == UploadStatus::Failed.to_string() {
+                totals.failed = count as u32;
+            }
+        }
+
+        Ok(totals)
+    }
+
+    /// Delete records older than the given number of days at `now`, returning how many were removed
+    pub fn apply_retention(&self, max_age_days: u32, now: DateTime<Utc>) -> Result<usize, String> {
+        let cutoff = now - chrono::Duration::days(max_age_days as i64);
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "DELETE FROM uploads WHERE created_ts < ?1",
+            [cutoff.timestamp_millis()],
+        )
+        .map_err(db_error)
+    }
+
+    /// Invalid `from` and `to` dates are rejected
+    fn filter(query: &HistoryQuery) -> Result<Filter, String> {
+        let mut filter = Filter::default();
+
+        filter.add_in(
+            "status",
+            query.statuses.iter().map(|s| Value::Text(s.to_string())),
+        );
+        if let Some(from) = &query.from {
+            filter.add("created_ts >= ?", [Value::Integer(timestamp_millis(from)?)]);
+        }
+        if let Some(to) = &query.to {
+            filter.add("created_ts <= ?", [Value::Integer(timestamp_millis(to)?)]);
+        }
+        if let Some(filename) = query.filename.as_deref().filter(|f| !f.is_empty()) {
+            filter.add(
+                "filename LIKE ? ESCAPE '\\'",
+                [Value::Text(sql::contains_pattern(filename))],
+            );
+        }
+        if let Some(visibility) = &query.visibility {
+            filter.add("visibility = ?", [Value::Text(visibility.to_string())]);
+        }
+        if let Some(account_id) = &query.account_id {
+            filter.add("account_id = ?", [Value::Text(account_id.clone())]);
+        }
+
+        Ok(filter)
+    }
+
+    fn parse_record(data: &str) -> Result<UploadRecord, String> {
+        serde_json::from_str(data).map_err(|e| format!("Failed to parse history record: {}", e))
+    }
+}
+
+/// Open the history database in the data directory
+/// Recovers from a corrupt database, imports the legacy history.json on first run,
+/// applies the retention policy and refreshes the backup copy
+pub fn init(ctx: &AgentContext) -> Result<HistoryStore, String> {
+    let data_dir = ctx.storage.data_dir()?;
+    std::fs::create_dir_all(&data_dir)
+        .map_err(|e| format!("Failed to create data directory: {}", e))?;
+
+    let db_path = data_dir.join(HISTORY_DB_FILE);
+    let backup_path = data_dir.join(HISTORY_BACKUP_FILE);
+
+    let store = match HistoryStore::open(&db_path, ctx.clock.clone()) {
+        Ok(store) => store,
+        Err(e) => {
+            tracing::error!("Failed to open history database: {}", e);
+            recover(ctx, &db_path, &backup_path)?
+        }
+    };
+
+    match config::load_legacy_history(ctx) {
+        Ok(legacy) if !legacy.records.is_empty() => {
+            store.save_all(&legacy.records)?;
+            config::clear_legacy_history(ctx)?;
+            tracing::info!("Migrated {} records from history.json", legacy.records.len());
+        }
+        Ok(_) => {}
+        Err(e) => tracing::error!("Failed to read legacy history: {}", e),
+    }
+
+    apply_retention(ctx, &store)?;
+
+    if let Err(e) = store.backup_to(&backup_path) {
+        tracing::warn!("Failed to back up history database: {}", e);
+    }
+
+    Ok(store)
+}
+
+/// Move a corrupt database aside and reopen from the backup, or start empty
+fn recover(ctx: &AgentContext, db_path: &Path, backup_path: &Path) -> Result<HistoryStore, String> {
+    let suffix = format!("corrupt-{}", ctx.now().timestamp());
+    for extension in ["", "-wal", "-shm"] {
+        let path = PathBuf::from(format!("{}{}", db_path.display(), extension));
+        if path.exists() {
+            let _ = std::fs::rename(&path, format!("{}.{}", path.display(), suffix));
+        }
+    }
+
+    let restored = if backup_path.exists() && std::fs::copy(backup_path, db_path).is_ok() {
+        HistoryStore::open(db_path, ctx.clock.clone()).ok()
+    } else {
+        None
+    };
+
+    let (store, source) = match restored {
+        Some(store) => (store, "backup"),
+        None => {
+            let _ = std::fs::remove_file(db_path);
+            (HistoryStore::open(db_path, ctx.clock.clone())?, "defaults")
+        }
+    };
+
+    tracing::warn!("Recovered history database from {}", source);
+    ctx.emit(
+        "store_recovered",
+        &StoreRecovered {
+            store: HISTORY_DB_FILE.to_string(),
+            source: source.to_string(),
+        },
+    );
+
+    Ok(store)
+}
+
+/// Get the history store of the agent
+pub fn store(ctx: &AgentContext) -> Result<&HistoryStore, String> {
+    ctx.history()
+}
+
+/// Delete records older than the configured retention, returning how many were removed
+pub fn apply_retention(ctx: &AgentContext, store: &HistoryStore) -> Result<usize, String> {
+    let Some(days) = config::load_config(ctx)?.history_retention_days else {
+        return Ok(0);
+    };
+    let removed = store.apply_retention(days, ctx.now())?;
+    if removed > 0 {
+        tracing::info!("Removed {} history records older than {} days", removed, days);
+    }
+    Ok(removed)
+}
+
+/// Apply the retention policy periodically, so an agent running for months keeps pruning
+/// The policy is read from the config every time, changes apply without a restart
+pub fn start_retention(ctx: &AgentContext) {
+    let task_ctx = ctx.clone();
+    ctx.spawn(async move {
+        loop {
+            tokio::time::sleep(RETENTION_INTERVAL).await;
+            let pruned = store(&task_ctx).and_then(|store| apply_retention(&task_ctx, store));
+            if let Err(e) = pruned {
+                tracing::warn!("History retention failed: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::testing::TestAgent;
+    use std::sync::{Arc, Barrier};
+
+    fn record(id: &str, status: &str) -> UploadRecord {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "filename": format!("{}.replay", id),
+            "filePath": format!("/replays/{}.replay", id),
+            "status": status,
+            "attempts": 1,
+            "createdAt": "2024-01-15T20:30:12Z",
+            "fileSize": 1000,
+        }))
+        .unwrap()
+    }
+
+    /// Writers in separate connections, like the app and the command line sharing history.db
+    #[test]
+    fn parallel_writers_share_one_database() {
+        const WRITERS: usize = 8;
+        const RECORDS: usize = 50;
+
+        let agent = TestAgent::new();
+        let path = agent.dir.join(HISTORY_DB_FILE);
+        let start = Arc::new(Barrier::new(WRITERS + 1));
+
+        let writers: Vec<_> = (0..WRITERS)
+            .map(|writer| {
+                let path = path.clone();
+                let clock = agent.clock.clone();
+                let start = start.clone();
+                std::thread::spawn(move || {
+                    // Opened together too, racing on creating the schema
+                    start.wait();
+                    let store = HistoryStore::open(&path, clock).unwrap();
+                    for n in 0..RECORDS {
+                        let status = if n % 5 == 0 { "failed" } else { "completed" };
+                        store.save(&record(&format!("{}-{}", writer, n), status)).unwrap();
+                        if n % 10 == 0 {
+                            let batch: Vec<UploadRecord> = (0..5)
+                                .map(|b| record(&format!("{}-{}-batch-{}", writer, n, b), "completed"))
+                                .collect();
+                            store.save_all(&batch).unwrap();
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        let reader = {
+            let path = path.clone();
+            let clock = agent.clock.clone();
+            let start = start.clone();
+            std::thread::spawn(move || {
+                start.wait();
+                let store = HistoryStore::open(&path, clock).unwrap();
+                for _ in 0..100 {
+                    store.totals().unwrap();
+                    store.recent(20).unwrap();
+                }
+            })
+        };
+
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        reader.join().unwrap();
+
+        let store = HistoryStore::open(&path, agent.clock.clone()).unwrap();
+        let totals = store.totals().unwrap();
+        let failed = WRITERS * RECORDS / 5;
+        let batches = WRITERS * (RECORDS / 10) * 5;
+        assert_eq!(totals.failed as usize, failed);
+        assert_eq!(totals.completed as usize, WRITERS * RECORDS - failed + batches);
+        assert_eq!(totals.completed_bytes, totals.completed as u64 * 1000);
+        assert!(store.get("7-49").unwrap().is_some());
+    }
+
+    #[test]
+    fn finds_uploads_by_replay_guid_including_older_records() {
+        let agent = TestAgent::new();
+        let path = agent.dir.join(HISTORY_DB_FILE);
+
+        // Saved before the GUID had its own column
+        let mut old = record("old", "completed");
+        old.metadata = Some(crate::types::ReplayMetadata {
+            replay_guid: Some("OLD-GUID".to_string()),
+            ..Default::default()
+        });
+        {
+            let conn = open_database(&path, &MIGRATIONS[..2]).unwrap();
+            conn.execute(
+                "INSERT INTO uploads (id, filename, file_path, status, created_ts, data)
+                 VALUES ('old', 'old.replay', '/replays/old.replay', 'completed', 0, ?1)",
+                [serde_json::to_string(&old).unwrap()],
+            )
+            .unwrap();
+        }
+
+        let store = HistoryStore::open(&path, agent.clock.clone()).unwrap();
+        assert_eq!(store.completed_for_replay("OLD-GUID").unwrap().unwrap().id, "old");
+
+        let mut failed = record("failed", "failed");
+        failed.metadata = Some(crate::types::ReplayMetadata {
+            replay_guid: Some("NEW-GUID".to_string()),
+            ..Default::default()
+        });
+        store.save(&failed).unwrap();
+        assert!(store.completed_for_replay("NEW-GUID").unwrap().is_none());
+
+        let mut uploaded = failed.clone();
+        uploaded.id = "uploaded".to_string();
+        uploaded.status = UploadStatus::Completed;
+        store.save(&uploaded).unwrap();
+        assert_eq!(store.completed_for_replay("NEW-GUID").unwrap().unwrap().id, "uploaded");
+    }
+
+    #[test]
+    fn retention_follows_the_agent_clock() {
+        let agent = TestAgent::new().with_config(&crate::types::AppConfig {
+            history_retention_days: Some(30),
+            ..Default::default()
+        });
+        let store = store(&agent.ctx).unwrap();
+        let created = |id: &str, created_at: &str| {
+            let mut record = record(id, "completed");
+            record.created_at = created_at.to_string();
+            store.save(&record).unwrap();
+        };
+        created("old", "2024-01-15T20:00:00Z");
+        created("recent", "2024-02-20T20:00:00Z");
+        // Kept from when it was saved, not deleted as if it were from 1970
+        created("unreadable", "yesterday");
+
+        assert_eq!(apply_retention(&agent.ctx, store).unwrap(), 1);
+        assert!(store.get("old").unwrap().is_none());
+        assert!(store.get("recent").unwrap().is_some());
+        assert!(store.get("unreadable").unwrap().is_some());
+
+        agent.clock.advance(chrono::Duration::days(25));
+        assert_eq!(apply_retention(&agent.ctx, store).unwrap(), 1);
+        assert!(store.get("recent").unwrap().is_none());
+
+        agent.clock.advance(chrono::Duration::days(10));
+        assert_eq!(apply_retention(&agent.ctx, store).unwrap(), 1);
+        assert!(store.get("unreadable").unwrap().is_none());
+    }
+
+    #[test]
+    fn queries_reject_invalid_dates() {
+        let agent = TestAgent::new();
+        let store = store(&agent.ctx).unwrap();
+        store.save(&record("a", "completed")).unwrap();
+
+        for query in [
+            HistoryQuery {
+                from: Some("last week".to_string()),
+                ..Default::default()
+            },
+            HistoryQuery {
+                to: Some("2024-13-01".to_string()),
+                ..Default::default()
+            },
+        ] {
+            assert!(store.query(&query).unwrap_err().starts_with("Invalid date"));
+        }
+
+        let query = HistoryQuery {
+            from: Some("2024-01-15T00:00:00Z".to_string()),
+            to: Some("2024-01-16T00:00:00+01:00".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(store.query(&query).unwrap().total, 1);
+    }
+}
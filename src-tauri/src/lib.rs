@@ -1,20 +1,42 @@
+pub mod agent;
+pub mod archive;
+pub mod archiving;
+pub mod batch;
+pub mod cleanup;
+pub mod cli;
 pub mod commands;
 pub mod config;
+pub mod control;
+pub mod desktop;
+pub mod export;
+pub mod headless;
+pub mod history;
+pub mod library;
+pub mod migrations;
+pub mod oauth;
+pub mod qr;
+pub mod queue;
+pub mod replay;
+pub mod retry;
+pub mod sql;
+pub mod staging;
+pub mod stats;
+pub mod store;
 pub mod types;
 pub mod uploader;
 pub mod watcher;
 
-use std::sync::Mutex;
+use agent::AgentContext;
 use tauri::Manager;
-use watcher::FileWatcher;
-
-// Global state for the file watcher
-pub struct AppState {
-    pub watcher: Mutex<FileWatcher>,
-}
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    run_with(Vec::new())
+}
+
+/// Run the app, uploading the replay files among `open` (`cli::Cli::open`)
+/// Links among them are followed by the deep link plugin, see `desktop::track_opened_files`.
+pub fn run_with(open: Vec<String>) {
     tracing_subscriber::fmt()
         .with_env_filter(
             tracing_subscriber::EnvFilter::from_default_env()
@@ -22,8 +44,20 @@ pub fn run() {
         )
         .init();
 
-    tauri::Builder::default()
-        .plugin(tauri_plugin_store::Builder::new().build())
+    let mut builder = tauri::Builder::default();
+
+    // Registered first: a second launch hands its arguments to this instance and exits
+    #[cfg(desktop)]
+    {
+        builder = builder.plugin(tauri_plugin_single_instance::init(|app, argv, cwd| {
+            desktop::show_main_window(app);
+            let args = argv.get(1..).unwrap_or_default();
+            desktop::open_replays(app, desktop::replay_args(args, std::path::Path::new(&cwd)));
+        }));
+    }
+
+    builder
+        .plugin(tauri_plugin_deep_link::init())
         .plugin(tauri_plugin_autostart::init(
             tauri_plugin_autostart::MacosLauncher::LaunchAgent,
             Some(vec!["--minimized"]),
@@ -34,10 +68,17 @@ pub fn run() {
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_updater::Builder::new().build())
         .plugin(tauri_plugin_process::init())
-        .manage(AppState {
-            watcher: Mutex::new(FileWatcher::new()),
-        })
-        .setup(|app| {
+        .setup(move |app| {
+            let cwd = std::env::current_dir().unwrap_or_default();
+            let replays = desktop::replay_args(&open, &cwd);
+
+            // Two agents would upload every replay twice
+            #[cfg(desktop)]
+            if desktop::forward_to_running_agent(app.handle(), &replays) {
+                tracing::warn!("Another BallCam Agent is already running, exiting");
+                std::process::exit(0);
+            }
+
             tracing::info!("BallCam Agent starting...");
 
             // Set up tray icon
@@ -63,16 +104,9 @@ pub fn run() {
                     .on_menu_event(|app, event| {
                         match event.id.as_ref() {
                             "pause" => {
-                                if let Some(state) = app.try_state::<AppState>() {
-                                    let watcher = state.watcher.lock().unwrap();
-                                    let is_paused = watcher.get_state().is_paused;
-                                    if is_paused {
-                                        let _ = watcher.resume();
-                                        let _ = app.emit("watcher_resumed", ());
-                                    } else {
-                                        let _ = watcher.pause();
-                                        let _ = app.emit("watcher_paused", ());
-                                    }
+                                if let Some(ctx) = app.try_state::<AgentContext>() {
+                                    let is_paused = ctx.watcher.lock().unwrap().get_state().is_paused;
+                                    let _ = watcher::set_paused(&ctx, !is_paused);
                                 }
                             }
                             "settings" => {
@@ -90,8 +124,8 @@ pub fn run() {
                                 }
                             }
                             "quit" => {
-                                if let Some(state) = app.try_state::<AppState>() {
-                                    let _ = state.watcher.lock().unwrap().stop();
+                                if let Some(ctx) = app.try_state::<AgentContext>() {
+                                    let _ = ctx.watcher.lock().unwrap().stop();
                                 }
                                 app.exit(0);
                             }
@@ -109,18 +143,51 @@ pub fn run() {
                     .build(app)?;
             }
 
+            // Open the stores and hand the core its Tauri adapters
+            let ctx = desktop::build_context(app.handle())?;
+            app.manage(ctx.clone());
+            app.manage(desktop::PendingHistoryItem::default());
+            desktop::track_login_state(app.handle());
+
+            // Bring the replay library up to date with the watched folders
+            if ctx.library().is_ok() {
+                library::spawn_scan(&ctx);
+            }
+
+            // Hold the uploads that were waiting for a login when the agent exited
+            queue::restore_held(&ctx);
+
+            // Login with a configured access token (headless setups)
+            let token_ctx = ctx.clone();
+            ctx.spawn(async move {
+                commands::login_with_configured_token(&token_ctx).await;
+            });
+
+            // Retry failed uploads in the background when enabled
+            retry::start_auto_retry(&ctx);
+
+            // Keep pruning old history records while the app runs
+            history::start_retention(&ctx);
+
+            // Drop staged replays that expired while the agent was stopped
+            staging::spawn_prune(&ctx);
+
+            // Apply the replay folder cleanup policy
+            cleanup::spawn(&ctx);
+
+            // Let the command line talk to this instance
+            control::start_server(&ctx);
+
             // Start watcher if setup is complete
-            let app_handle = app.handle().clone();
-            if let Ok(cfg) = config::load_config(&app_handle) {
-                if cfg.setup_complete && !cfg.replay_folder.is_empty() {
-                    let state = app.state::<AppState>();
-                    let mut watcher = state.watcher.lock().unwrap();
-                    if let Err(e) = watcher.start(app_handle.clone(), std::path::PathBuf::from(&cfg.replay_folder)) {
-                        tracing::error!("Failed to start watcher: {}", e);
-                    }
+            if let Ok(cfg) = config::load_config(&ctx) {
+                if let Err(e) = watcher::restart(&ctx, &cfg) {
+                    tracing::error!("Failed to start watcher: {}", e);
                 }
             }
 
+            // Upload replays and follow links the app was opened with
+            desktop::track_opened_files(app.handle(), replays);
+
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
@@ -129,6 +196,12 @@ pub fn run() {
             commands::get_session,
             commands::login,
             commands::logout,
+            commands::login_with_token,
+            commands::login_with_browser,
+            // Account commands
+            commands::list_accounts,
+            commands::switch_account,
+            commands::remove_account,
             // Device flow commands
             commands::request_device_code,
             commands::poll_device_token,
@@ -144,7 +217,21 @@ pub fn run() {
             // Upload commands
             commands::upload_replay,
             commands::get_history,
+            commands::query_history,
+            commands::export_history,
+            commands::import_history,
             commands::retry_upload,
+            commands::retry_failed_uploads,
+            commands::retry_uploads,
+            commands::delete_history_records,
+            commands::clear_history,
+            commands::get_upload_queue,
+            // Library commands
+            commands::query_library,
+            commands::rescan_library,
+            commands::upload_library_replays,
+            // Batch upload
+            commands::upload_batch,
             // Folder detection
             commands::detect_replay_folder,
             commands::detect_all_replay_folders,
@@ -153,6 +240,9 @@ pub fn run() {
             commands::open_folder,
             // Upload statistics
             commands::get_upload_stats,
+            commands::get_upload_report,
+            // Opened links
+            commands::take_pending_history_item,
         ])
         .on_window_event(|window, event| {
             if let tauri::WindowEvent::CloseRequested { api, .. } = event {
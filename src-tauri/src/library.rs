@@ -0,0 +1,562 @@
+use rusqlite::types::Value;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+use crate::agent::AgentContext;
+use crate::config;
+use crate::history;
+use crate::replay;
+use crate::sql::{self, open_database, timestamp_millis, Filter, Order};
+use crate::types::{
+    HistoryQuery, LibraryEntry, LibraryPage, LibraryQuery, LibraryScanResult, LibraryStatus,
+    UploadRecord, UploadStatus,
+};
+
+const LIBRARY_DB_FILE: &str = "library.db";
+
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 500;
+
+/// Schema migrations, applied in order and tracked with `PRAGMA user_version`
+const MIGRATIONS: &[&str] = &["CREATE TABLE replays (
+        path TEXT PRIMARY KEY,
+        folder TEXT NOT NULL,
+        filename TEXT NOT NULL,
+        map_name TEXT,
+        status TEXT NOT NULL,
+        file_size INTEGER NOT NULL,
+        modified_ts INTEGER NOT NULL,
+        data TEXT NOT NULL
+    );
+    CREATE INDEX idx_replays_modified ON replays (modified_ts, path);
+    CREATE INDEX idx_replays_status ON replays (status, modified_ts);
+    CREATE INDEX idx_replays_folder ON replays (folder, modified_ts);"];
+
+/// Index of every replay file in the watched folders (library.db)
+///
+/// Unlike history, which only knows uploaded files, the library lists everything on disk
+/// with its upload status. It's derived data: a corrupt index is simply rebuilt.
+pub struct LibraryStore {
+    conn: Mutex<Connection>,
+}
+
+fn db_error(e: rusqlite::Error) -> String {
+    format!("Library database error: {}", e)
+}
+
+/// Size and modification time, used to skip unchanged files when rescanning
+type Fingerprint = (u64, i64);
+
+impl LibraryStore {
+    /// Open (or create) the library database and apply pending migrations
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let conn = open_database(path, MIGRATIONS).map_err(|e| format!("Library {}", e))?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Insert or replace an entry
+    pub fn upsert(&self, entry: &LibraryEntry) -> Result<(), String> {
+        let conn = self.conn.lock().unwrap();
+        Self::upsert_with(&conn, entry)
+    }
+
+    fn upsert_with(conn: &Connection, entry: &LibraryEntry) -> Result<(), String> {
+        let data = serde_json::to_string(entry)
+            .map_err(|e| format!("Failed to serialize library entry: {}", e))?;
+
+        conn.execute(
+            "INSERT OR REPLACE INTO replays
+                (path, folder, filename, map_name, status, file_size, modified_ts, data)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                entry.path,
+                entry.folder,
+                entry.filename,
+                entry.metadata.as_ref().and_then(|m| m.map_name.clone()),
+                entry.status.to_string(),
+                entry.file_size as i64,
+                // Written from file times, always valid
+                timestamp_millis(&entry.modified_at).unwrap_or_default(),
+                data,
+            ],
+        )
+        .map_err(db_error)?;
+
+        Ok(())
+    }
+
+    /// Get an entry by file path
+    pub fn get(&self, path: &str) -> Result<Option<LibraryEntry>, String> {
+        let conn = self.conn.lock().unwrap();
+        Self::get_with(&conn, path)
+    }
+
+    fn get_with(conn: &Connection, path: &str) -> Result<Option<LibraryEntry>, String> {
+        let data: Option<String> = conn
+            .query_row("SELECT data FROM replays WHERE path = ?1", [path], |row| {
+                row.get(0)
+            })
+            .optional()
+            .map_err(db_error)?;
+
+        data.map(|d| Self::parse_entry(&d)).transpose()
+    }
+
+    /// Modify an entry under the store lock, returning the updated entry
+    pub fn update<F>(&self, path: &str, f: F) -> Result<Option<LibraryEntry>, String>
+    where
+        F: FnOnce(&mut LibraryEntry),
+    {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(db_error)?;
+        let entry = Self::update_with(&tx, path, f)?;
+        tx.commit().map_err(db_error)?;
+        Ok(entry)
+    }
+
+    fn update_with<F>(conn: &Connection, path: &str, f: F) -> Result<Option<LibraryEntry>, String>
+    where
+        F: FnOnce(&mut LibraryEntry),
+    {
+        let Some(mut entry) = Self::get_with(conn, path)? else {
+            return Ok(None);
+        };
+        f(&mut entry);
+        Self::upsert_with(conn, &entry)?;
+        Ok(Some(entry))
+    }
+
+    /// Remove an entry, returning whether it existed
+    pub fn remove(&self, path: &str) -> Result<bool, String> {
+        let conn = self.conn.lock().unwrap();
+        let removed = conn
+            .execute("DELETE FROM replays WHERE path = ?1", [path])
+            .map_err(db_error)?;
+        Ok(removed > 0)
+    }
+
+    /// Apply the outcome of a scan in one transaction: refresh the upload status of
+    /// unchanged files, store changed ones and drop every entry whose path isn't in `keep`
+    /// Returns how many entries were removed
+    fn apply_scan(
+        &self,
+        statuses: &[(String, Option<&UploadRecord>)],
+        changed: &[LibraryEntry],
+        keep: &HashSet<String>,
+    ) -> Result<usize, String> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(db_error)?;
+
+        for (path, record) in statuses {
+            Self::update_with(&tx, path, |entry| {
+                entry.status = record.map(status_for).unwrap_or_default();
+                entry.record_id = record.map(|r| r.id.clone());
+                entry.replay_url = record.and_then(|r| r.replay_url.clone());
+            })?;
+        }
+        for entry in changed {
+            Self::upsert_with(&tx, entry)?;
+        }
+
+        let stale: Vec<String> = {
+            let mut stmt = tx.prepare("SELECT path FROM replays").map_err(db_error)?;
+            let paths = stmt
+                .query_map([], |row| row.get::<_, String>(0))
+                .map_err(db_error)?
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(db_error)?;
+            paths.into_iter().filter(|p| !keep.contains(p)).collect()
+        };
+        for path in &stale {
+            tx.execute("DELETE FROM replays WHERE path = ?1", [path])
+                .map_err(db_error)?;
+        }
+
+        tx.commit().map_err(db_error)?;
+        Ok(stale.len())
+    }
+
+    /// Size and modification time of every indexed file
+    fn fingerprints(&self) -> Result<HashMap<String, Fingerprint>, String> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare("SELECT path, file_size, modified_ts FROM replays")
+            .map_err(db_error)?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    (row.get::<_, i64>(1)? as u64, row.get::<_, i64>(2)?),
+                ))
+            })
+            .map_err(db_error)?
+            .collect::<Result<HashMap<_, _>, _>>()
+            .map_err(db_error)?;
+        Ok(rows)
+    }
+
+    /// Query entries with filters and cursor pagination, newest files first
+    pub fn query(&self, query: &LibraryQuery) -> Result<LibraryPage, String> {
+        let limit = query
+            .limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE);
+
+        let mut filter = Filter::default();
+        filter.add_in(
+            "status",
+            query.statuses.iter().map(|s| Value::Text(s.to_string())),
+        );
+        if let Some(folder) = &query.folder {
+            filter.add("folder = ?", [Value::Text(folder.clone())]);
+        }
+        if let Some(search) = query.search.as_deref().filter(|s| !s.is_empty()) {
+            let pattern = sql::contains_pattern(search);
+            filter.add(
+                "(filename LIKE ? ESCAPE '\\' OR map_name LIKE ? ESCAPE '\\')",
+                [Value::Text(pattern.clone()), Value::Text(pattern)],
+            );
+        }
+        if let Some(from) = &query.from {
+            filter.add("modified_ts >= ?", [Value::Integer(timestamp_millis(from)?)]);
+        }
+        if let Some(to) = &query.to {
+            filter.add("modified_ts <= ?", [Value::Integer(timestamp_millis(to)?)]);
+        }
+
+        let order = Order {
+            sort: "modified_ts",
+            key: "path",
+            descending: true,
+        };
+        let conn = self.conn.lock().unwrap();
+        let page = sql::page(
+            &conn,
+            "replays",
+            filter,
+            &order,
+            query.cursor.as_deref(),
+            limit,
+            db_error,
+        )?;
+
+        Ok(LibraryPage {
+            entries: page
+                .rows
+                .iter()
+                .map(|data| Self::parse_entry(data))
+                .collect::<Result<_, _>>()?,
+            next_cursor: page.next_cursor,
+            total: page.total,
+        })
+    }
+
+    fn parse_entry(data: &str) -> Result<LibraryEntry, String> {
+        serde_json::from_str(data).map_err(|e| format!("Failed to parse library entry: {}", e))
+    }
+}
+
+/// Library status of a file from its latest history record
+pub fn status_for(record: &UploadRecord) -> LibraryStatus {
+    match record.status {
+        UploadStatus::Completed => LibraryStatus::Uploaded,
+        UploadStatus::Failed => LibraryStatus::Failed,
+        UploadStatus::Skipped => LibraryStatus::Skipped,
+        UploadStatus::Pending | UploadStatus::Uploading | UploadStatus::Processing => {
+            LibraryStatus::Pending
+        }
+    }
+}
+
+/// Whether a path has the `.replay` extension
+pub fn is_replay(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("replay"))
+}
+
+/// Read a replay file's info and metadata into a new entry
+fn read_entry(path: &Path, record: Option<&UploadRecord>) -> Option<LibraryEntry> {
+    let file = std::fs::metadata(path).ok().filter(|m| m.is_file())?;
+    let modified = file
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0);
+    let modified_at = chrono::DateTime::from_timestamp_millis(modified)
+        .unwrap_or_default()
+        .to_rfc3339();
+
+    Some(LibraryEntry {
+        path: path.to_string_lossy().to_string(),
+        filename: path.file_name()?.to_string_lossy().to_string(),
+        folder: path
+            .parent()
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        file_size: file.len(),
+        modified_at,
+        status: record.map(status_for).unwrap_or_default(),
+        record_id: record.map(|r| r.id.clone()),
+        replay_url: record.and_then(|r| r.replay_url.clone()),
+        metadata: replay::parse_header(path).ok(),
+    })
+}
+
+/// Open the library database in the data directory
+/// A corrupt index is deleted and rebuilt by the next scan
+pub fn init(ctx: &AgentContext) -> Result<LibraryStore, String> {
+    let data_dir = ctx.storage.data_dir()?;
+    std::fs::create_dir_all(&data_dir)
+        .map_err(|e| format!("Failed to create data directory: {}", e))?;
+
+    let db_path = data_dir.join(LIBRARY_DB_FILE);
+    match LibraryStore::open(&db_path) {
+        Ok(store) => Ok(store),
+        Err(e) => {
+            tracing::error!("Failed to open replay library, rebuilding it: {}", e);
+            for extension in ["", "-wal", "-shm"] {
+                let _ = std::fs::remove_file(format!("{}{}", db_path.display(), extension));
+            }
+            LibraryStore::open(&db_path)
+        }
+    }
+}
+
+/// Get the library store of the agent
+pub fn store(ctx: &AgentContext) -> Result<&LibraryStore, String> {
+    ctx.library()
+}
+
+/// Index every replay in the watched folders and drop entries whose file is gone
+/// Unchanged files keep their parsed metadata; upload statuses are refreshed from history
+pub fn scan(ctx: &AgentContext) -> Result<LibraryScanResult, String> {
+    let library = store(ctx)?;
+    let folders = config::load_config(ctx)?.watched_folders();
+
+    // Latest history record per file (records come newest first)
+    let mut records: HashMap<String, UploadRecord> = HashMap::new();
+    for record in history::store(ctx)?.query_all(&HistoryQuery::default())? {
+        records.entry(record.file_path.clone()).or_insert(record);
+    }
+
+    let known = library.fingerprints()?;
+    let mut seen = HashSet::new();
+    let mut statuses = Vec::new();
+    let mut changed = Vec::new();
+    let mut result = LibraryScanResult::default();
+
+    for folder in &folders {
+        let entries = match std::fs::read_dir(folder) {
+            Ok(entries) => entries,
+            Err(e) => {
+                tracing::warn!("Failed to scan {}: {}", folder, e);
+                continue;
+            }
+        };
+
+        for dir_entry in entries.flatten() {
+            let path = dir_entry.path();
+            if !is_replay(&path) {
+                continue;
+            }
+            let path_str = path.to_string_lossy().to_string();
+            let record = records.get(&path_str);
+
+            let Ok(file) = dir_entry.metadata() else {
+                continue;
+            };
+            let modified = file
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_millis() as i64)
+                .unwrap_or(0);
+
+            result.files += 1;
+            seen.insert(path_str.clone());
+
+            if known.get(&path_str) == Some(&(file.len(), modified)) {
+                statuses.push((path_str, record));
+            } else if let Some(entry) = read_entry(&path, record) {
+                changed.push(entry);
+            }
+        }
+    }
+
+    // Headers are parsed above, the database is only locked to write the results
+    result.indexed = changed.len() as u32;
+    result.removed = library.apply_scan(&statuses, &changed, &seen)? as u32;
+
+    tracing::info!(
+        "Library scan: {} replays, {} indexed, {} removed",
+        result.files,
+        result.indexed,
+        result.removed
+    );
+    ctx.emit("library_updated", &result);
+
+    Ok(result)
+}
+
+/// Rescan the library in the background
+pub fn spawn_scan(ctx: &AgentContext) {
+    let task_ctx = ctx.clone();
+    ctx.spawn_blocking(move || {
+        if let Err(e) = scan(&task_ctx) {
+            tracing::error!("Library scan failed: {}", e);
+        }
+    });
+}
+
+/// Add or refresh a single file, e.g. when the watcher detects it
+pub fn index_file(ctx: &AgentContext, path: &Path) -> Result<Option<LibraryEntry>, String> {
+    let path_str = path.to_string_lossy().to_string();
+    let record = history::store(ctx)?.latest_for_path(&path_str)?;
+    let Some(entry) = read_entry(path, record.as_ref()) else {
+        return Ok(None);
+    };
+
+    store(ctx)?.upsert(&entry)?;
+    ctx.emit("library_entry_updated", &entry);
+    Ok(Some(entry))
+}
+
+/// Drop a file that was deleted from a watched folder
+pub fn remove_file(ctx: &AgentContext, path: &Path) {
+    let path_str = path.to_string_lossy();
+    if let Ok(library) = store(ctx) {
+        if library.remove(&path_str).unwrap_or(false) {
+            ctx.emit("library_entry_removed", path_str.as_ref());
+        }
+    }
+}
+
+/// Reflect an upload's progress in the library
+/// Files outside the library (e.g. uploaded manually from elsewhere) are ignored
+pub fn track_upload(ctx: &AgentContext, record: &UploadRecord) {
+    let Ok(library) = store(ctx) else {
+        return;
+    };
+
+    let updated = library.update(&record.file_path, |entry| {
+        entry.status = status_for(record);
+        entry.record_id = Some(record.id.clone());
+        entry.replay_url = record.replay_url.clone();
+    });
+
+    match updated {
+        Ok(Some(entry)) => {
+            ctx.emit("library_entry_updated", &entry);
+        }
+        Ok(None) => {}
+        Err(e) => tracing::warn!("Failed to update library entry: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::testing::TestAgent;
+    use crate::types::{AppConfig, LibraryStatus};
+    use std::path::PathBuf;
+
+    struct TestLibrary {
+        agent: TestAgent,
+        folder: PathBuf,
+    }
+
+    fn library() -> TestLibrary {
+        let agent = TestAgent::new().with_stores();
+        let folder = agent.dir.join("Demos");
+        std::fs::create_dir_all(&folder).unwrap();
+        let config = AppConfig {
+            replay_folder: folder.to_string_lossy().to_string(),
+            setup_complete: true,
+            ..Default::default()
+        };
+        TestLibrary {
+            agent: agent.with_config(&config),
+            folder,
+        }
+    }
+
+    fn completed(path: &Path) -> UploadRecord {
+        serde_json::from_value(serde_json::json!({
+            "id": "uploaded",
+            "filename": "b.replay",
+            "filePath": path.to_string_lossy(),
+            "status": "completed",
+            "replayUrl": "https://ballcam.tv/replays/1",
+            "attempts": 1,
+            "createdAt": "2024-01-15T20:00:00Z",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn scan_indexes_refreshes_and_drops_files() {
+        let test = library();
+        for name in ["a.replay", "b.replay", "c.replay", "notes.txt"] {
+            std::fs::write(test.folder.join(name), name).unwrap();
+        }
+
+        let first = scan(&test.agent.ctx).unwrap();
+        assert_eq!((first.files, first.indexed, first.removed), (3, 3, 0));
+
+        // Unchanged files only get their upload status refreshed
+        let uploaded = test.folder.join("b.replay");
+        history::store(&test.agent.ctx).unwrap().save(&completed(&uploaded)).unwrap();
+        std::fs::remove_file(test.folder.join("c.replay")).unwrap();
+
+        let second = scan(&test.agent.ctx).unwrap();
+        assert_eq!((second.files, second.indexed, second.removed), (2, 0, 1));
+
+        let store = store(&test.agent.ctx).unwrap();
+        let entry = store.get(&uploaded.to_string_lossy()).unwrap().unwrap();
+        assert_eq!(entry.status, LibraryStatus::Uploaded);
+        assert_eq!(entry.record_id.as_deref(), Some("uploaded"));
+        assert_eq!(entry.replay_url.as_deref(), Some("https://ballcam.tv/replays/1"));
+        assert!(store.get(&test.folder.join("c.replay").to_string_lossy()).unwrap().is_none());
+
+        let page = store.query(&LibraryQuery::default()).unwrap();
+        assert_eq!(page.total, 2);
+        assert_eq!(page.entries.len(), 2);
+    }
+
+    #[test]
+    fn query_pages_through_filtered_entries() {
+        let test = library();
+        for n in 0..7 {
+            std::fs::write(test.folder.join(format!("match_{}.replay", n)), "x").unwrap();
+        }
+        std::fs::write(test.folder.join("other.replay"), "x").unwrap();
+        scan(&test.agent.ctx).unwrap();
+
+        let store = store(&test.agent.ctx).unwrap();
+        let mut query = LibraryQuery {
+            search: Some("match_".to_string()),
+            limit: Some(3),
+            ..Default::default()
+        };
+        let mut names = Vec::new();
+        loop {
+            let page = store.query(&query).unwrap();
+            assert_eq!(page.total, 7);
+            names.extend(page.entries.into_iter().map(|entry| entry.filename));
+            match page.next_cursor {
+                Some(cursor) => query.cursor = Some(cursor),
+                None => break,
+            }
+        }
+        names.sort();
+        let expected: Vec<String> = (0..7).map(|n| format!("match_{}.replay", n)).collect();
+        assert_eq!(names, expected);
+    }
+}
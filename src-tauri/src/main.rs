@@ -1,6 +1,28 @@
 // Prevents additional console window on Windows in release
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+use ballcam_agent_lib::{cli, config, headless};
+
 fn main() {
-    ballcam_agent_lib::run()
+    let args = cli::parse();
+
+    // Whatever the config says, until the agent exits
+    if args.dry_run {
+        config::force_dry_run();
+    }
+
+    // Run without the window, e.g. as a systemd service
+    if args.install_service {
+        std::process::exit(headless::run_install_service());
+    }
+    if args.headless {
+        std::process::exit(headless::run());
+    }
+
+    // Subcommands talk to the running agent, or work on their own
+    if let Some(command) = args.command {
+        std::process::exit(cli::run(command, args.dry_run));
+    }
+
+    ballcam_agent_lib::run_with(args.open)
 }
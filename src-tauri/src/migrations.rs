@@ -0,0 +1,197 @@
+use serde_json::{json, Value};
+
+use crate::types::AppConfig;
+
+/// Current config schema version
+///
+/// 1. Agent 1.1.0: the config stored without an envelope
+/// 2. Versioned envelope
+///
+/// Fields added since are filled in from their serde defaults. Bump the version
+/// and add a migration only when stored data has to be transformed.
+pub const CONFIG_VERSION: u32 = 2;
+
+/// Migrations from version `index + 2` to `index + 3`, applied in order
+/// Version 1 configs only need to be wrapped in the envelope
+const CONFIG_MIGRATIONS: &[fn(&mut Value)] = &[];
+
+/// Result of loading a stored config
+pub struct LoadedConfig {
+    pub config: AppConfig,
+    /// Version the config was stored with
+    pub stored_version: u32,
+}
+
+impl LoadedConfig {
+    /// Whether the stored config should be rewritten with the current version
+    pub fn needs_save(&self) -> bool {
+        self.stored_version < CONFIG_VERSION
+    }
+}
+
+/// Wrap a config in the versioned envelope stored under the `config` key
+pub fn wrap_config(config: &AppConfig) -> Result<Value, String> {
+    let config = serde_json::to_value(config)
+        .map_err(|e| format!("Failed to serialize config: {}", e))?;
+    Ok(json!({
+        "version": CONFIG_VERSION,
+        "config": config,
+    }))
+}
+
+/// Unwrap a stored config and migrate it to the current version
+/// Configs written before versioning (agent 1.1.0) are stored unwrapped and treated as version 1
+pub fn load_config(stored: Value) -> Result<LoadedConfig, String> {
+    let (stored_version, mut config) = match stored {
+        Value::Object(mut envelope)
+            if envelope.get("version").is_some_and(Value::is_u64)
+                && envelope.get("config").is_some_and(Value::is_object) =>
+        {
+            let version = envelope["version"].as_u64().unwrap_or(1) as u32;
+            (version, envelope.remove("config").unwrap_or_default())
+        }
+        legacy => (1, legacy),
+    };
+
+    if stored_version > CONFIG_VERSION {
+        tracing::warn!(
+            "Config was written by a newer agent (version {}), loading known fields only",
+            stored_version
+        );
+    }
+
+    for (index, migrate) in CONFIG_MIGRATIONS
+        .iter()
+        .enumerate()
+        .skip(stored_version.saturating_sub(2) as usize)
+    {
+        migrate(&mut config);
+        tracing::info!("Migrated config to version {}", index + 3);
+    }
+
+    let config = serde_json::from_value(config)
+        .map_err(|e| format!("Failed to parse config: {}", e))?;
+
+    Ok(LoadedConfig {
+        config,
+        stored_version,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{ArchiveAction, Visibility};
+
+    /// The `config` key of a config.json fixture
+    fn stored(fixture: &str) -> Value {
+        let mut file: Value = serde_json::from_str(fixture).unwrap();
+        file["config"].take()
+    }
+
+    fn json(config: &AppConfig) -> Value {
+        serde_json::to_value(config).unwrap()
+    }
+
+    #[test]
+    fn loads_agent_1_1_0_config() {
+        let loaded = load_config(stored(include_str!("../tests/fixtures/config/v1.1.0.json"))).unwrap();
+        assert_eq!(loaded.stored_version, 1);
+        assert!(loaded.needs_save());
+
+        let config = loaded.config;
+        assert_eq!(
+            config.replay_folder,
+            "C:\\Users\\player\\Documents\\My Games\\Rocket League\\TAGame\\Demos"
+        );
+        assert!(matches!(config.default_visibility, Visibility::Unlisted));
+        assert!(config.auto_start);
+        assert!(!config.notifications_enabled);
+        assert!(config.setup_complete);
+
+        // Everything added since comes from the defaults
+        let defaults = AppConfig::default();
+        assert!(config.account_routes.is_empty());
+        assert_eq!(config.api_token, None);
+        assert_eq!(config.history_retention_days, None);
+        assert_eq!(config.auto_retry, defaults.auto_retry);
+        assert_eq!(config.staging, defaults.staging);
+        assert_eq!(config.local_archive, defaults.local_archive);
+        assert_eq!(config.cleanup, defaults.cleanup);
+        assert!(!config.dry_run);
+    }
+
+    #[test]
+    fn loads_version_2_config() {
+        let loaded = load_config(stored(include_str!("../tests/fixtures/config/v2.json"))).unwrap();
+        assert_eq!(loaded.stored_version, 2);
+        assert!(!loaded.needs_save());
+
+        let config = loaded.config;
+        assert_eq!(config.replay_folder, "/home/player/replays");
+        assert_eq!(config.account_routes.len(), 1);
+        assert_eq!(config.account_routes[0].folder, "/home/player/replays/alt");
+        assert_eq!(config.account_routes[0].account_id, "user-2");
+        assert_eq!(config.api_token.as_deref(), Some("bc_pat_123"));
+        assert_eq!(config.history_retention_days, Some(90));
+        assert_eq!(json(&config)["autoRetry"], json(&AppConfig::default())["autoRetry"]);
+    }
+
+    #[test]
+    fn loads_version_2_config_with_policies() {
+        let loaded =
+            load_config(stored(include_str!("../tests/fixtures/config/v2-policies.json"))).unwrap();
+        assert!(!loaded.needs_save());
+
+        let config = loaded.config;
+        assert!(config.auto_retry.enabled);
+        assert_eq!(config.auto_retry.initial_delay_minutes, 10);
+        assert_eq!(config.auto_retry.max_delay_minutes, 120);
+        assert_eq!(config.auto_retry.max_age_hours, 24);
+        assert_eq!(config.staging.max_size_mb, 256);
+        assert_eq!(config.staging.max_age_days, 7);
+        assert_eq!(config.local_archive.action, ArchiveAction::Move);
+        assert_eq!(config.local_archive.folder, "/home/player/archive");
+        assert_eq!(config.local_archive.name_template, "{date}/{playlist} {score}");
+        assert!(config.cleanup.enabled);
+        assert_eq!(config.cleanup.max_age_days, None);
+        assert_eq!(config.cleanup.max_count, Some(200));
+        assert!(config.dry_run);
+    }
+
+    #[test]
+    fn saved_config_loads_unchanged() {
+        let config = load_config(stored(include_str!("../tests/fixtures/config/v2-policies.json")))
+            .unwrap()
+            .config;
+        let wrapped = wrap_config(&config).unwrap();
+        assert_eq!(wrapped["version"], CONFIG_VERSION);
+
+        let loaded = load_config(wrapped).unwrap();
+        assert_eq!(loaded.stored_version, CONFIG_VERSION);
+        assert_eq!(json(&loaded.config), json(&config));
+    }
+
+    #[test]
+    fn newer_config_loads_known_fields() {
+        let loaded = load_config(json!({
+            "version": CONFIG_VERSION + 1,
+            "config": {
+                "replayFolder": "/replays",
+                "setupComplete": true,
+                "somethingNew": { "enabled": true },
+            },
+        }))
+        .unwrap();
+        assert_eq!(loaded.stored_version, CONFIG_VERSION + 1);
+        assert!(!loaded.needs_save());
+        assert_eq!(loaded.config.replay_folder, "/replays");
+        assert!(loaded.config.setup_complete);
+    }
+
+    #[test]
+    fn config_with_the_wrong_shape_is_an_error() {
+        assert!(load_config(json!({ "version": 2, "config": { "replayFolder": 42 } })).is_err());
+        assert!(load_config(json!(["not", "a", "config"])).is_err());
+    }
+}
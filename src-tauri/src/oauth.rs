@@ -0,0 +1,421 @@
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::types::OAuthTokenResponse;
+
+// Use localhost in dev mode, production URL otherwise
+#[cfg(dev)]
+const API_BASE_URL: &str = "http://localhost:3000/api";
+#[cfg(not(dev))]
+const API_BASE_URL: &str = "https://ballcam.tv/api";
+
+#[cfg(dev)]
+const FRONTEND_URL: &str = "http://localhost:5173";
+#[cfg(not(dev))]
+const FRONTEND_URL: &str = "https://ballcam.tv";
+
+const CLIENT_ID: &str = "ballcam-agent";
+const CALLBACK_PATH: &str = "/callback";
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+/// Connections that don't send a request in time are dropped
+const REQUEST_READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait for the user to finish logging in the browser
+pub const DEFAULT_LOGIN_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Authorization server endpoints
+#[derive(Debug, Clone)]
+pub struct OAuthConfig {
+    pub authorize_url: String,
+    pub token_url: String,
+    pub client_id: String,
+}
+
+impl Default for OAuthConfig {
+    fn default() -> Self {
+        Self {
+            authorize_url: format!("{}/oauth/authorize", FRONTEND_URL),
+            token_url: format!("{}/auth/oauth/token", API_BASE_URL),
+            client_id: CLIENT_ID.to_string(),
+        }
+    }
+}
+
+/// PKCE code verifier and its S256 challenge (RFC 7636)
+struct Pkce {
+    verifier: String,
+    challenge: String,
+}
+
+impl Pkce {
+    fn generate() -> Self {
+        // Two v4 UUIDs give 244 random bits in 64 unreserved characters
+        let verifier = format!(
+            "{}{}",
+            uuid::Uuid::new_v4().simple(),
+            uuid::Uuid::new_v4().simple()
+        );
+        let challenge = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(Sha256::digest(verifier.as_bytes()));
+        Self {
+            verifier,
+            challenge,
+        }
+    }
+}
+
+/// Run the authorization code flow with PKCE over a loopback redirect
+///
+/// Binds an ephemeral port on 127.0.0.1, hands the authorization URL to `open_browser`,
+/// waits for the redirect and exchanges the code for tokens.
+pub async fn authorize<F>(
+    config: &OAuthConfig,
+    open_browser: F,
+    timeout: Duration,
+) -> Result<OAuthTokenResponse, String>
+where
+    F: FnOnce(&str) -> Result<(), String>,
+{
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .map_err(|e| format!("Failed to start login listener: {}", e))?;
+    let port = listener
+        .local_addr()
+        .map_err(|e| format!("Failed to start login listener: {}", e))?
+        .port();
+
+    let redirect_uri = format!("http://127.0.0.1:{}{}", port, CALLBACK_PATH);
+    let pkce = Pkce::generate();
+    let state = uuid::Uuid::new_v4().simple().to_string();
+
+    let mut authorize_url = reqwest::Url::parse(&config.authorize_url)
+        .map_err(|e| format!("Invalid authorization URL: {}", e))?;
+    authorize_url
+        .query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", &config.client_id)
+        .append_pair("redirect_uri", &redirect_uri)
+        .append_pair("code_challenge", &pkce.challenge)
+        .append_pair("code_challenge_method", "S256")
+        .append_pair("state", &state);
+
+    open_browser(authorize_url.as_str())?;
+
+    tracing::info!("Waiting for browser login on port {}", port);
+
+    let code = tokio::time::timeout(
+        timeout,
+        wait_for_code(&listener, &state, REQUEST_READ_TIMEOUT),
+    )
+        .await
+        .map_err(|_| "Login timed out, please try again".to_string())??;
+
+    exchange_code(config, &code, &redirect_uri, &pkce.verifier).await
+}
+
+/// Exchange a refresh token for new tokens
+pub async fn refresh(
+    client: &reqwest::Client,
+    config: &OAuthConfig,
+    refresh_token: &str,
+) -> Result<OAuthTokenResponse, String> {
+    let response = client
+        .post(&config.token_url)
+        .form(&[
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token),
+            ("client_id", config.client_id.as_str()),
+        ])
+        .send()
+        .await
+        .map_err(|e| format!("Network error during refresh: {}", e))?;
+
+    parse_token_response(response).await
+}
+
+async fn exchange_code(
+    config: &OAuthConfig,
+    code: &str,
+    redirect_uri: &str,
+    verifier: &str,
+) -> Result<OAuthTokenResponse, String> {
+    let client = reqwest::Client::new();
+    let response = client
+        .post(&config.token_url)
+        .form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", redirect_uri),
+            ("client_id", config.client_id.as_str()),
+            ("code_verifier", verifier),
+        ])
+        .send()
+        .await
+        .map_err(|e| format!("Network error: {}", e))?;
+
+    parse_token_response(response).await
+}
+
+async fn parse_token_response(response: reqwest::Response) -> Result<OAuthTokenResponse, String> {
+    let status = response.status();
+    if !status.is_success() {
+        let error_text = response.text().await.unwrap_or_default();
+        return Err(format!("Token request failed ({}): {}", status, error_text));
+    }
+
+    response
+        .json()
+        .await
+        .map_err(|e| format!("Failed to parse token response: {}", e))
+}
+
+/// Accept connections until the redirect carrying the authorization code arrives
+/// Connections silent for longer than `read_timeout` are dropped so they can't block the loop
+async fn wait_for_code(
+    listener: &TcpListener,
+    expected_state: &str,
+    read_timeout: Duration,
+) -> Result<String, String> {
+    loop {
+        let (mut stream, _) = listener
+            .accept()
+            .await
+            .map_err(|e| format!("Failed to accept login redirect: {}", e))?;
+
+        let read = tokio::time::timeout(read_timeout, read_request_target(&mut stream)).await;
+        let Ok(Some(target)) = read else {
+            continue;
+        };
+
+        let url = match reqwest::Url::parse(&format!("http://127.0.0.1{}", target)) {
+            Ok(url) if url.path() == CALLBACK_PATH => url,
+            // Browsers also ask for things like /favicon.ico
+            _ => {
+                respond(&mut stream, "404 Not Found", "Not found").await;
+                continue;
+            }
+        };
+
+        let param = |name: &str| {
+            url.query_pairs()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.into_owned())
+        };
+
+        if param("state").as_deref() != Some(expected_state) {
+            respond(&mut stream, "400 Bad Request", "Login failed: invalid state. You can close this window.").await;
+            return Err("Login failed: state mismatch".to_string());
+        }
+
+        if let Some(error) = param("error") {
+            respond(&mut stream, "200 OK", "Login was cancelled. You can close this window.").await;
+            return Err(match error.as_str() {
+                "access_denied" => "Login was denied".to_string(),
+                _ => format!("Login failed: {}", error),
+            });
+        }
+
+        let Some(code) = param("code") else {
+            respond(&mut stream, "400 Bad Request", "Login failed: missing code. You can close this window.").await;
+            return Err("Login failed: no authorization code received".to_string());
+        };
+
+        respond(&mut stream, "200 OK", "Login successful! You can close this window and return to BallCam Agent.").await;
+        return Ok(code);
+    }
+}
+
+/// Read an HTTP request head and return the request target of a GET request
+async fn read_request_target(stream: &mut TcpStream) -> Option<String> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 1024];
+
+    while !buffer.windows(4).any(|w| w == b"\r\n\r\n") {
+        let read = stream.read(&mut chunk).await.ok()?;
+        if read == 0 || buffer.len() + read > MAX_REQUEST_SIZE {
+            return None;
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+
+    let head = String::from_utf8_lossy(&buffer);
+    let mut parts = head.lines().next()?.split_whitespace();
+    match (parts.next(), parts.next()) {
+        (Some("GET"), Some(target)) => Some(target.to_string()),
+        _ => None,
+    }
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, message: &str) {
+    let body = format!(
+        "<!DOCTYPE html><html><head><title>BallCam Agent</title></head>\
+         <body style=\"font-family: sans-serif; text-align: center; padding-top: 4em;\">\
+         <p>{}</p></body></html>",
+        message
+    );
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answer one request with a JSON body, returning the request that was received
+    async fn serve_once(listener: TcpListener, body: &'static str) -> String {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut chunk = [0u8; 1024];
+        loop {
+            let read = stream.read(&mut chunk).await.unwrap();
+            request.extend_from_slice(&chunk[..read]);
+            let text = String::from_utf8_lossy(&request);
+            if let Some((head, content)) = text.split_once("\r\n\r\n") {
+                let length = head
+                    .lines()
+                    .find_map(|line| {
+                        let (name, value) = line.split_once(':')?;
+                        name.eq_ignore_ascii_case("content-length")
+                            .then(|| value.trim().parse::<usize>().ok())?
+                    })
+                    .unwrap_or(0);
+                if content.len() >= length {
+                    break;
+                }
+            }
+            if read == 0 {
+                break;
+            }
+        }
+
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        stream.write_all(response.as_bytes()).await.unwrap();
+        String::from_utf8_lossy(&request).to_string()
+    }
+
+    /// Send a GET request and return the response status line
+    async fn get(url: &str) -> String {
+        let url = reqwest::Url::parse(url).unwrap();
+        let mut stream = TcpStream::connect((url.host_str().unwrap(), url.port().unwrap()))
+            .await
+            .unwrap();
+        let target = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+        stream
+            .write_all(format!("GET {} HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n", target).as_bytes())
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response.lines().next().unwrap_or_default().to_string()
+    }
+
+    fn query_param(url: &reqwest::Url, name: &str) -> String {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn authorize_exchanges_the_redirected_code() {
+        let token_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let token_url = format!("http://{}/token", token_listener.local_addr().unwrap());
+        let token_server = tokio::spawn(serve_once(
+            token_listener,
+            r#"{"access_token":"at","token_type":"Bearer","expires_in":3600,"refresh_token":"rt"}"#,
+        ));
+
+        let config = OAuthConfig {
+            authorize_url: "https://ballcam.test/oauth/authorize".to_string(),
+            token_url,
+            client_id: CLIENT_ID.to_string(),
+        };
+
+        let (redirect_tx, redirect_rx) = tokio::sync::oneshot::channel();
+        let browser = move |url: &str| {
+            let url = reqwest::Url::parse(url).unwrap();
+            assert_eq!(query_param(&url, "code_challenge_method"), "S256");
+            let redirect = format!(
+                "{}?code=the-code&state={}",
+                query_param(&url, "redirect_uri"),
+                query_param(&url, "state")
+            );
+            tokio::spawn(async move {
+                // Browsers also ask for other paths
+                let favicon = redirect.replace(CALLBACK_PATH, "/favicon.ico");
+                let favicon = favicon.split('?').next().unwrap().to_string();
+                assert!(get(&favicon).await.contains("404"));
+                let _ = redirect_tx.send(get(&redirect).await);
+            });
+            Ok(())
+        };
+
+        let tokens = authorize(&config, browser, Duration::from_secs(10))
+            .await
+            .unwrap();
+        assert_eq!(tokens.access_token, "at");
+        assert_eq!(tokens.refresh_token.as_deref(), Some("rt"));
+        assert!(redirect_rx.await.unwrap().contains("200 OK"));
+
+        let request = token_server.await.unwrap();
+        assert!(request.starts_with("POST /token"));
+        assert!(request.contains("grant_type=authorization_code"));
+        assert!(request.contains("code=the-code"));
+        assert!(request.contains("code_verifier="));
+    }
+
+    #[tokio::test]
+    async fn authorize_rejects_a_mismatched_state() {
+        let config = OAuthConfig {
+            authorize_url: "https://ballcam.test/oauth/authorize".to_string(),
+            token_url: "http://127.0.0.1:9/token".to_string(),
+            client_id: CLIENT_ID.to_string(),
+        };
+        let browser = |url: &str| {
+            let url = reqwest::Url::parse(url).unwrap();
+            let redirect = format!("{}?code=c&state=forged", query_param(&url, "redirect_uri"));
+            tokio::spawn(async move { get(&redirect).await });
+            Ok(())
+        };
+
+        let result = authorize(&config, browser, Duration::from_secs(10)).await;
+        assert_eq!(result.unwrap_err(), "Login failed: state mismatch");
+    }
+
+    #[tokio::test]
+    async fn silent_connections_do_not_block_the_redirect() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Connects and never sends anything
+        let _silent = TcpStream::connect(addr).await.unwrap();
+        let client = tokio::spawn(async move {
+            get(&format!("http://{}{}?code=c&state=s", addr, CALLBACK_PATH)).await
+        });
+
+        let code = tokio::time::timeout(
+            Duration::from_secs(5),
+            wait_for_code(&listener, "s", Duration::from_millis(100)),
+        )
+        .await
+        .expect("a silent connection blocked the listener");
+        assert_eq!(code.unwrap(), "c");
+        assert!(client.await.unwrap().contains("200 OK"));
+    }
+}
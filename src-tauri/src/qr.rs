@@ -0,0 +1,122 @@
+use base64::Engine;
+use image::{ImageFormat, Luma};
+use qrcode::render::{svg, unicode};
+use qrcode::QrCode;
+use std::io::Cursor;
+
+/// Minimum rendered size (in pixels) of the generated QR codes
+const QR_MIN_SIZE: u32 = 256;
+
+/// Query parameter the device page of the web app pre-fills the user code from
+/// Only used when the server doesn't send a complete verification URL
+const USER_CODE_PARAM: &str = "code";
+
+/// Build the verification URL with the user code pre-filled
+/// Uses the server-provided complete URL when available (RFC 8628 `verification_uri_complete`)
+pub fn complete_verification_url(
+    verification_url: &str,
+    user_code: &str,
+    verification_url_complete: Option<&str>,
+) -> String {
+    if let Some(url) = verification_url_complete.filter(|u| !u.is_empty()) {
+        return url.to_string();
+    }
+
+    match reqwest::Url::parse(verification_url) {
+        Ok(mut url) => {
+            url.query_pairs_mut().append_pair(USER_CODE_PARAM, user_code);
+            url.to_string()
+        }
+        Err(_) => verification_url.to_string(),
+    }
+}
+
+/// Render data as a QR code SVG document
+pub fn render_svg(data: &str) -> Result<String, String> {
+    let code = QrCode::new(data.as_bytes())
+        .map_err(|e| format!("Failed to generate QR code: {}", e))?;
+
+    Ok(code
+        .render::<svg::Color>()
+        .min_dimensions(QR_MIN_SIZE, QR_MIN_SIZE)
+        .dark_color(svg::Color("#000000"))
+        .light_color(svg::Color("#ffffff"))
+        .build())
+}
+
+/// Render data as a QR code PNG, returned as a `data:image/png;base64,...` URL
+pub fn render_png_data_url(data: &str) -> Result<String, String> {
+    let code = QrCode::new(data.as_bytes())
+        .map_err(|e| format!("Failed to generate QR code: {}", e))?;
+
+    let image = code
+        .render::<Luma<u8>>()
+        .min_dimensions(QR_MIN_SIZE, QR_MIN_SIZE)
+        .build();
+
+    let mut png = Cursor::new(Vec::new());
+    image
+        .write_to(&mut png, ImageFormat::Png)
+        .map_err(|e| format!("Failed to encode QR code: {}", e))?;
+
+    Ok(format!(
+        "data:image/png;base64,{}",
+        base64::engine::general_purpose::STANDARD.encode(png.into_inner())
+    ))
+}
+
+/// Render data as a QR code made of Unicode block characters, for printing to a terminal
+pub fn render_terminal(data: &str) -> Result<String, String> {
+    let code = QrCode::new(data.as_bytes())
+        .map_err(|e| format!("Failed to generate QR code: {}", e))?;
+
+    // Light modules are drawn, so the code scans on dark terminal backgrounds
+    Ok(code
+        .render::<unicode::Dense1x2>()
+        .dark_color(unicode::Dense1x2::Light)
+        .light_color(unicode::Dense1x2::Dark)
+        .build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn server_complete_url_wins() {
+        assert_eq!(
+            complete_verification_url(
+                "https://ballcam.tv/device",
+                "ABCD-1234",
+                Some("https://ballcam.tv/device?user_code=ABCD-1234"),
+            ),
+            "https://ballcam.tv/device?user_code=ABCD-1234"
+        );
+        // An empty one is ignored
+        assert_eq!(
+            complete_verification_url("https://ballcam.tv/device", "ABCD-1234", Some("")),
+            "https://ballcam.tv/device?code=ABCD-1234"
+        );
+    }
+
+    #[test]
+    fn code_is_appended_to_the_query() {
+        assert_eq!(
+            complete_verification_url("https://ballcam.tv/device?lang=fr", "AB CD", None),
+            "https://ballcam.tv/device?lang=fr&code=AB+CD"
+        );
+    }
+
+    #[test]
+    fn unparseable_urls_are_kept() {
+        assert_eq!(complete_verification_url("ballcam.tv/device", "ABCD", None), "ballcam.tv/device");
+    }
+
+    #[test]
+    fn renders_svg_and_png() {
+        let url = "https://ballcam.tv/device?code=ABCD-1234";
+        assert!(render_svg(url).unwrap().contains("<svg"));
+        assert!(render_png_data_url(url).unwrap().starts_with("data:image/png;base64,"));
+        assert!(!render_terminal(url).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,167 @@
+use std::sync::Mutex;
+
+use crate::agent::AgentContext;
+use crate::history;
+use crate::types::{UploadJob, UploadQueueState};
+use crate::uploader::{self, Uploader};
+
+/// Store file the held jobs are kept in across restarts
+const QUEUE_FILE: &str = "queue.json";
+
+/// Uploads held back while the session needs re-authentication
+pub struct UploadQueue {
+    state: Mutex<UploadQueueState>,
+}
+
+impl UploadQueue {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(UploadQueueState::default()),
+        }
+    }
+
+    /// Whether uploads are paused until the user logs in again
+    pub fn is_paused(&self) -> bool {
+        self.state.lock().unwrap().paused_for_auth
+    }
+
+    /// Hold a job until the next successful login
+    /// Returns true if this call paused the queue
+    pub fn hold(&self, job: UploadJob) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let newly_paused = !state.paused_for_auth;
+        state.paused_for_auth = true;
+        if !state.held_jobs.iter().any(|j| j.file_path == job.file_path) {
+            state.held_jobs.push(job);
+        }
+        newly_paused
+    }
+
+    /// Unpause the queue and take all held jobs
+    pub fn take_held(&self) -> Vec<UploadJob> {
+        let mut state = self.state.lock().unwrap();
+        state.paused_for_auth = false;
+        std::mem::take(&mut state.held_jobs)
+    }
+
+    /// Hold jobs again after a restart, pausing the queue if there are any
+    pub fn restore(&self, jobs: Vec<UploadJob>) {
+        let mut state = self.state.lock().unwrap();
+        state.paused_for_auth = !jobs.is_empty();
+        state.held_jobs = jobs;
+    }
+
+    pub fn get_state(&self) -> UploadQueueState {
+        self.state.lock().unwrap().clone()
+    }
+}
+
+impl Default for UploadQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Hold an upload after an authentication failure
+/// The first failure pauses the queue and asks the user to log in again
+pub fn hold_for_login(ctx: &AgentContext, job: UploadJob) {
+    tracing::warn!("Session expired, holding upload: {}", job.file_path);
+
+    let newly_paused = ctx.upload_queue.hold(job);
+    save_held(ctx);
+
+    if newly_paused {
+        ctx.emit("session_expired", &());
+        uploader::notify(
+            ctx,
+            "Login required",
+            "Your BallCam session has expired. Log in again to resume uploads.",
+        );
+    }
+}
+
+/// Resume uploads held back by an expired session
+/// Called after every successful login
+pub fn resume_held_uploads(ctx: &AgentContext) {
+    let jobs = ctx.upload_queue.take_held();
+    if jobs.is_empty() {
+        return;
+    }
+    save_held(ctx);
+
+    tracing::info!("Resuming {} held uploads", jobs.len());
+    ctx.emit("uploads_resumed", &jobs.len());
+
+    for job in jobs {
+        let task_ctx = ctx.clone();
+        ctx.spawn(async move {
+            let uploader = Uploader::new();
+            // Retries continue their record, if it's still in history
+            let record = job
+                .record_id
+                .as_deref()
+                .and_then(|id| history::store(&task_ctx).ok()?.get(id).ok().flatten());
+            let result = match record {
+                Some(record) => uploader.retry_record(&task_ctx, record).await,
+                None => {
+                    uploader
+                        .upload_replay_from(&task_ctx, &job.file_path, job.visibility, job.source)
+                        .await
+                }
+            };
+            if let Err(e) = result {
+                tracing::error!("Held upload failed: {}", e);
+            }
+        });
+    }
+}
+
+/// Resume held uploads once the active session is confirmed usable (refreshed if needed)
+/// Jobs stay held when it isn't, instead of failing straight back into the hold
+pub fn resume_if_session_valid(ctx: &AgentContext) {
+    if !ctx.upload_queue.is_paused() {
+        return;
+    }
+
+    let task_ctx = ctx.clone();
+    ctx.spawn(async move {
+        match Uploader::new().ensure_valid_session(&task_ctx, None).await {
+            Ok(_) => resume_held_uploads(&task_ctx),
+            Err(e) => tracing::warn!("Held uploads stay paused: {}", e),
+        }
+    });
+}
+
+/// Hold the jobs that were held when the agent last exited
+/// They resume right away if the session is still usable
+pub fn restore_held(ctx: &AgentContext) {
+    let jobs: Vec<UploadJob> = match ctx.storage.get(QUEUE_FILE, "heldJobs") {
+        Ok(Some(value)) => serde_json::from_value(value).unwrap_or_else(|e| {
+            tracing::warn!("Ignoring unreadable held uploads: {}", e);
+            Vec::new()
+        }),
+        Ok(None) => Vec::new(),
+        Err(e) => {
+            tracing::warn!("Failed to load held uploads: {}", e);
+            Vec::new()
+        }
+    };
+    if jobs.is_empty() {
+        return;
+    }
+
+    tracing::info!("Restored {} held uploads", jobs.len());
+    ctx.upload_queue.restore(jobs);
+    resume_if_session_valid(ctx);
+}
+
+/// Persist the held jobs so they survive a restart
+fn save_held(ctx: &AgentContext) {
+    let jobs = ctx.upload_queue.get_state().held_jobs;
+    let result = serde_json::to_value(jobs)
+        .map_err(|e| e.to_string())
+        .and_then(|value| ctx.storage.set(QUEUE_FILE, "heldJobs", value));
+    if let Err(e) = result {
+        tracing::warn!("Failed to save held uploads: {}", e);
+    }
+}
@@ -0,0 +1,452 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use crate::types::ReplayMetadata;
+
+/// Headers are a few KB; anything larger is not a replay
+const MAX_HEADER_SIZE: u32 = 1024 * 1024;
+/// Guard against corrupt length prefixes and runaway nesting
+const MAX_STRING_LENGTH: i32 = 64 * 1024;
+const MAX_ARRAY_LENGTH: i32 = 10_000;
+const MAX_DEPTH: usize = 4;
+
+/// Header property value
+#[derive(Debug, Clone)]
+enum Property {
+    Bool(bool),
+    Int(i32),
+    Float(f32),
+    Str(String),
+    Array(Vec<HashMap<String, Property>>),
+    Other,
+}
+
+/// Read the metadata stored in a replay's header
+/// Only the header is read, the network stream is never parsed
+pub fn parse_header(path: &Path) -> Result<ReplayMetadata, String> {
+    let mut file = File::open(path).map_err(|e| format!("Failed to open replay: {}", e))?;
+
+    let mut prefix = [0u8; 8];
+    file.read_exact(&mut prefix)
+        .map_err(|_| "Not a replay file: too short".to_string())?;
+    let header_size = u32::from_le_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]);
+    if header_size == 0 || header_size > MAX_HEADER_SIZE {
+        return Err(format!("Not a replay file: invalid header size {}", header_size));
+    }
+
+    let mut header = vec![0u8; header_size as usize];
+    file.read_exact(&mut header)
+        .map_err(|_| "Replay header is truncated".to_string())?;
+
+    let mut reader = HeaderReader::new(&header);
+    let major_version = reader.u32()?;
+    let minor_version = reader.u32()?;
+    if major_version >= 866 && minor_version >= 18 {
+        reader.u32()?; // net version
+    }
+
+    let game_type = reader.string()?;
+    if !game_type.starts_with("TAGame.Replay_") {
+        return Err(format!("Unsupported replay type: {}", game_type));
+    }
+
+    let properties = reader.properties(0)?;
+    Ok(metadata_from(&properties))
+}
+
+fn metadata_from(properties: &HashMap<String, Property>) -> ReplayMetadata {
+    let string = |key: &str| match properties.get(key) {
+        Some(Property::Str(value)) if !value.is_empty() => Some(value.clone()),
+        _ => None,
+    };
+    let int = |key: &str| match properties.get(key) {
+        Some(Property::Int(value)) => Some(*value),
+        _ => None,
+    };
+    let float = |key: &str| match properties.get(key) {
+        Some(Property::Float(value)) => Some(*value),
+        _ => None,
+    };
+
+    // Older replays lack TotalSecondsPlayed, derive it from the frame count
+    let duration_seconds = float("TotalSecondsPlayed").or_else(|| {
+        let frames = int("NumFrames")?;
+        let fps = float("RecordFPS").filter(|fps| *fps > 0.0)?;
+        Some(frames as f32 / fps)
+    });
+
+    let goals = match properties.get("Goals") {
+        Some(Property::Array(goals)) => goals.len() as u32,
+        _ => 0,
+    };
+
+    ReplayMetadata {
+        replay_guid: string("Id"),
+        replay_name: string("ReplayName"),
+        map_name: string("MapName"),
+        match_type: string("MatchType"),
+        team_size: int("TeamSize").map(|size| size.max(0) as u32),
+        // Scores of zero are omitted from the header
+        team0_score: int("Team0Score").unwrap_or(0).max(0) as u32,
+        team1_score: int("Team1Score").unwrap_or(0).max(0) as u32,
+        goals,
+        player_name: string("PlayerName"),
+        date: string("Date"),
+        duration_seconds,
+        build_version: string("BuildVersion"),
+        unfair_teams: matches!(properties.get("UnfairTeamSize"), Some(Property::Bool(true))),
+    }
+}
+
+struct HeaderReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> HeaderReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, offset: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self
+            .offset
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| "Replay header is truncated".to_string())?;
+        let bytes = &self.data[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn i32(&mut self) -> Result<i32, String> {
+        Ok(self.u32()? as i32)
+    }
+
+    fn f32(&mut self) -> Result<f32, String> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        let low = self.u32()? as u64;
+        let high = self.u32()? as u64;
+        Ok(high << 32 | low)
+    }
+
+    /// Length-prefixed string: Latin-1 for positive lengths, UTF-16 for negative ones
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.i32()?;
+        if !(-MAX_STRING_LENGTH..=MAX_STRING_LENGTH).contains(&len) {
+            return Err(format!("Invalid string length in replay header: {}", len));
+        }
+
+        let value = if len < 0 {
+            let bytes = self.take(len.unsigned_abs() as usize * 2)?;
+            let units: Vec<u16> = bytes
+                .chunks_exact(2)
+                .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        } else {
+            self.take(len as usize)?.iter().map(|b| *b as char).collect()
+        };
+
+        Ok(value.trim_end_matches('\0').to_string())
+    }
+
+    /// Properties until the terminating "None" key
+    fn properties(&mut self, depth: usize) -> Result<HashMap<String, Property>, String> {
+        if depth > MAX_DEPTH {
+            return Err("Replay header is nested too deeply".to_string());
+        }
+
+        let mut properties = HashMap::new();
+        loop {
+            let key = self.string()?;
+            if key == "None" {
+                return Ok(properties);
+            }
+
+            let kind = self.string()?;
+            let size = self.u64()?;
+            let value = match kind.as_str() {
+                "BoolProperty" => Property::Bool(self.u8()? != 0),
+                "IntProperty" => Property::Int(self.i32()?),
+                "FloatProperty" => Property::Float(self.f32()?),
+                "QWordProperty" => {
+                    self.u64()?;
+                    Property::Other
+                }
+                "StrProperty" | "NameProperty" => Property::Str(self.string()?),
+                "ByteProperty" => {
+                    let enum_type = self.string()?;
+                    // Platform values are stored without a separate enum name
+                    if !enum_type.starts_with("OnlinePlatform_") {
+                        self.string()?;
+                    }
+                    Property::Other
+                }
+                "ArrayProperty" => {
+                    let len = self.i32()?;
+                    if !(0..=MAX_ARRAY_LENGTH).contains(&len) {
+                        return Err(format!("Invalid array length in replay header: {}", len));
+                    }
+                    let items = (0..len)
+                        .map(|_| self.properties(depth + 1))
+                        .collect::<Result<_, _>>()?;
+                    Property::Array(items)
+                }
+                _ => {
+                    let size = usize::try_from(size)
+                        .map_err(|_| "Invalid property size in replay header".to_string())?;
+                    self.take(size)?;
+                    Property::Other
+                }
+            };
+
+            properties.insert(key, value);
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Builds replay headers property by property
+    struct Header(Vec<u8>);
+
+    impl Header {
+        fn new() -> Self {
+            Self::versioned(868, 32).u32(11).str("TAGame.Replay_Soccar_TA")
+        }
+
+        /// Header of a replay from before net versions
+        fn versioned(major: u32, minor: u32) -> Self {
+            Self(Vec::new()).u32(major).u32(minor)
+        }
+
+        fn raw(mut self, bytes: &[u8]) -> Self {
+            self.0.extend_from_slice(bytes);
+            self
+        }
+
+        fn u32(self, value: u32) -> Self {
+            self.raw(&value.to_le_bytes())
+        }
+
+        fn str(self, value: &str) -> Self {
+            let mut bytes = value.as_bytes().to_vec();
+            bytes.push(0);
+            self.u32(bytes.len() as u32).raw(&bytes)
+        }
+
+        fn prop(self, key: &str, kind: &str, payload: &[u8]) -> Self {
+            self.str(key)
+                .str(kind)
+                .raw(&(payload.len() as u64).to_le_bytes())
+                .raw(payload)
+        }
+
+        fn int(self, key: &str, value: i32) -> Self {
+            self.prop(key, "IntProperty", &value.to_le_bytes())
+        }
+
+        fn float(self, key: &str, value: f32) -> Self {
+            self.prop(key, "FloatProperty", &value.to_le_bytes())
+        }
+
+        /// Payload of a string property
+        fn string_payload(value: &str) -> Vec<u8> {
+            Header(Vec::new()).str(value).0
+        }
+
+        fn none(self) -> Self {
+            self.str("None")
+        }
+    }
+
+    struct TempReplay(PathBuf);
+
+    impl Drop for TempReplay {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    /// Write a replay file with a header and a few body bytes
+    fn replay(header: &[u8]) -> TempReplay {
+        file(&contents(header))
+    }
+
+    fn contents(header: &[u8]) -> Vec<u8> {
+        let mut contents = (header.len() as u32).to_le_bytes().to_vec();
+        contents.extend_from_slice(&[0; 4]); // CRC, not checked
+        contents.extend_from_slice(header);
+        contents.extend_from_slice(&[0; 16]);
+        contents
+    }
+
+    /// Contents of a replay file the game gave a GUID, for tests of other modules
+    pub(crate) fn replay_with_guid(guid: &str) -> Vec<u8> {
+        let id = Header::string_payload(guid);
+        contents(&Header::new().prop("Id", "StrProperty", &id).none().0)
+    }
+
+    fn file(contents: &[u8]) -> TempReplay {
+        let path = std::env::temp_dir().join(format!("ballcam-replay-{}.replay", uuid::Uuid::new_v4()));
+        std::fs::write(&path, contents).unwrap();
+        TempReplay(path)
+    }
+
+    fn parse(header: Header) -> Result<ReplayMetadata, String> {
+        parse_header(&replay(&header.0).0)
+    }
+
+    #[test]
+    fn parses_fixture_replay() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/replays/soccar.replay");
+        let metadata = parse_header(&path).unwrap();
+
+        assert_eq!(metadata.replay_guid.as_deref(), Some("8A1E2C4F4B7D11EEB0C1A2B3C4D5E6F7"));
+        assert_eq!(metadata.replay_name.as_deref(), Some("Overtime thriller"));
+        assert_eq!(metadata.map_name.as_deref(), Some("stadium_p"));
+        assert_eq!(metadata.match_type.as_deref(), Some("Online"));
+        assert_eq!(metadata.team_size, Some(1));
+        assert_eq!((metadata.team0_score, metadata.team1_score), (2, 1));
+        assert_eq!(metadata.goals, 3);
+        assert_eq!(metadata.player_name.as_deref(), Some("Zephyr"));
+        assert_eq!(metadata.date.as_deref(), Some("2024-01-15 20-30-12"));
+        assert_eq!(metadata.duration_seconds, Some(312.5));
+        assert_eq!(metadata.build_version.as_deref(), Some("240112.51467.437402"));
+        assert!(!metadata.unfair_teams);
+    }
+
+    #[test]
+    fn older_replays_derive_the_duration_from_frames() {
+        let header = Header::versioned(868, 12)
+            .str("TAGame.Replay_Soccar_TA")
+            .int("Team1Score", 4)
+            .int("NumFrames", 900)
+            .float("RecordFPS", 30.0)
+            .prop("UnfairTeamSize", "BoolProperty", &[1])
+            .none();
+
+        let metadata = parse(header).unwrap();
+        assert_eq!(metadata.duration_seconds, Some(30.0));
+        // Scores of zero are left out of headers
+        assert_eq!((metadata.team0_score, metadata.team1_score), (0, 4));
+        assert!(metadata.unfair_teams);
+        assert_eq!(metadata.replay_guid, None);
+    }
+
+    #[test]
+    fn rejects_truncated_headers() {
+        assert_eq!(
+            parse_header(&file(&[0x10, 0, 0]).0).unwrap_err(),
+            "Not a replay file: too short"
+        );
+
+        // Header size larger than the file
+        let header = Header::new().int("TeamSize", 2).none().0;
+        let mut contents = (header.len() as u32 + 100).to_le_bytes().to_vec();
+        contents.extend_from_slice(&[0; 4]);
+        contents.extend_from_slice(&header);
+        assert_eq!(parse_header(&file(&contents).0).unwrap_err(), "Replay header is truncated");
+
+        // Cut inside a property, or before the terminating "None"
+        let header = Header::new().int("TeamSize", 2).0;
+        assert_eq!(parse_header(&replay(&header[..header.len() - 2]).0).unwrap_err(), "Replay header is truncated");
+        assert_eq!(parse_header(&replay(&header).0).unwrap_err(), "Replay header is truncated");
+    }
+
+    #[test]
+    fn rejects_oversized_length_prefixes() {
+        let mut contents = (MAX_HEADER_SIZE + 1).to_le_bytes().to_vec();
+        contents.extend_from_slice(&[0; 8]);
+        assert!(parse_header(&file(&contents).0)
+            .unwrap_err()
+            .starts_with("Not a replay file: invalid header size"));
+
+        let header = Header::new().u32(i32::MAX as u32);
+        assert!(parse(header).unwrap_err().starts_with("Invalid string length"));
+
+        // UTF-16 strings have negative lengths
+        let header = Header::new().u32(i32::MIN as u32);
+        assert!(parse(header).unwrap_err().starts_with("Invalid string length"));
+
+        let header = Header::new()
+            .str("Goals")
+            .str("ArrayProperty")
+            .raw(&4u64.to_le_bytes())
+            .u32(1_000_000);
+        assert!(parse(header).unwrap_err().starts_with("Invalid array length"));
+
+        let header = Header::new()
+            .str("Camera")
+            .str("StructProperty")
+            .raw(&u64::MAX.to_le_bytes())
+            .none();
+        assert!(parse(header).is_err());
+    }
+
+    #[test]
+    fn rejects_deeply_nested_arrays() {
+        let mut header = Header::new();
+        for _ in 0..=MAX_DEPTH {
+            header = header.str("Nested").str("ArrayProperty").raw(&0u64.to_le_bytes()).u32(1);
+        }
+        assert_eq!(parse(header).unwrap_err(), "Replay header is nested too deeply");
+    }
+
+    #[test]
+    fn skips_unknown_properties() {
+        let mut platform = Header::string_payload("OnlinePlatform");
+        platform.extend(Header::string_payload("OnlinePlatform_Epic"));
+        let mut enum_value = Header::string_payload("ETeamColor");
+        enum_value.extend(Header::string_payload("ETeamColor_Orange"));
+
+        let header = Header::new()
+            .prop("Color", "ByteProperty", &enum_value)
+            .prop("Platform", "ByteProperty", &platform)
+            // Steam and PlayStation values are stored without the enum name
+            .prop("Platform", "ByteProperty", &Header::string_payload("OnlinePlatform_Steam"))
+            .prop("OnlineID", "QWordProperty", &7u64.to_le_bytes())
+            .prop("Camera", "StructProperty", &[1, 2, 3, 4, 5])
+            .int("Team0Score", 3)
+            .prop("MapName", "NameProperty", &Header::string_payload("eurostadium_p"))
+            .none();
+
+        let metadata = parse(header).unwrap();
+        assert_eq!(metadata.team0_score, 3);
+        assert_eq!(metadata.map_name.as_deref(), Some("eurostadium_p"));
+    }
+
+    #[test]
+    fn reads_utf16_strings() {
+        let name: Vec<u16> = "プレイヤー\0".encode_utf16().collect();
+        let mut payload = (-(name.len() as i32)).to_le_bytes().to_vec();
+        payload.extend(name.iter().flat_map(|unit| unit.to_le_bytes()));
+
+        let header = Header::new().prop("PlayerName", "StrProperty", &payload).none();
+        assert_eq!(parse(header).unwrap().player_name.as_deref(), Some("プレイヤー"));
+    }
+
+    #[test]
+    fn rejects_other_replay_types() {
+        let header = Header::versioned(868, 12).str("TAGame.Demo_TA").none();
+        assert_eq!(parse(header).unwrap_err(), "Unsupported replay type: TAGame.Demo_TA");
+    }
+}
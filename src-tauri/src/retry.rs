@@ -0,0 +1,346 @@
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::agent::AgentContext;
+use crate::config;
+use crate::history;
+use crate::staging;
+use crate::types::{
+    AutoRetryPolicy, BulkRetryResult, ErrorClass, HistoryQuery, UploadRecord, UploadStatus,
+};
+use crate::uploader::{self, Uploader, UPLOAD_HELD_ERROR};
+
+/// How often the background policy looks for failed uploads to retry
+const AUTO_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Kind of failure of a failed record
+/// Records saved before errors were classified are classified from their message
+pub fn error_class(record: &UploadRecord) -> ErrorClass {
+    record
+        .error_class
+        .unwrap_or_else(|| classify_message(record.error_message.as_deref().unwrap_or_default()))
+}
+
+/// Classify the error message of a record saved without an error class
+fn classify_message(message: &str) -> ErrorClass {
+    if message == uploader::SESSION_EXPIRED_ERROR || message == uploader::NOT_LOGGED_IN_ERROR {
+        return ErrorClass::Auth;
+    }
+    if message.starts_with("Failed to read file") || message.starts_with("Replay file no longer exists") {
+        return ErrorClass::Permanent;
+    }
+
+    // "Upload failed (422 Unprocessable Entity): ..."
+    let status = message
+        .strip_prefix("Upload failed (")
+        .and_then(|rest| rest.split_whitespace().next())
+        .and_then(|code| code.trim_end_matches(')').parse::<u16>().ok());
+    match status {
+        Some(408 | 429) => ErrorClass::Transient,
+        Some(400..=499) => ErrorClass::Permanent,
+        _ => ErrorClass::Transient,
+    }
+}
+
+/// Whether a record's replay can still be uploaded, from the original or its staged copy
+fn replay_available(ctx: &AgentContext, record: &UploadRecord) -> bool {
+    let file_path = Path::new(&record.file_path);
+    file_path.exists() || staging::staged_copy(ctx, file_path).is_some()
+}
+
+/// Upload a failed record's replay again, continuing the same record
+pub async fn retry_record(ctx: &AgentContext, record_id: &str) -> Result<UploadRecord, String> {
+    let record = history::store(ctx)?
+        .get(record_id)?
+        .ok_or("Record not found")?;
+
+    // Retried from the staged copy when the original is gone
+    if !replay_available(ctx, &record) {
+        return Err("Replay file no longer exists".to_string());
+    }
+
+    // Bulk and background retries don't overlap, released even if the retry is cancelled
+    let Some(_retrying) = ctx.try_start_retry(record_id) else {
+        return Err("Retry already in progress".to_string());
+    };
+
+    Uploader::new().retry_record(ctx, record).await
+}
+
+/// Retry records one after another
+/// With `skip_permanent`, records whose error can't be fixed by retrying are skipped
+pub async fn retry_records(
+    ctx: &AgentContext,
+    record_ids: &[String],
+    skip_permanent: bool,
+) -> Result<BulkRetryResult, String> {
+    let mut result = BulkRetryResult::default();
+
+    for record_id in record_ids {
+        let Some(record) = history::store(ctx)?.get(record_id)? else {
+            result.skipped += 1;
+            continue;
+        };
+
+        let retryable = matches!(record.status, UploadStatus::Failed)
+            && !(skip_permanent && error_class(&record) == ErrorClass::Permanent);
+        if !retryable {
+            result.skipped += 1;
+            continue;
+        }
+
+        match retry_record(ctx, record_id).await {
+            Ok(_) => result.succeeded += 1,
+            Err(e) if e == UPLOAD_HELD_ERROR => result.skipped += 1,
+            Err(e) => {
+                tracing::warn!("Retry of {} failed: {}", record.filename, e);
+                result.failed += 1;
+            }
+        }
+    }
+
+    tracing::info!(
+        "Bulk retry: {} succeeded, {} failed, {} skipped",
+        result.succeeded,
+        result.failed,
+        result.skipped
+    );
+
+    Ok(result)
+}
+
+/// IDs of all failed records, newest first
+pub fn failed_record_ids(ctx: &AgentContext) -> Result<Vec<String>, String> {
+    let query = HistoryQuery {
+        statuses: vec![UploadStatus::Failed],
+        ..Default::default()
+    };
+    Ok(history::store(ctx)?
+        .query_all(&query)?
+        .into_iter()
+        .map(|record| record.id)
+        .collect())
+}
+
+/// Background retry schedule of one failed record
+struct RetryState {
+    /// Failed retry rounds so far
+    rounds: u32,
+    /// Set once retrying can't help anymore (e.g. the file was deleted)
+    gave_up: bool,
+    next_attempt_at: DateTime<Utc>,
+}
+
+impl RetryState {
+    fn new(record: &UploadRecord, policy: &AutoRetryPolicy, now: DateTime<Utc>) -> Self {
+        let last_attempt = record
+            .attempt_log
+            .last()
+            .map(|attempt| attempt.started_at.as_str())
+            .unwrap_or(&record.created_at);
+        let last_attempt_at = DateTime::parse_from_rfc3339(last_attempt)
+            .map(|dt| dt.with_timezone(&Utc))
+            .unwrap_or(now);
+        Self {
+            rounds: 0,
+            gave_up: false,
+            next_attempt_at: last_attempt_at + retry_delay(policy, 0),
+        }
+    }
+}
+
+/// Delay before the next round: the initial delay doubled per failed round, capped
+fn retry_delay(policy: &AutoRetryPolicy, rounds: u32) -> chrono::Duration {
+    let minutes = (policy.initial_delay_minutes as u64)
+        .saturating_mul(1u64 << rounds.min(20))
+        .min(policy.max_delay_minutes as u64);
+    chrono::Duration::minutes(minutes as i64)
+}
+
+/// Start the background retry policy
+/// The policy is read from the config on every round, so changes apply without a restart
+pub fn start_auto_retry(ctx: &AgentContext) {
+    let task_ctx = ctx.clone();
+    ctx.spawn(async move {
+        let mut schedule = HashMap::new();
+        loop {
+            tokio::time::sleep(AUTO_RETRY_INTERVAL).await;
+            if let Err(e) = auto_retry_round(&task_ctx, &mut schedule).await {
+                tracing::warn!("Automatic retry failed: {}", e);
+            }
+        }
+    });
+}
+
+async fn auto_retry_round(
+    ctx: &AgentContext,
+    schedule: &mut HashMap<String, RetryState>,
+) -> Result<(), String> {
+    let config = config::load_config(ctx)?;
+    // Retries are refused in a dry run
+    let dry_run = config::is_dry_run(&config);
+    let policy = config.auto_retry;
+    if !policy.enabled || dry_run {
+        schedule.clear();
+        return Ok(());
+    }
+
+    // Uploads wait for the user to log in again, or the agent is exiting
+    if ctx.upload_queue.is_paused() || ctx.is_shutting_down() {
+        return Ok(());
+    }
+
+    // Records keep their creation time across retries, older ones are given up on
+    let now = ctx.now();
+    let max_age = chrono::Duration::hours(policy.max_age_hours as i64);
+    let query = HistoryQuery {
+        statuses: vec![UploadStatus::Failed],
+        from: Some((now - max_age).to_rfc3339()),
+        ..Default::default()
+    };
+    let failed = history::store(ctx)?.query_all(&query)?;
+
+    let ids: HashSet<&str> = failed.iter().map(|record| record.id.as_str()).collect();
+    schedule.retain(|id, _| ids.contains(id.as_str()));
+
+    for record in &failed {
+        if error_class(record) != ErrorClass::Transient {
+            continue;
+        }
+
+        let state = schedule
+            .entry(record.id.clone())
+            .or_insert_with(|| RetryState::new(record, &policy, now));
+        if state.gave_up || now < state.next_attempt_at {
+            continue;
+        }
+        if !replay_available(ctx, record) {
+            tracing::info!("Not retrying {}, the replay is gone", record.filename);
+            state.gave_up = true;
+            continue;
+        }
+
+        tracing::info!(
+            "Automatically retrying {} (round {})",
+            record.filename,
+            state.rounds + 1
+        );
+
+        match retry_record(ctx, &record.id).await {
+            Ok(_) => {
+                schedule.remove(&record.id);
+            }
+            // The session expired, remaining retries wait for the next login
+            Err(e) if e == UPLOAD_HELD_ERROR => return Ok(()),
+            Err(e) => {
+                tracing::warn!("Automatic retry of {} failed: {}", record.filename, e);
+                // The failed upload recorded why it failed
+                let failed = history::store(ctx)?.get(&record.id)?;
+                state.gave_up = failed.is_some_and(|failed| error_class(&failed) == ErrorClass::Permanent);
+                state.rounds += 1;
+                state.next_attempt_at = ctx.now() + retry_delay(&policy, state.rounds);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::testing::TestAgent;
+    use serde_json::{json, Value};
+
+    fn policy(initial: u32, max: u32) -> AutoRetryPolicy {
+        AutoRetryPolicy {
+            enabled: true,
+            initial_delay_minutes: initial,
+            max_delay_minutes: max,
+            max_age_hours: 72,
+        }
+    }
+
+    fn failed(fields: Value) -> UploadRecord {
+        let mut record = json!({
+            "id": "failed",
+            "filename": "a.replay",
+            "filePath": "/replays/a.replay",
+            "status": "failed",
+            "attempts": 1,
+            "createdAt": "2024-01-15T20:00:00Z",
+        });
+        record.as_object_mut().unwrap().extend(fields.as_object().unwrap().clone());
+        serde_json::from_value(record).unwrap()
+    }
+
+    #[test]
+    fn retry_delay_doubles_up_to_the_maximum() {
+        let policy = policy(5, 60);
+        let minutes: Vec<i64> = (0..6).map(|rounds| retry_delay(&policy, rounds).num_minutes()).collect();
+        assert_eq!(minutes, vec![5, 10, 20, 40, 60, 60]);
+
+        // Many failed rounds don't overflow
+        assert_eq!(retry_delay(&policy, u32::MAX).num_minutes(), 60);
+        assert_eq!(retry_delay(&AutoRetryPolicy::default(), 100).num_minutes(), 6 * 60);
+    }
+
+    #[test]
+    fn first_retry_counts_from_the_last_attempt() {
+        let now = "2024-01-16T00:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let record = failed(json!({
+            "attemptLog": [
+                { "startedAt": "2024-01-15T20:00:00Z", "durationMs": 10 },
+                { "startedAt": "2024-01-15T21:00:00Z", "durationMs": 10 },
+            ],
+        }));
+        let state = RetryState::new(&record, &policy(5, 60), now);
+        assert_eq!(state.next_attempt_at.to_rfc3339(), "2024-01-15T21:05:00+00:00");
+
+        let record = failed(json!({}));
+        let state = RetryState::new(&record, &policy(5, 60), now);
+        assert_eq!(state.next_attempt_at.to_rfc3339(), "2024-01-15T20:05:00+00:00");
+    }
+
+    #[test]
+    fn records_keep_their_error_class() {
+        let record = failed(json!({ "errorMessage": "Network error", "errorClass": "permanent" }));
+        assert_eq!(error_class(&record), ErrorClass::Permanent);
+    }
+
+    #[test]
+    fn classifies_records_saved_without_a_class() {
+        let class = |message: &str| error_class(&failed(json!({ "errorMessage": message })));
+        assert_eq!(class(uploader::SESSION_EXPIRED_ERROR), ErrorClass::Auth);
+        assert_eq!(class(uploader::NOT_LOGGED_IN_ERROR), ErrorClass::Auth);
+        assert_eq!(class("Failed to read file: denied"), ErrorClass::Permanent);
+        assert_eq!(class("Replay file no longer exists"), ErrorClass::Permanent);
+        assert_eq!(class("Upload failed (422 Unprocessable Entity): bad"), ErrorClass::Permanent);
+        assert_eq!(class("Upload failed (429 Too Many Requests): slow down"), ErrorClass::Transient);
+        assert_eq!(class("Upload failed (503 Service Unavailable): "), ErrorClass::Transient);
+        assert_eq!(class("Network error: timed out"), ErrorClass::Transient);
+        assert_eq!(error_class(&failed(json!({}))), ErrorClass::Transient);
+    }
+
+    #[tokio::test]
+    async fn one_retry_of_a_record_at_a_time() {
+        let agent = TestAgent::new();
+        let replay = agent.file("a.replay", b"not really a replay");
+        let record = failed(json!({ "filePath": replay.to_string_lossy() }));
+        history::store(&agent.ctx).unwrap().save(&record).unwrap();
+
+        let retrying = agent.ctx.try_start_retry("failed").unwrap();
+        assert!(agent.ctx.try_start_retry("failed").is_none());
+        assert_eq!(
+            retry_record(&agent.ctx, "failed").await.unwrap_err(),
+            "Retry already in progress"
+        );
+
+        // Released with the guard, nobody is logged in so the retry is held
+        drop(retrying);
+        assert_eq!(retry_record(&agent.ctx, "failed").await.unwrap_err(), UPLOAD_HELD_ERROR);
+        assert!(agent.ctx.try_start_retry("failed").is_some());
+    }
+}
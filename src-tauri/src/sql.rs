@@ -0,0 +1,311 @@
+use base64::Engine;
+use rusqlite::types::Value;
+use rusqlite::{params_from_iter, Connection, TransactionBehavior};
+use std::path::Path;
+
+/// How long to wait for another connection's write lock
+const BUSY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Open (or create) a SQLite database, check its integrity and apply pending migrations
+/// Migrations are applied in order and tracked with `PRAGMA user_version`
+pub fn open_database(path: &Path, migrations: &[&str]) -> Result<Connection, String> {
+    let error = |e: rusqlite::Error| format!("database error: {}", e);
+    let mut conn = Connection::open(path).map_err(error)?;
+
+    // Wait for other processes' locks (CLI, headless agent) instead of failing,
+    // and let them read while we write
+    conn.busy_timeout(BUSY_TIMEOUT).map_err(error)?;
+    conn.pragma_update(None, "journal_mode", "WAL")
+        .map_err(error)?;
+
+    let version: usize = conn
+        .query_row("PRAGMA user_version", [], |row| row.get(0))
+        .map_err(error)?;
+
+    let check: String = conn
+        .query_row("PRAGMA quick_check", [], |row| row.get(0))
+        .map_err(error)?;
+    if check != "ok" {
+        return Err(format!("database is corrupt: {}", check));
+    }
+
+    if version < migrations.len() {
+        // Another connection may be migrating too, the version is read again under the write lock
+        let tx = conn
+            .transaction_with_behavior(TransactionBehavior::Immediate)
+            .map_err(error)?;
+        let version: usize = tx
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .map_err(error)?;
+        for (index, migration) in migrations.iter().enumerate().skip(version) {
+            tx.execute_batch(&format!("{} PRAGMA user_version = {};", migration, index + 1))
+                .map_err(error)?;
+            tracing::info!("Applied migration {} to {}", index + 1, path.display());
+        }
+        tx.commit().map_err(error)?;
+    }
+
+    Ok(conn)
+}
+
+/// Convert an RFC 3339 timestamp to unix milliseconds
+pub fn timestamp_millis(value: &str) -> Result<i64, String> {
+    chrono::DateTime::parse_from_rfc3339(value)
+        .map(|dt| dt.timestamp_millis())
+        .map_err(|_| format!("Invalid date: {}", value))
+}
+
+/// `LIKE` pattern matching values that contain `text`, used with `ESCAPE '\'`
+pub fn contains_pattern(text: &str) -> String {
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+/// Conditions of a query and the values bound to their placeholders
+#[derive(Default)]
+pub struct Filter {
+    conditions: Vec<String>,
+    values: Vec<Value>,
+}
+
+impl Filter {
+    /// Add a condition with one value per `?` placeholder
+    pub fn add(&mut self, condition: impl Into<String>, values: impl IntoIterator<Item = Value>) {
+        self.conditions.push(condition.into());
+        self.values.extend(values);
+    }
+
+    /// Add a `column IN (...)` condition, skipped when there are no values
+    pub fn add_in(&mut self, column: &str, values: impl IntoIterator<Item = Value>) {
+        let values: Vec<Value> = values.into_iter().collect();
+        if values.is_empty() {
+            return;
+        }
+        let placeholders = vec!["?"; values.len()].join(", ");
+        self.add(format!("{} IN ({})", column, placeholders), values);
+    }
+
+    /// The conditions as a `WHERE` clause, empty without conditions
+    pub fn where_sql(&self) -> String {
+        if self.conditions.is_empty() {
+            String::new()
+        } else {
+            format!(" WHERE {}", self.conditions.join(" AND "))
+        }
+    }
+}
+
+/// Order of a paginated query: a sort expression, then a unique key breaking ties
+pub struct Order<'a> {
+    pub sort: &'a str,
+    pub key: &'a str,
+    pub descending: bool,
+}
+
+/// One page of the `data` column of a table
+pub struct Page {
+    pub rows: Vec<String>,
+    /// Cursor of the next page, None on the last one
+    pub next_cursor: Option<String>,
+    /// Rows matching the filter on all pages
+    pub total: u64,
+}
+
+/// Read a page of a table with keyset pagination
+/// Pages continue after the (sort value, key) of the previous page's last row, so rows
+/// written in between don't shift them
+pub fn page(
+    conn: &Connection,
+    table: &str,
+    mut filter: Filter,
+    order: &Order,
+    cursor: Option<&str>,
+    limit: u32,
+    db_error: fn(rusqlite::Error) -> String,
+) -> Result<Page, String> {
+    // Total matching rows, ignoring the cursor
+    let total: i64 = conn
+        .query_row(
+            &format!("SELECT COUNT(*) FROM {}{}", table, filter.where_sql()),
+            params_from_iter(filter.values.iter()),
+            |row| row.get(0),
+        )
+        .map_err(db_error)?;
+
+    let (direction, comparison) = if order.descending {
+        ("DESC", "<")
+    } else {
+        ("ASC", ">")
+    };
+
+    if let Some(cursor) = cursor {
+        let (sort_value, key) = decode_cursor(cursor)?;
+        filter.add(
+            format!("({}, {}) {} (?, ?)", order.sort, order.key, comparison),
+            [sort_value, Value::Text(key)],
+        );
+    }
+
+    let sql = format!(
+        "SELECT data, {sort}, {key} FROM {table}{where_sql} ORDER BY {sort} {dir}, {key} {dir} LIMIT {limit}",
+        sort = order.sort,
+        key = order.key,
+        table = table,
+        where_sql = filter.where_sql(),
+        dir = direction,
+        limit = limit + 1,
+    );
+
+    let mut stmt = conn.prepare(&sql).map_err(db_error)?;
+    let mut rows = stmt
+        .query_map(params_from_iter(filter.values.iter()), |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, Value>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })
+        .map_err(db_error)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(db_error)?;
+
+    // One row past the limit tells whether there's a next page
+    let mut next_cursor = None;
+    if rows.len() > limit as usize {
+        rows.truncate(limit as usize);
+        if let Some((_, sort_value, key)) = rows.last() {
+            next_cursor = Some(encode_cursor(sort_value, key));
+        }
+    }
+
+    Ok(Page {
+        rows: rows.into_iter().map(|(data, _, _)| data).collect(),
+        next_cursor,
+        total: total as u64,
+    })
+}
+
+/// Encode the position after a row as an opaque cursor
+fn encode_cursor(sort_value: &Value, key: &str) -> String {
+    let sort_value = match sort_value {
+        Value::Integer(n) => serde_json::json!(n),
+        Value::Text(s) => serde_json::json!(s),
+        _ => serde_json::Value::Null,
+    };
+    let cursor = serde_json::json!([sort_value, key]).to_string();
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(cursor)
+}
+
+fn decode_cursor(cursor: &str) -> Result<(Value, String), String> {
+    let invalid = || "Invalid page cursor".to_string();
+
+    let bytes = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(cursor)
+        .map_err(|_| invalid())?;
+    let (sort_value, key): (serde_json::Value, String) =
+        serde_json::from_slice(&bytes).map_err(|_| invalid())?;
+
+    let sort_value = match sort_value {
+        serde_json::Value::Number(n) => Value::Integer(n.as_i64().ok_or_else(invalid)?),
+        serde_json::Value::String(s) => Value::Text(s),
+        _ => return Err(invalid()),
+    };
+
+    Ok((sort_value, key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn db_error(e: rusqlite::Error) -> String {
+        e.to_string()
+    }
+
+    fn table() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE items (id TEXT PRIMARY KEY, rank INTEGER, data TEXT)")
+            .unwrap();
+        for n in 0..25 {
+            // Ranks repeat, so pages have to break ties on the ID
+            conn.execute(
+                "INSERT INTO items VALUES (?1, ?2, ?1)",
+                rusqlite::params![format!("item-{:02}", n), n / 4],
+            )
+            .unwrap();
+        }
+        conn
+    }
+
+    fn all_pages(conn: &Connection, descending: bool, filter: impl Fn() -> Filter) -> Vec<String> {
+        let order = Order {
+            sort: "rank",
+            key: "id",
+            descending,
+        };
+        let mut rows = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = page(conn, "items", filter(), &order, cursor.as_deref(), 4, db_error).unwrap();
+            assert!(page.rows.len() <= 4);
+            rows.extend(page.rows);
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => return rows,
+            }
+        }
+    }
+
+    #[test]
+    fn pages_cover_every_row_once_in_order() {
+        let conn = table();
+        let expected: Vec<String> = (0..25).map(|n| format!("item-{:02}", n)).collect();
+        assert_eq!(all_pages(&conn, false, Filter::default), expected);
+
+        let mut reversed = expected;
+        reversed.reverse();
+        assert_eq!(all_pages(&conn, true, Filter::default), reversed);
+    }
+
+    #[test]
+    fn pages_apply_the_filter() {
+        let conn = table();
+        let filter = || {
+            let mut filter = Filter::default();
+            filter.add_in("rank", [Value::Integer(1), Value::Integer(5)]);
+            filter.add("id <> ?", [Value::Text("item-05".to_string())]);
+            filter
+        };
+        assert_eq!(
+            all_pages(&conn, false, filter),
+            vec!["item-04", "item-06", "item-07", "item-20", "item-21", "item-22", "item-23"]
+        );
+
+        let order = Order {
+            sort: "rank",
+            key: "id",
+            descending: false,
+        };
+        let first = page(&conn, "items", filter(), &order, None, 4, db_error).unwrap();
+        assert_eq!(first.total, 7);
+        assert!(page(&conn, "items", filter(), &order, Some("not a cursor"), 4, db_error).is_err());
+    }
+
+    #[test]
+    fn builds_where_clauses_and_patterns() {
+        let mut filter = Filter::default();
+        assert_eq!(filter.where_sql(), "");
+        filter.add_in("status", []);
+        assert_eq!(filter.where_sql(), "");
+        filter.add_in("status", [Value::Text("failed".to_string()), Value::Text("completed".to_string())]);
+        filter.add("created_ts >= ?", [Value::Integer(1)]);
+        assert_eq!(filter.where_sql(), " WHERE status IN (?, ?) AND created_ts >= ?");
+
+        assert_eq!(contains_pattern("50%_off\\"), "%50\\%\\_off\\\\%");
+        assert_eq!(timestamp_millis("1970-01-01T00:00:01Z"), Ok(1000));
+        assert_eq!(timestamp_millis("yesterday"), Err("Invalid date: yesterday".to_string()));
+    }
+}
@@ -0,0 +1,385 @@
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use crate::agent::AgentContext;
+use crate::config;
+
+/// Staged files and folders being uploaded, with how many uploads use them
+/// They are never evicted, whatever the cache size
+#[derive(Default)]
+pub struct StagedFiles {
+    in_use: Mutex<HashMap<PathBuf, usize>>,
+}
+
+impl StagedFiles {
+    fn pin(self: &Arc<Self>, path: PathBuf) -> StagedGuard {
+        *self.in_use.lock().unwrap().entry(path.clone()).or_default() += 1;
+        StagedGuard {
+            path,
+            files: self.clone(),
+        }
+    }
+
+    fn is_in_use(&self, path: &Path) -> bool {
+        self.in_use
+            .lock()
+            .unwrap()
+            .keys()
+            .any(|used| path.starts_with(used))
+    }
+}
+
+/// Keeps a staged copy, or a folder of them, from being evicted while in use
+pub struct StagedGuard {
+    path: PathBuf,
+    files: Arc<StagedFiles>,
+}
+
+impl StagedGuard {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for StagedGuard {
+    fn drop(&mut self) {
+        let mut in_use = self.files.in_use.lock().unwrap();
+        if let Some(count) = in_use.get_mut(&self.path) {
+            *count -= 1;
+            if *count == 0 {
+                in_use.remove(&self.path);
+            }
+        }
+    }
+}
+
+/// Folder of the staging cache: copies of replays being uploaded, kept until
+/// their upload succeeds so retries don't depend on the original files
+fn staging_dir(ctx: &AgentContext) -> Result<PathBuf, String> {
+    Ok(ctx.storage.data_dir()?.join("staging"))
+}
+
+/// Whether a file is in the staging cache (a staged copy or an extracted replay)
+pub fn is_staged(ctx: &AgentContext, path: &Path) -> bool {
+    staging_dir(ctx).is_ok_and(|dir| path.starts_with(dir))
+}
+
+/// Where the staged copy of a replay goes, one folder per original path
+/// so the copy keeps the file name it's uploaded with
+fn copy_path(ctx: &AgentContext, original: &Path) -> Result<PathBuf, String> {
+    let key = format!("{:x}", Sha256::digest(original.to_string_lossy().as_bytes()));
+    let file_name = original
+        .file_name()
+        .ok_or_else(|| format!("Not a file: {}", original.display()))?;
+    Ok(staging_dir(ctx)?.join(&key[..16]).join(file_name))
+}
+
+/// Staged copy of a replay, if there is one
+pub fn staged_copy(ctx: &AgentContext, original: &Path) -> Option<PathBuf> {
+    if is_staged(ctx, original) {
+        return Some(original.to_path_buf()).filter(|path| path.is_file());
+    }
+    copy_path(ctx, original).ok().filter(|path| path.is_file())
+}
+
+/// New folder in the staging cache for files staged together (an archive's replays)
+/// Room is made for `reserve` bytes first
+pub fn new_folder(ctx: &AgentContext, reserve: u64) -> Result<StagedGuard, String> {
+    prune(ctx, reserve);
+
+    let dir = staging_dir(ctx)?.join(uuid::Uuid::new_v4().to_string());
+    std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create staging folder: {}", e))?;
+    Ok(ctx.staged.pin(dir))
+}
+
+/// Copy a replay to the staging cache before uploading it
+///
+/// The copy is refreshed while the original exists; once the original is gone
+/// (deleted or rotated by the game) the previous copy is used. Files already in
+/// the cache are used as they are. The copy is kept until `remove` is called.
+pub fn stage(ctx: &AgentContext, original: &Path) -> Result<StagedGuard, String> {
+    let target = if is_staged(ctx, original) {
+        original.to_path_buf()
+    } else {
+        copy_path(ctx, original)?
+    };
+    // Pinned first, so making room can't evict it
+    let guard = ctx.staged.pin(target.clone());
+
+    if target != original && original.is_file() {
+        // A previous copy is replaced, only the difference needs room
+        let size = std::fs::metadata(original).map(|m| m.len()).unwrap_or(0);
+        let previous = std::fs::metadata(&target).map(|m| m.len()).unwrap_or(0);
+        prune(ctx, size.saturating_sub(previous));
+
+        let dir = target.parent().unwrap_or(&target);
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create staging folder: {}", e))?;
+        // Copied next to the target first, a failed copy never replaces a good one
+        let tmp = target.with_extension("staging");
+        std::fs::copy(original, &tmp)
+            .and_then(|_| std::fs::rename(&tmp, &target))
+            .map_err(|e| {
+                let _ = std::fs::remove_file(&tmp);
+                format!("Failed to stage {}: {}", original.display(), e)
+            })?;
+    } else if !target.is_file() {
+        return Err("Replay file no longer exists".to_string());
+    }
+
+    // The modification time tracks the last use, for expiry and eviction
+    touch(ctx, &target);
+    Ok(guard)
+}
+
+/// Delete the staged copy of a replay once it's uploaded (or a staged file itself)
+pub fn remove(ctx: &AgentContext, original: &Path) {
+    let Ok(staging) = staging_dir(ctx) else {
+        return;
+    };
+    let staged = if original.starts_with(&staging) {
+        original.to_path_buf()
+    } else {
+        match copy_path(ctx, original) {
+            Ok(path) => path,
+            Err(_) => return,
+        }
+    };
+
+    match std::fs::remove_file(&staged) {
+        Ok(()) => remove_empty_folders(&staging, &staged),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => tracing::warn!("Failed to remove staged replay {}: {}", staged.display(), e),
+    }
+}
+
+/// Delete the folders of a removed file up to the staging folder, while they're empty
+fn remove_empty_folders(staging: &Path, removed: &Path) {
+    for dir in removed.ancestors().skip(1) {
+        if dir == staging || std::fs::remove_dir(dir).is_err() {
+            break;
+        }
+    }
+}
+
+/// Expire and evict staged copies in the background
+pub fn spawn_prune(ctx: &AgentContext) {
+    let task_ctx = ctx.clone();
+    ctx.spawn_blocking(move || prune(&task_ctx, 0));
+}
+
+/// Delete staged copies unused for longer than the configured days, then the
+/// least recently used ones until the cache plus `reserve` bytes fits its size cap
+/// Copies in use are kept.
+pub fn prune(ctx: &AgentContext, reserve: u64) {
+    let Ok(staging) = staging_dir(ctx) else {
+        return;
+    };
+    let policy = config::load_config(ctx)
+        .map(|config| config.staging)
+        .unwrap_or_default();
+
+    let mut files = Vec::new();
+    list_files(&staging, &mut files);
+
+    let expires = SystemTime::from(ctx.now())
+        .checked_sub(Duration::from_secs(policy.max_age_days as u64 * 24 * 60 * 60))
+        .unwrap_or(SystemTime::UNIX_EPOCH);
+    let max_size = policy.max_size_mb as u64 * 1024 * 1024;
+    let mut size: u64 = files.iter().map(|(_, len, _)| len).sum();
+
+    // Least recently used first
+    files.sort_by_key(|(_, _, modified)| *modified);
+
+    let mut removed = 0;
+    for (path, len, modified) in files {
+        let expired = modified < expires;
+        if !expired && size + reserve <= max_size {
+            continue;
+        }
+        if ctx.staged.is_in_use(&path) {
+            continue;
+        }
+
+        match std::fs::remove_file(&path) {
+            Ok(()) => {
+                size -= len;
+                removed += 1;
+                remove_empty_folders(&staging, &path);
+            }
+            Err(e) => tracing::warn!("Failed to evict staged replay {}: {}", path.display(), e),
+        }
+    }
+
+    if removed > 0 {
+        tracing::info!(
+            "Removed {} staged replays, {} MB left in the staging cache",
+            removed,
+            size / (1024 * 1024)
+        );
+    }
+}
+
+/// Files under a folder with their size and modification time
+fn list_files(dir: &Path, files: &mut Vec<(PathBuf, u64, SystemTime)>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if metadata.is_dir() {
+            list_files(&entry.path(), files);
+        } else {
+            let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            files.push((entry.path(), metadata.len(), modified));
+        }
+    }
+}
+
+fn touch(ctx: &AgentContext, path: &Path) {
+    let result = File::options()
+        .write(true)
+        .open(path)
+        .and_then(|file| file.set_modified(ctx.now().into()));
+    if let Err(e) = result {
+        tracing::debug!("Failed to touch {}: {}", path.display(), e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::testing::TestAgent;
+    use crate::types::{AppConfig, StagingPolicy};
+
+    struct TestCache {
+        agent: TestAgent,
+    }
+
+    impl TestCache {
+        /// Staging cache of `max_size_mb` with copies expiring after a week
+        fn new(max_size_mb: u32) -> Self {
+            let config = AppConfig {
+                staging: StagingPolicy {
+                    max_size_mb,
+                    max_age_days: 7,
+                },
+                ..Default::default()
+            };
+            let agent = TestAgent::new().with_config(&config);
+            std::fs::create_dir_all(agent.dir.join("replays")).unwrap();
+            Self { agent }
+        }
+
+        /// Write a replay of `kb` kilobytes
+        fn replay(&self, name: &str, kb: usize) -> PathBuf {
+            let path = self.agent.dir.join("replays").join(name);
+            std::fs::write(&path, vec![name.len() as u8; kb * 1024]).unwrap();
+            path
+        }
+
+        /// Stage a replay an hour after the previous one, and release it
+        fn stage(&self, original: &Path) {
+            self.agent.clock.advance(chrono::Duration::hours(1));
+            stage(&self.agent.ctx, original).unwrap();
+        }
+
+        fn is_staged(&self, original: &Path) -> bool {
+            staged_copy(&self.agent.ctx, original).is_some()
+        }
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_copies() {
+        let cache = TestCache::new(1);
+        let [a, b, c, d] = ["a.replay", "b.replay", "c.replay", "d.replay"].map(|name| cache.replay(name, 300));
+        cache.stage(&a);
+        cache.stage(&b);
+        cache.stage(&c);
+        // Used again, b is now the oldest
+        cache.stage(&a);
+
+        // Room for d: the cache can hold three
+        cache.stage(&d);
+        assert!(!cache.is_staged(&b));
+        assert!(cache.is_staged(&c));
+        assert!(cache.is_staged(&a));
+        assert!(cache.is_staged(&d));
+
+        // Room for 600 KB more
+        prune(&cache.agent.ctx, 600 * 1024);
+        assert!(!cache.is_staged(&c));
+        assert!(!cache.is_staged(&a));
+        assert!(cache.is_staged(&d));
+    }
+
+    #[test]
+    fn copies_in_use_are_never_evicted() {
+        let cache = TestCache::new(1);
+        let [a, b, c] = ["a.replay", "b.replay", "c.replay"].map(|name| cache.replay(name, 400));
+        let in_use = stage(&cache.agent.ctx, &a).unwrap();
+        cache.stage(&b);
+
+        // a is the oldest but being uploaded
+        cache.stage(&c);
+        assert!(cache.is_staged(&a));
+        assert!(!cache.is_staged(&b));
+        assert!(cache.is_staged(&c));
+
+        // Expired copies too
+        cache.agent.clock.advance(chrono::Duration::days(8));
+        prune(&cache.agent.ctx, 0);
+        assert!(cache.is_staged(&a));
+        assert!(!cache.is_staged(&c));
+
+        drop(in_use);
+        prune(&cache.agent.ctx, 0);
+        assert!(!cache.is_staged(&a));
+
+        // Folders of archive replays as well
+        let folder = new_folder(&cache.agent.ctx, 0).unwrap();
+        std::fs::write(folder.path().join("extracted.replay"), vec![0; 2048 * 1024]).unwrap();
+        prune(&cache.agent.ctx, 0);
+        assert!(folder.path().join("extracted.replay").exists());
+    }
+
+    #[test]
+    fn new_folders_make_room_first() {
+        let cache = TestCache::new(1);
+        let a = cache.replay("a.replay", 600);
+        cache.stage(&a);
+
+        let _folder = new_folder(&cache.agent.ctx, 600 * 1024).unwrap();
+        assert!(!cache.is_staged(&a));
+    }
+
+    #[test]
+    fn uploads_fall_back_to_the_copy_once_the_original_is_gone() {
+        let cache = TestCache::new(10);
+        let original = cache.replay("a.replay", 10);
+        let contents = std::fs::read(&original).unwrap();
+        cache.stage(&original);
+
+        std::fs::remove_file(&original).unwrap();
+        let staged = stage(&cache.agent.ctx, &original).unwrap();
+        assert_ne!(staged.path(), original);
+        assert_eq!(staged.path().file_name(), original.file_name());
+        assert_eq!(std::fs::read(staged.path()).unwrap(), contents);
+        drop(staged);
+
+        // Gone for good once uploaded
+        remove(&cache.agent.ctx, &original);
+        assert!(!cache.is_staged(&original));
+        assert_eq!(
+            stage(&cache.agent.ctx, &original).err().as_deref(),
+            Some("Replay file no longer exists")
+        );
+    }
+}
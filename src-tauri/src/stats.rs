@@ -0,0 +1,375 @@
+use chrono::{DateTime, Datelike, Local, NaiveDate, Utc};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use crate::retry;
+use crate::types::{
+    ErrorClass, FailureBreakdown, StatsBucket, StatsCounts, StatsInterval, StatsQuery, StatsSplit,
+    UploadRecord, UploadReport, UploadStatus,
+};
+
+/// Keep reports small enough to chart
+const MAX_BUCKETS: usize = 1000;
+
+impl StatsCounts {
+    fn add(&mut self, record: &UploadRecord) {
+        match record.status {
+            UploadStatus::Completed => {
+                self.completed += 1;
+                self.bytes += record.file_size.unwrap_or(0);
+            }
+            UploadStatus::Failed => self.failed += 1,
+            _ => {}
+        }
+    }
+}
+
+/// Local date a record was created on
+fn local_date(timestamp: &str) -> Option<NaiveDate> {
+    DateTime::parse_from_rfc3339(timestamp)
+        .ok()
+        .map(|dt| dt.with_timezone(&Local).date_naive())
+}
+
+/// First day of the bucket containing a date
+fn bucket_start(date: NaiveDate, interval: StatsInterval) -> NaiveDate {
+    match interval {
+        StatsInterval::Day => date,
+        StatsInterval::Week => {
+            date - chrono::Duration::days(date.weekday().num_days_from_monday() as i64)
+        }
+    }
+}
+
+/// Build a report from the records created in the query's range
+pub fn build_report(
+    records: &[UploadRecord],
+    query: &StatsQuery,
+    now: DateTime<Utc>,
+) -> Result<UploadReport, String> {
+    let records: Vec<&UploadRecord> = records
+        .iter()
+        .filter(|r| matches!(r.status, UploadStatus::Completed | UploadStatus::Failed))
+        .collect();
+
+    let mut totals = StatsCounts::default();
+    let mut failures = FailureBreakdown::default();
+    let mut by_bucket: BTreeMap<NaiveDate, StatsCounts> = BTreeMap::new();
+    let mut by_folder: HashMap<String, StatsSplit> = HashMap::new();
+    let mut by_account: HashMap<String, StatsSplit> = HashMap::new();
+    let mut duration_ms: u64 = 0;
+    let mut timed_uploads: u64 = 0;
+    let mut timed_bytes: u64 = 0;
+
+    for record in &records {
+        totals.add(record);
+
+        if let Some(date) = local_date(&record.created_at) {
+            by_bucket
+                .entry(bucket_start(date, query.interval))
+                .or_default()
+                .add(record);
+        }
+
+        let folder = Path::new(&record.file_path)
+            .parent()
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_default();
+        by_folder
+            .entry(folder.clone())
+            .or_insert_with(|| StatsSplit {
+                key: folder,
+                label: None,
+                counts: StatsCounts::default(),
+            })
+            .counts
+            .add(record);
+
+        if let Some(account_id) = &record.account_id {
+            let split = by_account
+                .entry(account_id.clone())
+                .or_insert_with(|| StatsSplit {
+                    key: account_id.clone(),
+                    label: None,
+                    counts: StatsCounts::default(),
+                });
+            split.counts.add(record);
+            if split.label.is_none() {
+                split.label = record.account_username.clone();
+            }
+        }
+
+        match record.status {
+            UploadStatus::Failed => match retry::error_class(record) {
+                ErrorClass::Transient => failures.transient += 1,
+                ErrorClass::Auth => failures.auth += 1,
+                ErrorClass::Permanent => failures.permanent += 1,
+            },
+            UploadStatus::Completed => {
+                // Records from before the attempt log have no duration
+                let succeeded = record.attempt_log.iter().rev().find(|a| a.error.is_none());
+                if let Some(attempt) = succeeded {
+                    duration_ms += attempt.duration_ms;
+                    timed_uploads += 1;
+                    timed_bytes += record.file_size.unwrap_or(0);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Fill the range with empty buckets so charts have no gaps
+    let first = query
+        .from
+        .as_deref()
+        .and_then(local_date)
+        .or_else(|| by_bucket.keys().next().copied());
+    let last = query
+        .to
+        .as_deref()
+        .and_then(local_date)
+        .unwrap_or_else(|| now.with_timezone(&Local).date_naive());
+
+    let mut buckets = Vec::new();
+    if let Some(first) = first {
+        let step = match query.interval {
+            StatsInterval::Day => 1,
+            StatsInterval::Week => 7,
+        };
+        let mut start = bucket_start(first, query.interval);
+        while start <= last {
+            if buckets.len() == MAX_BUCKETS {
+                return Err(
+                    "Date range is too long, use a shorter range or weekly buckets".to_string(),
+                );
+            }
+            buckets.push(StatsBucket {
+                start: start.format("%Y-%m-%d").to_string(),
+                counts: by_bucket.remove(&start).unwrap_or_default(),
+            });
+            start += chrono::Duration::days(step);
+        }
+    }
+
+    let attempts = totals.completed + totals.failed;
+    let success_rate = if attempts > 0 {
+        (totals.completed as f32 / attempts as f32) * 100.0
+    } else {
+        0.0
+    };
+
+    let sorted = |splits: HashMap<String, StatsSplit>| {
+        let mut splits: Vec<StatsSplit> = splits.into_values().collect();
+        splits.sort_by(|a, b| {
+            b.counts
+                .completed
+                .cmp(&a.counts.completed)
+                .then_with(|| a.key.cmp(&b.key))
+        });
+        splits
+    };
+
+    Ok(UploadReport {
+        totals,
+        success_rate,
+        buckets,
+        average_duration_ms: (timed_uploads > 0).then(|| duration_ms / timed_uploads),
+        throughput_bytes_per_sec: (duration_ms > 0)
+            .then(|| timed_bytes as f64 / (duration_ms as f64 / 1000.0)),
+        failures,
+        by_folder: sorted(by_folder),
+        by_account: sorted(by_account),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::testing::now;
+    use chrono::{NaiveTime, TimeZone};
+    use serde_json::{json, Value};
+
+    /// Noon local time on a day, so buckets don't depend on the time zone
+    fn local_noon(date: &str) -> String {
+        let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap();
+        Local
+            .from_local_datetime(&date.and_time(NaiveTime::from_hms_opt(12, 0, 0).unwrap()))
+            .unwrap()
+            .to_rfc3339()
+    }
+
+    fn record(date: &str, status: &str, fields: Value) -> UploadRecord {
+        let mut record = json!({
+            "id": uuid::Uuid::new_v4().to_string(),
+            "filename": "a.replay",
+            "filePath": "/replays/a.replay",
+            "status": status,
+            "attempts": 1,
+            "createdAt": local_noon(date),
+            "fileSize": 1000,
+        });
+        record.as_object_mut().unwrap().extend(fields.as_object().unwrap().clone());
+        serde_json::from_value(record).unwrap()
+    }
+
+    fn query(from: &str, to: &str, interval: StatsInterval) -> StatsQuery {
+        StatsQuery {
+            from: Some(local_noon(from)),
+            to: Some(local_noon(to)),
+            interval,
+        }
+    }
+
+    #[test]
+    fn daily_buckets_cover_the_whole_range() {
+        let records = vec![
+            record("2024-01-15", "completed", json!({})),
+            record("2024-01-15", "failed", json!({})),
+            record("2024-01-17", "completed", json!({ "fileSize": 500 })),
+        ];
+        let report = build_report(&records, &query("2024-01-14", "2024-01-18", StatsInterval::Day), now()).unwrap();
+
+        let buckets: Vec<(&str, u32, u32, u64)> = report
+            .buckets
+            .iter()
+            .map(|b| (b.start.as_str(), b.counts.completed, b.counts.failed, b.counts.bytes))
+            .collect();
+        assert_eq!(
+            buckets,
+            vec![
+                ("2024-01-14", 0, 0, 0),
+                ("2024-01-15", 1, 1, 1000),
+                ("2024-01-16", 0, 0, 0),
+                ("2024-01-17", 1, 0, 500),
+                ("2024-01-18", 0, 0, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn weekly_buckets_start_on_monday() {
+        let records = vec![
+            record("2024-01-17", "completed", json!({})), // Wednesday
+            record("2024-01-21", "completed", json!({})), // Sunday, same week
+            record("2024-01-22", "completed", json!({})), // Monday
+        ];
+        let report = build_report(&records, &query("2024-01-17", "2024-01-30", StatsInterval::Week), now()).unwrap();
+
+        let buckets: Vec<(&str, u32)> = report
+            .buckets
+            .iter()
+            .map(|b| (b.start.as_str(), b.counts.completed))
+            .collect();
+        assert_eq!(buckets, vec![("2024-01-15", 2), ("2024-01-22", 1), ("2024-01-29", 0)]);
+    }
+
+    #[test]
+    fn range_starts_at_the_first_record_without_a_start() {
+        let records = vec![record("2024-01-15", "completed", json!({}))];
+        let query = StatsQuery {
+            to: Some(local_noon("2024-01-16")),
+            ..Default::default()
+        };
+        let report = build_report(&records, &query, now()).unwrap();
+        let starts: Vec<&str> = report.buckets.iter().map(|b| b.start.as_str()).collect();
+        assert_eq!(starts, vec!["2024-01-15", "2024-01-16"]);
+
+        let empty = build_report(&[], &StatsQuery::default(), now()).unwrap();
+        assert!(empty.buckets.is_empty());
+        assert_eq!(empty.success_rate, 0.0);
+    }
+
+    #[test]
+    fn rejects_ranges_with_too_many_buckets() {
+        let long = query("2020-01-01", "2024-01-01", StatsInterval::Day);
+        assert!(build_report(&[], &long, now()).is_err());
+
+        let weekly = query("2020-01-01", "2024-01-01", StatsInterval::Week);
+        assert_eq!(build_report(&[], &weekly, now()).unwrap().buckets.len(), 210);
+    }
+
+    #[test]
+    fn success_rate_and_failure_classes() {
+        let records = vec![
+            record("2024-01-15", "completed", json!({})),
+            record("2024-01-15", "completed", json!({})),
+            record("2024-01-15", "completed", json!({})),
+            record("2024-01-15", "failed", json!({ "errorClass": "transient" })),
+            record("2024-01-15", "failed", json!({ "errorClass": "auth" })),
+            record("2024-01-15", "failed", json!({ "errorClass": "permanent" })),
+            // Classified from the message when saved without a class
+            record("2024-01-15", "failed", json!({ "errorMessage": "Upload failed (400 Bad Request): bad" })),
+            // Neither uploaded nor failed
+            record("2024-01-15", "skipped", json!({ "skipReason": "Dry run" })),
+            record("2024-01-15", "pending", json!({})),
+        ];
+        let report = build_report(&records, &query("2024-01-15", "2024-01-15", StatsInterval::Day), now()).unwrap();
+
+        assert_eq!((report.totals.completed, report.totals.failed), (3, 4));
+        assert_eq!(report.totals.bytes, 3000);
+        assert!((report.success_rate - 300.0 / 7.0).abs() < 0.001);
+        let failures = &report.failures;
+        assert_eq!((failures.transient, failures.auth, failures.permanent), (1, 1, 2));
+    }
+
+    #[test]
+    fn durations_come_from_successful_attempts() {
+        let records = vec![
+            record(
+                "2024-01-15",
+                "completed",
+                json!({
+                    "fileSize": 4000,
+                    "attemptLog": [
+                        { "startedAt": "2024-01-15T12:00:00Z", "durationMs": 9000, "error": "Network error" },
+                        { "startedAt": "2024-01-15T12:01:00Z", "durationMs": 1000 },
+                    ],
+                }),
+            ),
+            record(
+                "2024-01-15",
+                "completed",
+                json!({
+                    "fileSize": 2000,
+                    "attemptLog": [{ "startedAt": "2024-01-15T13:00:00Z", "durationMs": 3000 }],
+                }),
+            ),
+            // No attempt log, left out of the averages
+            record("2024-01-15", "completed", json!({ "fileSize": 100_000 })),
+        ];
+        let report = build_report(&records, &query("2024-01-15", "2024-01-15", StatsInterval::Day), now()).unwrap();
+
+        assert_eq!(report.average_duration_ms, Some(2000));
+        assert_eq!(report.throughput_bytes_per_sec, Some(1500.0));
+
+        let untimed = vec![record("2024-01-15", "completed", json!({}))];
+        let report = build_report(&untimed, &query("2024-01-15", "2024-01-15", StatsInterval::Day), now()).unwrap();
+        assert_eq!(report.average_duration_ms, None);
+        assert_eq!(report.throughput_bytes_per_sec, None);
+    }
+
+    #[test]
+    fn splits_by_folder_and_account() {
+        let records = vec![
+            record("2024-01-15", "completed", json!({ "filePath": "/b/1.replay", "accountId": "u1", "accountUsername": "Zephyr" })),
+            record("2024-01-15", "completed", json!({ "filePath": "/a/2.replay", "accountId": "u2" })),
+            record("2024-01-15", "completed", json!({ "filePath": "/a/3.replay", "accountId": "u2", "accountUsername": "Mailys" })),
+            record("2024-01-15", "failed", json!({ "filePath": "/b/4.replay" })),
+        ];
+        let report = build_report(&records, &query("2024-01-15", "2024-01-15", StatsInterval::Day), now()).unwrap();
+
+        let folders: Vec<(&str, u32, u32)> = report
+            .by_folder
+            .iter()
+            .map(|s| (s.key.as_str(), s.counts.completed, s.counts.failed))
+            .collect();
+        assert_eq!(folders, vec![("/a", 2, 0), ("/b", 1, 1)]);
+
+        let accounts: Vec<(&str, Option<&str>, u32)> = report
+            .by_account
+            .iter()
+            .map(|s| (s.key.as_str(), s.label.as_deref(), s.counts.completed))
+            .collect();
+        assert_eq!(accounts, vec![("u2", Some("Mailys"), 2), ("u1", Some("Zephyr"), 1)]);
+    }
+}
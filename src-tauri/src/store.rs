@@ -0,0 +1,311 @@
+use serde_json::{Map, Value};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::agent::{EventSink, Storage};
+use crate::types::StoreRecovered;
+
+/// Serializes access to store files within the process
+static STORE_LOCK: Mutex<()> = Mutex::new(());
+
+/// Storage backed by JSON store files in a data directory
+pub struct FileStorage {
+    dir: PathBuf,
+    events: Arc<dyn EventSink>,
+}
+
+impl FileStorage {
+    /// Store files under `dir`, reporting recovered stores to `events`
+    pub fn new(dir: PathBuf, events: Arc<dyn EventSink>) -> Self {
+        Self { dir, events }
+    }
+
+    fn open(&self, file: &str) -> JsonStore {
+        JsonStore::open(&self.dir, file, self.events.clone())
+    }
+}
+
+impl Storage for FileStorage {
+    fn get(&self, file: &str, key: &str) -> Result<Option<Value>, String> {
+        self.open(file).get(key)
+    }
+
+    fn set(&self, file: &str, key: &str, value: Value) -> Result<(), String> {
+        self.open(file).set(key, value)
+    }
+
+    fn delete(&self, file: &str, key: &str) -> Result<(), String> {
+        self.open(file).delete(key)
+    }
+
+    fn update(
+        &self,
+        file: &str,
+        key: &str,
+        update: &mut dyn FnMut(Option<Value>) -> Result<Option<Value>, String>,
+    ) -> Result<(), String> {
+        self.open(file).update(key, update)
+    }
+
+    fn backup(&self, file: &str, key: &str) -> Result<Option<Value>, String> {
+        self.open(file).backup(key)
+    }
+
+    fn data_dir(&self) -> Result<PathBuf, String> {
+        Ok(self.dir.clone())
+    }
+}
+
+/// Crash-safe JSON key-value file
+///
+/// Writes go to a temporary file that is synced and renamed over the original,
+/// after copying the last good file to `<name>.bak`. If the file can't be parsed
+/// on load, the backup (or an empty store) is used and `store_recovered` is emitted.
+pub struct JsonStore {
+    events: Arc<dyn EventSink>,
+    name: String,
+    path: PathBuf,
+}
+
+impl JsonStore {
+    /// Open a store file in a directory by name (e.g. "config.json")
+    pub fn open(dir: &Path, name: &str, events: Arc<dyn EventSink>) -> Self {
+        Self {
+            events,
+            name: name.to_string(),
+            path: dir.join(name),
+        }
+    }
+
+    /// Get a value by key
+    pub fn get(&self, key: &str) -> Result<Option<Value>, String> {
+        let _guard = STORE_LOCK.lock().unwrap();
+        Ok(self.load()?.remove(key))
+    }
+
+    /// Set a value and persist the store
+    pub fn set(&self, key: &str, value: Value) -> Result<(), String> {
+        let _guard = STORE_LOCK.lock().unwrap();
+        let mut entries = self.load()?;
+        entries.insert(key.to_string(), value);
+        self.write(&entries)
+    }
+
+    /// Delete a value and persist the store
+    pub fn delete(&self, key: &str) -> Result<(), String> {
+        let _guard = STORE_LOCK.lock().unwrap();
+        let mut entries = self.load()?;
+        if entries.remove(key).is_some() {
+            self.write(&entries)?;
+        }
+        Ok(())
+    }
+
+    /// Replace a value with what `update` makes of the current one (None deletes it)
+    pub fn update(
+        &self,
+        key: &str,
+        update: &mut dyn FnMut(Option<Value>) -> Result<Option<Value>, String>,
+    ) -> Result<(), String> {
+        let _guard = STORE_LOCK.lock().unwrap();
+        let mut entries = self.load()?;
+        match update(entries.get(key).cloned())? {
+            Some(value) => entries.insert(key.to_string(), value),
+            None => entries.remove(key),
+        };
+        self.write(&entries)
+    }
+
+    /// Get a value from the last good backup of the store
+    pub fn backup(&self, key: &str) -> Result<Option<Value>, String> {
+        let _guard = STORE_LOCK.lock().unwrap();
+        Ok(read_entries(&sibling(&self.path, "bak")).and_then(|mut entries| entries.remove(key)))
+    }
+
+    /// Load all entries, recovering from a corrupt file if needed
+    fn load(&self) -> Result<Map<String, Value>, String> {
+        let contents = match fs::read(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Map::new()),
+            Err(e) => return Err(format!("Failed to read {}: {}", self.name, e)),
+        };
+
+        match serde_json::from_slice(&contents) {
+            Ok(entries) => return Ok(entries),
+            Err(e) => tracing::error!("Store {} is corrupt: {}", self.name, e),
+        }
+
+        // Keep the corrupt file around for inspection
+        let corrupt_path = sibling(&self.path, &format!("corrupt-{}", chrono::Utc::now().timestamp()));
+        let _ = fs::rename(&self.path, &corrupt_path);
+
+        let (entries, source) = match read_entries(&sibling(&self.path, "bak")) {
+            Some(entries) => (entries, "backup"),
+            None => (Map::new(), "defaults"),
+        };
+
+        tracing::warn!("Recovered store {} from {}", self.name, source);
+
+        if let Err(e) = self.write_file(&entries) {
+            tracing::error!("Failed to rewrite recovered store {}: {}", self.name, e);
+        }
+
+        self.events.emit(
+            "store_recovered",
+            &StoreRecovered {
+                store: self.name.clone(),
+                source: source.to_string(),
+            },
+        );
+
+        Ok(entries)
+    }
+
+    /// Back up the current file, then write the new contents atomically
+    fn write(&self, entries: &Map<String, Value>) -> Result<(), String> {
+        if read_entries(&self.path).is_some() {
+            let _ = fs::copy(&self.path, sibling(&self.path, "bak"));
+        }
+        self.write_file(entries)
+    }
+
+    fn write_file(&self, entries: &Map<String, Value>) -> Result<(), String> {
+        let contents = serde_json::to_vec_pretty(entries)
+            .map_err(|e| format!("Failed to serialize {}: {}", self.name, e))?;
+        write_atomic(&self.path, &contents)
+            .map_err(|e| format!("Failed to save {}: {}", self.name, e))
+    }
+}
+
+/// Read and parse a store file, None if it's missing or unreadable
+fn read_entries(path: &Path) -> Option<Map<String, Value>> {
+    let contents = fs::read(path).ok()?;
+    serde_json::from_slice(&contents).ok()
+}
+
+/// Write a file by syncing a temporary sibling and renaming it over the target
+pub fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let tmp_path = sibling(path, "tmp");
+    {
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+    }
+
+    fs::rename(&tmp_path, path)
+}
+
+/// `config.json` -> `config.json.<suffix>`
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::testing::RecordingEvents;
+    use serde_json::json;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ballcam-store-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn recovered_sources(events: &RecordingEvents) -> Vec<Value> {
+        events
+            .payloads("store_recovered")
+            .into_iter()
+            .map(|payload| payload["source"].clone())
+            .collect()
+    }
+
+    #[test]
+    fn truncated_file_recovers_the_backup() {
+        let dir = temp_dir();
+        let events = Arc::new(RecordingEvents::default());
+        let store = JsonStore::open(&dir, "config.json", events.clone());
+        store.set("config", json!({ "n": 1 })).unwrap();
+        store.set("config", json!({ "n": 2 })).unwrap();
+
+        // Cut off mid-write, the backup still holds the previous save
+        let contents = fs::read(dir.join("config.json")).unwrap();
+        fs::write(dir.join("config.json"), &contents[..contents.len() / 2]).unwrap();
+
+        assert_eq!(store.get("config").unwrap(), Some(json!({ "n": 1 })));
+        assert_eq!(recovered_sources(&events), vec![json!("backup")]);
+        // The recovered contents are written back, the corrupt file kept aside
+        assert_eq!(read_entries(&dir.join("config.json")).unwrap()["config"], json!({ "n": 1 }));
+        assert!(fs::read_dir(&dir).unwrap().any(|entry| entry
+            .unwrap()
+            .file_name()
+            .to_string_lossy()
+            .starts_with("config.json.corrupt-")));
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn corrupt_file_and_backup_recover_an_empty_store() {
+        let dir = temp_dir();
+        let events = Arc::new(RecordingEvents::default());
+        fs::write(dir.join("session.json"), b"{\"accounts\": ").unwrap();
+        fs::write(dir.join("session.json.bak"), b"not json").unwrap();
+
+        let store = JsonStore::open(&dir, "session.json", events.clone());
+        assert_eq!(store.get("accounts").unwrap(), None);
+        assert_eq!(recovered_sources(&events), vec![json!("defaults")]);
+
+        // Usable again afterwards
+        store.set("accounts", json!([])).unwrap();
+        assert_eq!(store.get("accounts").unwrap(), Some(json!([])));
+        assert_eq!(events.payloads("store_recovered").len(), 1);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn concurrent_updates_are_not_lost() {
+        const THREADS: usize = 8;
+        const UPDATES: usize = 25;
+
+        let dir = temp_dir();
+        let storage = Arc::new(FileStorage::new(dir.clone(), Arc::new(RecordingEvents::default())));
+
+        let threads: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let storage = storage.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..UPDATES {
+                        storage
+                            .update("counter.json", "count", &mut |count| {
+                                let count = count.and_then(|count| count.as_u64()).unwrap_or(0);
+                                Ok(Some(json!(count + 1)))
+                            })
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(
+            storage.get("counter.json", "count").unwrap(),
+            Some(json!(THREADS * UPDATES))
+        );
+        assert_eq!(storage.backup("counter.json", "count").unwrap(), Some(json!(THREADS * UPDATES - 1)));
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
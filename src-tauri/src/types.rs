@@ -18,15 +18,132 @@ impl std::fmt::Display for Visibility {
     }
 }
 
+/// Schedule for retrying failed uploads in the background
+/// The delay doubles after every failed round, up to `max_delay_minutes`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct AutoRetryPolicy {
+    pub enabled: bool,
+    /// Delay before the first retry, counted from the failure
+    pub initial_delay_minutes: u32,
+    pub max_delay_minutes: u32,
+    /// Give up on uploads that first failed longer ago than this
+    pub max_age_hours: u32,
+}
+
+impl Default for AutoRetryPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            initial_delay_minutes: 5,
+            max_delay_minutes: 6 * 60,
+            max_age_hours: 72,
+        }
+    }
+}
+
+/// Staging cache of replays being uploaded
+/// Copies of failed uploads are kept for retries until they succeed or expire
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct StagingPolicy {
+    /// Least recently used copies are evicted beyond this size
+    pub max_size_mb: u32,
+    /// Copies unused for longer than this are deleted
+    pub max_age_days: u32,
+}
+
+impl Default for StagingPolicy {
+    fn default() -> Self {
+        Self {
+            max_size_mb: 1024,
+            max_age_days: 14,
+        }
+    }
+}
+
+/// What to do with a replay once it's uploaded
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchiveAction {
+    /// Leave the replay where it is
+    #[default]
+    None,
+    Move,
+    Copy,
+}
+
+/// Local archive uploaded replays are moved or copied to
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct LocalArchivePolicy {
+    pub action: ArchiveAction,
+    pub folder: String,
+    /// File name of archived replays, `/` separates subfolders
+    /// Placeholders: `{date}`, `{time}`, `{playlist}`, `{map}`, `{score}`, `{player}`, `{name}`
+    pub name_template: String,
+}
+
+impl Default for LocalArchivePolicy {
+    fn default() -> Self {
+        Self {
+            action: ArchiveAction::None,
+            folder: String::new(),
+            name_template: "{date} {time} {playlist} {map} {score}".to_string(),
+        }
+    }
+}
+
+/// Deletes uploaded replays from the watched folders
+/// Only replays whose upload is confirmed in the history are deleted
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct CleanupPolicy {
+    pub enabled: bool,
+    /// Delete uploaded replays older than this many days
+    pub max_age_days: Option<u32>,
+    /// Keep this many replays per folder, deleting older uploaded ones
+    pub max_count: Option<u32>,
+}
+
+impl Default for CleanupPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_age_days: Some(30),
+            max_count: None,
+        }
+    }
+}
+
 /// Application configuration stored in config.json
+/// Missing fields fall back to their defaults so older configs keep loading
 #[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
+#[serde(default, rename_all = "camelCase")]
 pub struct AppConfig {
     pub replay_folder: String,
     pub default_visibility: Visibility,
     pub auto_start: bool,
     pub notifications_enabled: bool,
     pub setup_complete: bool,
+    /// Folders mapped to a specific account (also watched alongside `replay_folder`)
+    pub account_routes: Vec<AccountRoute>,
+    /// Personal access token for headless setups (overridden by `BALLCAM_API_TOKEN`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_token: Option<String>,
+    /// Delete history records older than this many days (keep everything when unset)
+    pub history_retention_days: Option<u32>,
+    /// Background retries of failed uploads
+    pub auto_retry: AutoRetryPolicy,
+    /// Copies of replays kept for uploads and retries
+    pub staging: StagingPolicy,
+    /// Move or copy replays to a local archive once uploaded
+    pub local_archive: LocalArchivePolicy,
+    /// Delete uploaded replays from the watched folders
+    pub cleanup: CleanupPolicy,
+    /// Run detection, validation and metadata parsing without uploading;
+    /// replays are recorded as skipped instead
+    pub dry_run: bool,
 }
 
 impl Default for AppConfig {
@@ -37,10 +154,52 @@ impl Default for AppConfig {
             auto_start: false,
             notifications_enabled: true,
             setup_complete: false,
+            account_routes: Vec::new(),
+            api_token: None,
+            history_retention_days: None,
+            auto_retry: AutoRetryPolicy::default(),
+            staging: StagingPolicy::default(),
+            local_archive: LocalArchivePolicy::default(),
+            cleanup: CleanupPolicy::default(),
+            dry_run: false,
         }
     }
 }
 
+impl AppConfig {
+    /// All folders to watch: the main replay folder plus every routed folder
+    pub fn watched_folders(&self) -> Vec<String> {
+        let mut folders = Vec::new();
+        if !self.replay_folder.is_empty() {
+            folders.push(self.replay_folder.clone());
+        }
+        for route in &self.account_routes {
+            if !route.folder.is_empty() && !folders.contains(&route.folder) {
+                folders.push(route.folder.clone());
+            }
+        }
+        folders
+    }
+
+    /// Account a file should be uploaded as, based on the most specific matching route
+    /// Returns None when no route matches (the active account is used)
+    pub fn account_for_path(&self, file_path: &std::path::Path) -> Option<&str> {
+        self.account_routes
+            .iter()
+            .filter(|route| !route.folder.is_empty() && file_path.starts_with(&route.folder))
+            .max_by_key(|route| route.folder.len())
+            .map(|route| route.account_id.as_str())
+    }
+}
+
+/// Maps a watched folder to the account its replays are uploaded as
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountRoute {
+    pub folder: String,
+    pub account_id: String,
+}
+
 /// User information from BallCam API
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -66,6 +225,73 @@ pub struct AuthSession {
     /// Device ID for device flow authentication
     #[serde(skip_serializing_if = "Option::is_none")]
     pub device_id: Option<String>,
+    /// How the session was obtained
+    #[serde(default)]
+    pub kind: SessionKind,
+}
+
+/// How an auth session was obtained
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionKind {
+    /// Email/password login with refreshable cookies
+    #[default]
+    Password,
+    /// Device flow authorization
+    Device,
+    /// Long-lived personal access token, never refreshed
+    Token,
+    /// Browser login (authorization code with PKCE), refreshed at the token endpoint
+    #[serde(rename = "oauth")]
+    OAuth,
+}
+
+/// All stored accounts, keyed by user ID (stored in session.json)
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountStore {
+    pub accounts: Vec<AuthSession>,
+    pub active_account_id: Option<String>,
+}
+
+impl AccountStore {
+    /// Session of the active account
+    pub fn active(&self) -> Option<&AuthSession> {
+        self.active_account_id.as_deref().and_then(|id| self.get(id))
+    }
+
+    /// Session of a specific account
+    pub fn get(&self, account_id: &str) -> Option<&AuthSession> {
+        self.accounts.iter().find(|s| s.user.id == account_id)
+    }
+
+    /// Insert or replace the session for its account
+    /// The first stored account becomes the active one
+    pub fn upsert(&mut self, session: AuthSession) {
+        if self.active_account_id.is_none() {
+            self.active_account_id = Some(session.user.id.clone());
+        }
+        match self.accounts.iter_mut().find(|s| s.user.id == session.user.id) {
+            Some(existing) => *existing = session,
+            None => self.accounts.push(session),
+        }
+    }
+
+    /// Remove an account, falling back to the next stored account if it was active
+    pub fn remove(&mut self, account_id: &str) {
+        self.accounts.retain(|s| s.user.id != account_id);
+        if self.active_account_id.as_deref() == Some(account_id) {
+            self.active_account_id = self.accounts.first().map(|s| s.user.id.clone());
+        }
+    }
+}
+
+/// Stored account summary for the account switcher
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountInfo {
+    pub user: User,
+    pub is_active: bool,
 }
 
 // ============================================================================
@@ -81,6 +307,15 @@ pub struct DeviceCodeResponse {
     pub verification_url: String,
     pub expires_in: u32,
     pub interval: u32,
+    /// Verification URL with the user code pre-filled
+    #[serde(default, alias = "verification_uri_complete")]
+    pub verification_url_complete: Option<String>,
+    /// QR code of the complete verification URL as an SVG document
+    #[serde(default)]
+    pub qr_code_svg: Option<String>,
+    /// QR code of the complete verification URL as a PNG data URL
+    #[serde(default)]
+    pub qr_code_png: Option<String>,
 }
 
 /// Response from POST /api/auth/device/token on success
@@ -93,6 +328,20 @@ pub struct DeviceTokenResponse {
     pub user: User,
 }
 
+/// Response from the OAuth token endpoint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OAuthTokenResponse {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: u32,
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+    #[serde(default)]
+    pub refresh_token_expires_in: Option<u32>,
+    #[serde(default)]
+    pub user: Option<User>,
+}
+
 /// Error response from device flow endpoints
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DeviceFlowError {
@@ -126,9 +375,77 @@ pub enum UploadStatus {
     Processing,
     Completed,
     Failed,
+    /// Deliberately not uploaded, see `skip_reason`
+    Skipped,
 }
 
-/// Record of a single upload attempt
+impl std::fmt::Display for UploadStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UploadStatus::Pending => write!(f, "pending"),
+            UploadStatus::Uploading => write!(f, "uploading"),
+            UploadStatus::Processing => write!(f, "processing"),
+            UploadStatus::Completed => write!(f, "completed"),
+            UploadStatus::Failed => write!(f, "failed"),
+            UploadStatus::Skipped => write!(f, "skipped"),
+        }
+    }
+}
+
+/// Kind of upload failure, used to decide whether retrying can help
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorClass {
+    /// Network errors, timeouts, rate limits and server errors
+    Transient,
+    /// The session can't be used until the user logs in again
+    Auth,
+    /// Retrying won't help (missing file, rejected replay, ...)
+    Permanent,
+}
+
+/// Match details read from a replay's header
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayMetadata {
+    /// Replay GUID assigned by the game
+    pub replay_guid: Option<String>,
+    /// Name given when saving the replay manually
+    pub replay_name: Option<String>,
+    pub map_name: Option<String>,
+    /// "Online", "Private", "Offline", ...
+    pub match_type: Option<String>,
+    /// Players per team
+    pub team_size: Option<u32>,
+    pub team0_score: u32,
+    pub team1_score: u32,
+    pub goals: u32,
+    /// Player who recorded the replay
+    pub player_name: Option<String>,
+    /// Match date as written by the game ("2024-01-15 20-30-12")
+    pub date: Option<String>,
+    pub duration_seconds: Option<f32>,
+    pub build_version: Option<String>,
+    pub unfair_teams: bool,
+}
+
+/// One attempt at uploading a replay
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadAttempt {
+    pub started_at: String,
+    pub duration_ms: u64,
+    /// Error message, None if the attempt succeeded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_class: Option<ErrorClass>,
+    /// HTTP status of a rejected upload
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_status: Option<u16>,
+}
+
+/// Upload of a single replay, kept across retries
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct UploadRecord {
@@ -145,21 +462,291 @@ pub struct UploadRecord {
     /// File size in bytes (for statistics tracking)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub file_size: Option<u64>,
+    /// ID of the account the replay was uploaded as
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub account_id: Option<String>,
+    /// Username of the account the replay was uploaded as
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub account_username: Option<String>,
+    /// Visibility the replay was uploaded with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub visibility: Option<Visibility>,
+    /// Match details parsed from the replay header
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<ReplayMetadata>,
+    /// Kind of failure, for failed uploads
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_class: Option<ErrorClass>,
+    /// Every attempt so far, oldest first (`attempts` counts them)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attempt_log: Vec<UploadAttempt>,
+    /// Where a manual upload came from
+    #[serde(flatten)]
+    pub source: UploadSource,
+    /// Where the replay was moved or copied to in the local archive
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archived_path: Option<String>,
+    /// Why the replay wasn't uploaded, for skipped records ("Dry run")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skip_reason: Option<String>,
 }
 
-/// Upload history collection
+/// Where a manual upload came from, kept on its record
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadSource {
+    /// Batch the upload was started in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub batch_id: Option<String>,
+    /// `.zip` archive the replay was extracted from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive_name: Option<String>,
+}
+
+/// Legacy upload history collection stored in history.json
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct UploadHistory {
     pub records: Vec<UploadRecord>,
 }
 
-impl UploadHistory {
-    pub const MAX_RECORDS: usize = 50;
+/// Field to sort history queries by
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum HistorySortField {
+    #[default]
+    CreatedAt,
+    Filename,
+    FileSize,
+}
+
+/// Sort direction
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Desc,
+    Asc,
+}
 
-    pub fn add_record(&mut self, record: UploadRecord) {
-        self.records.insert(0, record);
-        if self.records.len() > Self::MAX_RECORDS {
-            self.records.truncate(Self::MAX_RECORDS);
+/// Filters, sorting and pagination for history queries
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryQuery {
+    /// Only records with one of these statuses (all when empty)
+    #[serde(default)]
+    pub statuses: Vec<UploadStatus>,
+    /// Created at or after this RFC 3339 timestamp
+    pub from: Option<String>,
+    /// Created at or before this RFC 3339 timestamp
+    pub to: Option<String>,
+    /// Filename contains this text
+    pub filename: Option<String>,
+    pub visibility: Option<Visibility>,
+    pub account_id: Option<String>,
+    #[serde(default)]
+    pub sort_by: HistorySortField,
+    #[serde(default)]
+    pub sort_order: SortOrder,
+    /// Cursor from a previous page's `next_cursor`
+    pub cursor: Option<String>,
+    /// Page size (default 50, max 500)
+    pub limit: Option<u32>,
+}
+
+/// One page of history query results
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryPage {
+    pub records: Vec<UploadRecord>,
+    /// Cursor for the next page, None on the last page
+    pub next_cursor: Option<String>,
+    /// Number of records matching the filters
+    pub total: u64,
+}
+
+/// Outcome of a bulk retry
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkRetryResult {
+    pub succeeded: u32,
+    pub failed: u32,
+    /// Records that weren't retried (not failed, permanent error, file missing)
+    pub skipped: u32,
+}
+
+/// File format for history export and import
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryExportFormat {
+    /// One row per record, metadata flattened into columns
+    Csv,
+    /// One JSON record per line, lossless
+    Jsonl,
+}
+
+/// Outcome of a history import
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryImportResult {
+    pub imported: u32,
+    /// Records already in history (same record or same replay ID)
+    pub skipped: u32,
+}
+
+/// Upload state of a replay in the library
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LibraryStatus {
+    /// Never uploaded
+    #[default]
+    Never,
+    /// Uploading or waiting to be uploaded
+    Pending,
+    Uploaded,
+    Failed,
+    /// Deliberately not uploaded
+    Skipped,
+}
+
+impl std::fmt::Display for LibraryStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LibraryStatus::Never => write!(f, "never"),
+            LibraryStatus::Pending => write!(f, "pending"),
+            LibraryStatus::Uploaded => write!(f, "uploaded"),
+            LibraryStatus::Failed => write!(f, "failed"),
+            LibraryStatus::Skipped => write!(f, "skipped"),
+        }
+    }
+}
+
+/// A replay file found in a watched folder
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LibraryEntry {
+    pub path: String,
+    pub filename: String,
+    pub folder: String,
+    pub file_size: u64,
+    /// Last modification time of the file (RFC 3339)
+    pub modified_at: String,
+    pub status: LibraryStatus,
+    /// Latest history record for this file
+    pub record_id: Option<String>,
+    pub replay_url: Option<String>,
+    /// Match details, None if the header couldn't be read
+    pub metadata: Option<ReplayMetadata>,
+}
+
+/// Filters and pagination for library queries (newest files first)
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct LibraryQuery {
+    /// Only these statuses (all when empty)
+    #[serde(default)]
+    pub statuses: Vec<LibraryStatus>,
+    /// Only files in this folder
+    pub folder: Option<String>,
+    /// Case-insensitive match on the filename or map name
+    pub search: Option<String>,
+    /// Modified at or after this time (RFC 3339)
+    pub from: Option<String>,
+    /// Modified at or before this time (RFC 3339)
+    pub to: Option<String>,
+    /// Opaque cursor from the previous page
+    pub cursor: Option<String>,
+    pub limit: Option<u32>,
+}
+
+/// One page of library query results
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LibraryPage {
+    pub entries: Vec<LibraryEntry>,
+    /// Cursor for the next page, None on the last page
+    pub next_cursor: Option<String>,
+    /// Number of entries matching the filters
+    pub total: u64,
+}
+
+/// Outcome of a library scan
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct LibraryScanResult {
+    /// Replay files found in the watched folders
+    pub files: u32,
+    /// New or changed files that were (re)indexed
+    pub indexed: u32,
+    /// Entries removed because their file is gone
+    pub removed: u32,
+}
+
+/// A replay waiting to be uploaded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadJob {
+    pub file_path: String,
+    pub visibility: Option<Visibility>,
+    /// History record to continue, for retries
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub record_id: Option<String>,
+    /// Where the upload came from, for new records
+    #[serde(flatten)]
+    pub source: UploadSource,
+}
+
+/// Upload queue state, held jobs are kept in queue.json across restarts
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadQueueState {
+    /// Uploads are paused until the user logs in again
+    pub paused_for_auth: bool,
+    /// Jobs held back by an expired session
+    pub held_jobs: Vec<UploadJob>,
+}
+
+/// Payload of the `store_recovered` event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StoreRecovered {
+    /// Store file that was corrupt (e.g. "config.json")
+    pub store: String,
+    /// Where the data was recovered from: "backup" or "defaults"
+    pub source: String,
+}
+
+/// Payload of the `config_changed` event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigChanged {
+    /// Names of the fields that changed (camelCase, as in `AppConfig`)
+    pub changed: Vec<String>,
+    pub config: AppConfig,
+}
+
+/// Validation error for a single config field
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigFieldError {
+    /// Field name (camelCase, as in `AppConfig`)
+    pub field: String,
+    pub message: String,
+}
+
+/// Error returned by `save_config`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SaveConfigError {
+    pub message: String,
+    /// Per-field validation errors (empty when saving or applying failed)
+    pub fields: Vec<ConfigFieldError>,
+}
+
+impl From<String> for SaveConfigError {
+    fn from(message: String) -> Self {
+        Self {
+            message,
+            fields: Vec::new(),
         }
     }
 }
@@ -174,6 +761,114 @@ pub struct WatcherState {
     pub pending_files: Vec<String>,
 }
 
+/// State of a running agent, reported to the command line
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AgentStatus {
+    pub watcher: WatcherState,
+    pub watched_folders: Vec<String>,
+    pub queue: UploadQueueState,
+    /// Uploads in progress
+    pub active_uploads: usize,
+    /// The active account, None when logged out
+    pub account: Option<User>,
+    /// Replays are processed without being uploaded
+    pub dry_run: bool,
+}
+
+/// Outcome of uploading one file from the command line
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadOutcome {
+    pub file_path: String,
+    /// Saved record (also for failed uploads), None if the upload never started
+    pub record: Option<UploadRecord>,
+    pub error: Option<String>,
+}
+
+// ============================================================================
+// Batch Upload Types
+// ============================================================================
+
+/// Files and folders to upload in one batch
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchRequest {
+    /// Replay files, `.zip` archives of replays and folders of replays
+    pub paths: Vec<String>,
+    /// Also upload replays in subfolders of the given folders
+    #[serde(default)]
+    pub recursive: bool,
+    /// Only replays modified at or after this RFC 3339 timestamp
+    pub modified_after: Option<String>,
+    /// Only replays modified at or before this RFC 3339 timestamp
+    pub modified_before: Option<String>,
+    pub visibility: Option<Visibility>,
+}
+
+/// A file left out of a batch, or a failed upload in its summary
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchFileIssue {
+    pub file_path: String,
+    pub reason: String,
+}
+
+/// A batch accepted for upload (also the `batch_started` event payload)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchStarted {
+    pub batch_id: String,
+    /// Files that will be uploaded
+    pub total: u32,
+    /// Files left out: not replays, outside the date range, duplicates, already uploaded,
+    /// archive entries over the size limits
+    pub skipped: Vec<BatchFileIssue>,
+}
+
+/// Payload of the `batch_progress` event, sent before each file and at the end
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchProgress {
+    pub batch_id: String,
+    pub total: u32,
+    pub completed: u32,
+    pub failed: u32,
+    /// Held until the user logs in again
+    pub held: u32,
+    /// Processed without being uploaded in a dry run
+    pub skipped: u32,
+    /// File being uploaded
+    pub current_file: Option<String>,
+    /// Share of files done (0-100)
+    pub percentage: u8,
+}
+
+/// Payload of the `batch_completed` event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchSummary {
+    pub batch_id: String,
+    pub total: u32,
+    pub completed: u32,
+    pub failed: u32,
+    pub held: u32,
+    /// Left out when collecting the files, or not uploaded in a dry run
+    pub skipped: u32,
+    pub failures: Vec<BatchFileIssue>,
+    pub started_at: String,
+    pub finished_at: String,
+}
+
+/// Payload of the `replays_cleaned_up` event
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct CleanupResult {
+    /// Uploaded replays deleted from the watched folders
+    pub deleted: u32,
+    pub freed_bytes: u64,
+}
+
 // ============================================================================
 // Status Page Enhancement Types
 // ============================================================================
@@ -214,6 +909,89 @@ pub struct UploadStats {
     pub total_bytes_formatted: String,
 }
 
+/// Bucket size for upload report time series
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum StatsInterval {
+    #[default]
+    Day,
+    /// Weeks starting on Monday
+    Week,
+}
+
+/// Date range and bucket size of an upload report
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct StatsQuery {
+    /// Start of the range (RFC 3339), from the first record when unset
+    pub from: Option<String>,
+    /// End of the range (RFC 3339), until now when unset
+    pub to: Option<String>,
+    #[serde(default)]
+    pub interval: StatsInterval,
+}
+
+/// Upload counts and bytes for a bucket or a split
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct StatsCounts {
+    pub completed: u32,
+    pub failed: u32,
+    /// Bytes of completed uploads
+    pub bytes: u64,
+}
+
+/// Counts for one time bucket
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatsBucket {
+    /// First day of the bucket (YYYY-MM-DD, local time)
+    pub start: String,
+    #[serde(flatten)]
+    pub counts: StatsCounts,
+}
+
+/// Counts for one folder or account
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatsSplit {
+    /// Folder path or account ID
+    pub key: String,
+    /// Display name (account username), if known
+    pub label: Option<String>,
+    #[serde(flatten)]
+    pub counts: StatsCounts,
+}
+
+/// Failed uploads per error class
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct FailureBreakdown {
+    pub transient: u32,
+    pub auth: u32,
+    pub permanent: u32,
+}
+
+/// Upload statistics over a date range
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadReport {
+    pub totals: StatsCounts,
+    /// Percentage of successful uploads (0-100)
+    pub success_rate: f32,
+    /// One entry per interval, including empty ones
+    pub buckets: Vec<StatsBucket>,
+    /// Average duration of successful upload attempts
+    pub average_duration_ms: Option<u64>,
+    /// Bytes per second over successful upload attempts
+    pub throughput_bytes_per_sec: Option<f64>,
+    pub failures: FailureBreakdown,
+    /// Per watched folder, most uploads first
+    pub by_folder: Vec<StatsSplit>,
+    /// Per account, most uploads first
+    pub by_account: Vec<StatsSplit>,
+}
+
 /// Information about the watched folder for display
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
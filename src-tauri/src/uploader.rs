@@ -1,9 +1,20 @@
 use std::path::Path;
 use std::time::Duration;
-use tauri::{AppHandle, Emitter};
 
+use crate::agent::AgentContext;
+use crate::archiving;
+use crate::cleanup;
 use crate::config;
-use crate::types::{AuthSession, UploadProgress, UploadRecord, UploadStatus, Visibility};
+use crate::history;
+use crate::library;
+use crate::oauth::{self, OAuthConfig};
+use crate::queue;
+use crate::replay;
+use crate::staging;
+use crate::types::{
+    AuthSession, ErrorClass, SessionKind, UploadAttempt, UploadJob, UploadProgress, UploadRecord,
+    UploadSource, UploadStatus, Visibility,
+};
 
 // Use localhost in dev mode, production URL otherwise
 #[cfg(dev)]
@@ -19,23 +30,169 @@ const FRONTEND_URL: &str = "https://ballcam.tv";
 const MAX_RETRIES: u32 = 3;
 const RETRY_DELAYS: [u64; 3] = [1, 2, 4]; // seconds
 
+/// Error returned when the session can't be used or refreshed
+pub const SESSION_EXPIRED_ERROR: &str = "Session expired, please login again";
+/// Error returned when no account is logged in
+pub const NOT_LOGGED_IN_ERROR: &str = "Not logged in";
+/// Error returned when the server rejects the file size
+const FILE_TOO_LARGE_ERROR: &str = "File too large";
+/// Error returned for uploads held until the user logs in again
+pub const UPLOAD_HELD_ERROR: &str = "Upload paused until you log in again";
+/// Skip reason of replays processed in a dry run
+pub const DRY_RUN_REASON: &str = "Dry run";
+
+/// Why an upload attempt failed
+#[derive(Debug, Clone, PartialEq)]
+pub enum UploadError {
+    /// No account is logged in, or its session expired and can't be refreshed
+    Auth(String),
+    /// The replay can't be read
+    File(String),
+    /// The server rejected the upload
+    Rejected { status: u16, message: String },
+    /// Network errors, unexpected responses, ...
+    Other(String),
+}
+
+impl UploadError {
+    /// Whether retrying can help
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            Self::Auth(_) => ErrorClass::Auth,
+            Self::File(_) => ErrorClass::Permanent,
+            // Timeouts and rate limits pass, other client errors won't
+            Self::Rejected { status: 408 | 429, .. } => ErrorClass::Transient,
+            Self::Rejected { status: 400..=499, .. } => ErrorClass::Permanent,
+            Self::Rejected { .. } | Self::Other(_) => ErrorClass::Transient,
+        }
+    }
+
+    /// HTTP status of an upload rejected by the server
+    pub fn http_status(&self) -> Option<u16> {
+        match self {
+            Self::Rejected { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for UploadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Auth(message) | Self::File(message) | Self::Other(message) => {
+                f.write_str(message)
+            }
+            Self::Rejected { message, .. } => f.write_str(message),
+        }
+    }
+}
+
+impl From<String> for UploadError {
+    fn from(message: String) -> Self {
+        Self::Other(message)
+    }
+}
+
+impl From<UploadError> for String {
+    fn from(error: UploadError) -> Self {
+        error.to_string()
+    }
+}
+
+#[derive(Clone)]
 pub struct Uploader {
     client: reqwest::Client,
+    api_base_url: String,
+    /// Dry run whatever the config says, e.g. for `upload --dry-run`
+    dry_run: bool,
 }
 
 impl Uploader {
     pub fn new() -> Self {
+        Self::with_base_url(API_BASE_URL)
+    }
+
+    /// Uploader talking to another API server, e.g. a local one in tests
+    pub fn with_base_url(api_base_url: impl Into<String>) -> Self {
         Self {
             client: reqwest::Client::new(),
+            api_base_url: api_base_url.into(),
+            dry_run: false,
         }
     }
 
+    /// Process replays without uploading them, whatever the config says
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
     pub async fn upload_replay(
         &self,
-        app: &AppHandle,
+        ctx: &AgentContext,
+        file_path: &str,
+        visibility: Option<Visibility>,
+    ) -> Result<UploadRecord, String> {
+        self.upload(ctx, file_path, visibility, None, UploadSource::default())
+            .await
+    }
+
+    /// Upload a replay and record where it came from (batch, ...) on its record
+    pub async fn upload_replay_from(
+        &self,
+        ctx: &AgentContext,
         file_path: &str,
         visibility: Option<Visibility>,
+        source: UploadSource,
+    ) -> Result<UploadRecord, String> {
+        self.upload(ctx, file_path, visibility, None, source).await
+    }
+
+    /// Upload a failed record's replay again
+    /// The record keeps its ID and creation time, new attempts are appended to its log
+    pub async fn retry_record(
+        &self,
+        ctx: &AgentContext,
+        record: UploadRecord,
     ) -> Result<UploadRecord, String> {
+        let file_path = record.file_path.clone();
+        let visibility = record.visibility.clone();
+        let source = record.source.clone();
+        self.upload(ctx, &file_path, visibility, Some(record), source)
+            .await
+    }
+
+    async fn upload(
+        &self,
+        ctx: &AgentContext,
+        file_path: &str,
+        visibility: Option<Visibility>,
+        existing: Option<UploadRecord>,
+        source: UploadSource,
+    ) -> Result<UploadRecord, String> {
+        let _active = ctx.track_upload();
+
+        let job = UploadJob {
+            file_path: file_path.to_string(),
+            visibility: visibility.clone(),
+            record_id: existing.as_ref().map(|r| r.id.clone()),
+            source: source.clone(),
+        };
+
+        let config = config::load_config(ctx)?;
+        let dry_run = self.dry_run || config::is_dry_run(&config);
+
+        // A dry run would turn the failed record into a skipped one
+        if dry_run && existing.is_some() {
+            return Err("Retries are disabled in dry-run mode".to_string());
+        }
+
+        // Hold new uploads while waiting for the user to log in again (dry runs don't upload)
+        if !dry_run && ctx.upload_queue.is_paused() {
+            queue::hold_for_login(ctx, job);
+            return Err(UPLOAD_HELD_ERROR.to_string());
+        }
+
         let path = Path::new(file_path);
         let filename = path
             .file_name()
@@ -43,29 +200,120 @@ impl Uploader {
             .unwrap_or("unknown.replay")
             .to_string();
 
+        // Upload a copy, the game may rotate the original away before a retry
+        let staged = if dry_run {
+            None
+        } else {
+            match staging::stage(ctx, path) {
+                Ok(staged) => Some(staged),
+                Err(e) => {
+                    tracing::warn!("Uploading {} without a staged copy: {}", filename, e);
+                    None
+                }
+            }
+        };
+        let upload_path = staged.as_ref().map(|s| s.path()).unwrap_or(path);
+
         // Get file size for progress tracking and statistics
-        let file_size = tokio::fs::metadata(file_path)
+        let file_size = tokio::fs::metadata(upload_path)
             .await
             .map(|m| m.len())
             .ok();
 
-        // Create initial upload record
-        let mut record = UploadRecord {
-            id: uuid::Uuid::new_v4().to_string(),
-            filename: filename.clone(),
-            file_path: file_path.to_string(),
-            status: UploadStatus::Pending,
-            replay_id: None,
-            replay_url: None,
-            error_message: None,
-            attempts: 0,
-            created_at: chrono::Utc::now().to_rfc3339(),
-            completed_at: None,
-            file_size,
+        // Resolve the account to upload as: a folder route, or the active account
+        let routed_account = config.account_for_path(path).map(str::to_string);
+        let account = match routed_account.as_deref() {
+            Some(account_id) => match config::load_session_for(ctx, account_id)? {
+                Some(session) => Some(session),
+                // A dry run doesn't upload, it still records the account the route names
+                None if dry_run => {
+                    tracing::warn!("Account {} for {} is not logged in", account_id, filename);
+                    None
+                }
+                None => {
+                    return Err(format!("Account {} for {} is not logged in", account_id, filename))
+                }
+            },
+            None => config::load_session(ctx)?,
+        };
+
+        // Get visibility from config if not specified
+        let visibility = visibility.unwrap_or(config.default_visibility);
+
+        // Match details are informational, a header we can't read doesn't block the upload
+        let metadata = match replay::parse_header(upload_path) {
+            Ok(metadata) => Some(metadata),
+            Err(e) => {
+                tracing::warn!("Failed to read replay header for {}: {}", filename, e);
+                None
+            }
         };
 
+        let account_id = account
+            .as_ref()
+            .map(|a| a.user.id.clone())
+            .or(routed_account);
+        let account_username = account.as_ref().map(|a| a.user.username.clone());
+
+        // Continue the existing record on retries, or create the initial upload record
+        let mut record = match existing {
+            Some(mut record) => {
+                record.status = UploadStatus::Pending;
+                record.error_message = None;
+                record.error_class = None;
+                record.file_size = file_size.or(record.file_size);
+                record.account_id = account_id;
+                record.account_username = account_username;
+                record.visibility = Some(visibility.clone());
+                record.metadata = metadata.or(record.metadata);
+                record
+            }
+            None => UploadRecord {
+                id: uuid::Uuid::new_v4().to_string(),
+                filename: filename.clone(),
+                file_path: file_path.to_string(),
+                status: UploadStatus::Pending,
+                replay_id: None,
+                replay_url: None,
+                error_message: None,
+                attempts: 0,
+                created_at: ctx.now().to_rfc3339(),
+                completed_at: None,
+                file_size,
+                account_id,
+                account_username,
+                visibility: Some(visibility.clone()),
+                metadata,
+                error_class: None,
+                attempt_log: Vec::new(),
+                source,
+                archived_path: None,
+                skip_reason: None,
+            },
+        };
+
+        // Everything but the upload itself ran, record the replay as skipped
+        if dry_run {
+            record.status = UploadStatus::Skipped;
+            record.skip_reason = Some(DRY_RUN_REASON.to_string());
+            record.completed_at = Some(ctx.now().to_rfc3339());
+            if let Err(e) = self.save_to_history(ctx, &record) {
+                tracing::error!("Failed to record skipped {}: {}", filename, e);
+            }
+
+            // Replays extracted from archives aren't needed anymore
+            if staging::is_staged(ctx, path) {
+                staging::remove(ctx, path);
+            }
+
+            ctx.emit("upload_skipped", &record);
+            tracing::info!("Dry run, not uploading {}", filename);
+            return Ok(record);
+        }
+
         // Emit upload started event
-        let _ = app.emit("upload_started", &record);
+        ctx.emit("upload_started", &record);
+        library::track_upload(ctx, &record);
 
         // Emit initial progress (0%)
         if let Some(total_bytes) = file_size {
@@ -78,31 +326,49 @@ impl Uploader {
                 speed: 0,
                 estimated_remaining: None,
             };
-            let _ = app.emit("upload_progress", &progress);
+            ctx.emit("upload_progress", &progress);
         }
 
-        // Get visibility from config if not specified
-        let visibility = match visibility {
-            Some(v) => v,
-            None => {
-                let config = config::load_config(app)?;
-                config.default_visibility
-            }
-        };
-
         // Perform upload with retries
-        let mut last_error = String::new();
+        let mut last_error = UploadError::Other("Upload was not attempted".to_string());
         for attempt in 0..MAX_RETRIES {
-            record.attempts = attempt + 1;
             record.status = UploadStatus::Uploading;
-            let _ = app.emit("upload_progress", &record);
+            ctx.emit("upload_progress", &record);
+
+            let started_at = ctx.now().to_rfc3339();
+            let started = std::time::Instant::now();
+            let result = self
+                .try_upload(ctx, upload_path, &visibility, record.account_id.as_deref())
+                .await;
+
+            // Held uploads are attempted again as if this one never happened
+            if !matches!(&result, Err(UploadError::Auth(_))) {
+                record.attempts += 1;
+                let error = result.as_ref().err();
+                record.attempt_log.push(UploadAttempt {
+                    started_at,
+                    duration_ms: started.elapsed().as_millis() as u64,
+                    error: error.map(|e| e.to_string()),
+                    error_class: error.map(UploadError::class),
+                    http_status: error.and_then(UploadError::http_status),
+                });
+            }
 
-            match self.try_upload(app, file_path, &visibility).await {
+            match result {
                 Ok((replay_id, replay_url)) => {
                     record.status = UploadStatus::Completed;
                     record.replay_id = Some(replay_id);
                     record.replay_url = Some(replay_url.clone());
-                    record.completed_at = Some(chrono::Utc::now().to_rfc3339());
+                    record.completed_at = Some(ctx.now().to_rfc3339());
+
+                    // Move or copy the replay to the local archive when enabled
+                    let archived =
+                        archiving::archive_replay(ctx, &config.local_archive, &record, upload_path);
+                    match archived {
+                        Ok(Some(archived_path)) => record.archived_path = Some(archived_path),
+                        Ok(None) => {}
+                        Err(e) => tracing::warn!("{}", e),
+                    }
 
                     // Emit final progress (100%)
                     if let Some(total_bytes) = file_size {
@@ -115,22 +381,39 @@ impl Uploader {
                             speed: 0,
                             estimated_remaining: Some(0),
                         };
-                        let _ = app.emit("upload_progress", &progress);
+                        ctx.emit("upload_progress", &progress);
+                    }
+
+                    // The replay is uploaded, a history that can't be written doesn't undo that
+                    if let Err(e) = self.save_to_history(ctx, &record) {
+                        tracing::error!("Failed to record upload of {}: {}", filename, e);
                     }
 
-                    // Save to history
-                    self.save_to_history(app, &record)?;
+                    // The staged copy is only kept for retries
+                    drop(staged);
+                    staging::remove(ctx, path);
 
-                    // Send notification
-                    self.send_notification(app, &filename, &replay_url).await;
+                    // Watched folders may have grown past the cleanup policy
+                    cleanup::spawn(ctx);
+
+                    // Batches notify once when they finish
+                    if record.source.batch_id.is_none() {
+                        self.send_notification(ctx, &filename, &replay_url).await;
+                    }
 
                     // Emit completion event
-                    let _ = app.emit("upload_completed", &record);
+                    ctx.emit("upload_completed", &record);
 
                     tracing::info!("Upload completed: {} -> {}", filename, replay_url);
 
                     return Ok(record);
                 }
+                Err(UploadError::Auth(_)) => {
+                    // Keep the job pending instead of recording a failure
+                    ctx.emit("upload_held", &record);
+                    queue::hold_for_login(ctx, job);
+                    return Err(UPLOAD_HELD_ERROR.to_string());
+                }
                 Err(e) => {
                     last_error = e;
                     tracing::warn!(
@@ -140,6 +423,11 @@ impl Uploader {
                         last_error
                     );
 
+                    // Retrying won't change the outcome
+                    if last_error.class() == ErrorClass::Permanent {
+                        break;
+                    }
+
                     // Wait before retry (except on last attempt)
                     if attempt < MAX_RETRIES - 1 {
                         tokio::time::sleep(Duration::from_secs(RETRY_DELAYS[attempt as usize]))
@@ -151,34 +439,36 @@ impl Uploader {
 
         // All retries failed
         record.status = UploadStatus::Failed;
-        record.error_message = Some(last_error.clone());
+        record.error_class = Some(last_error.class());
+        record.error_message = Some(last_error.to_string());
 
         // Save to history
-        let _ = self.save_to_history(app, &record);
+        let _ = self.save_to_history(ctx, &record);
 
         // Emit failure event
-        let _ = app.emit("upload_failed", &record);
+        ctx.emit("upload_failed", &record);
 
-        tracing::error!("Upload failed after {} attempts: {}", MAX_RETRIES, filename);
+        tracing::error!("Upload failed after {} attempts: {}", record.attempts, filename);
 
-        Err(last_error)
+        Err(last_error.to_string())
     }
 
     async fn try_upload(
         &self,
-        app: &AppHandle,
-        file_path: &str,
+        ctx: &AgentContext,
+        file_path: &Path,
         visibility: &Visibility,
-    ) -> Result<(String, String), String> {
+        account_id: Option<&str>,
+    ) -> Result<(String, String), UploadError> {
         // Ensure we have a valid session
-        let session = self.ensure_valid_session(app).await?;
+        let session = self.ensure_valid_session(ctx, account_id).await?;
 
         // Read file
         let file_data = tokio::fs::read(file_path)
             .await
-            .map_err(|e| format!("Failed to read file: {}", e))?;
+            .map_err(|e| UploadError::File(format!("Failed to read file: {}", e)))?;
 
-        let filename = Path::new(file_path)
+        let filename = file_path
             .file_name()
             .and_then(|n| n.to_str())
             .unwrap_or("replay.replay");
@@ -194,13 +484,7 @@ impl Uploader {
             .text("visibility", visibility.to_string());
 
         // Send request
-        let response = self
-            .client
-            .post(format!("{}/replays", API_BASE_URL))
-            .header(
-                "Cookie",
-                format!("access_token={}", session.access_token),
-            )
+        let response = authorize(self.client.post(format!("{}/replays", self.api_base_url)), &session)
             .multipart(form)
             .send()
             .await
@@ -210,9 +494,15 @@ impl Uploader {
         if !status.is_success() {
             let error_text = response.text().await.unwrap_or_default();
             return Err(match status.as_u16() {
-                401 => "Authentication expired".to_string(),
-                413 => "File too large".to_string(),
-                _ => format!("Upload failed ({}): {}", status, error_text),
+                401 => UploadError::Auth(SESSION_EXPIRED_ERROR.to_string()),
+                413 => UploadError::Rejected {
+                    status: 413,
+                    message: FILE_TOO_LARGE_ERROR.to_string(),
+                },
+                code => UploadError::Rejected {
+                    status: code,
+                    message: format!("Upload failed ({}): {}", status, error_text),
+                },
             });
         }
 
@@ -241,29 +531,50 @@ impl Uploader {
         Ok((replay_id, replay_url))
     }
 
-    async fn ensure_valid_session(&self, app: &AppHandle) -> Result<AuthSession, String> {
-        let session = config::load_session(app)?
-            .ok_or("Not logged in")?;
+    /// Session of an account (the active one by default), refreshed when about to expire
+    pub async fn ensure_valid_session(
+        &self,
+        ctx: &AgentContext,
+        account_id: Option<&str>,
+    ) -> Result<AuthSession, UploadError> {
+        let session = match account_id {
+            Some(id) => config::load_session_for(ctx, id)?,
+            None => config::load_session(ctx)?,
+        }
+        .ok_or_else(|| UploadError::Auth(NOT_LOGGED_IN_ERROR.to_string()))?;
+
+        // Access tokens are long-lived and never refreshed
+        if session.kind == SessionKind::Token {
+            return Ok(session);
+        }
 
         // Check if access token is expired or about to expire
         let expiry = chrono::DateTime::parse_from_rfc3339(&session.access_token_expiry)
-            .map_err(|_| "Invalid expiry date")?;
+            .map_err(|_| UploadError::Other("Invalid expiry date".to_string()))?;
 
-        let now = chrono::Utc::now();
+        let now = ctx.now();
         let buffer = chrono::Duration::minutes(5);
 
         if expiry.with_timezone(&chrono::Utc) <= now + buffer {
             tracing::info!("Access token expired, refreshing...");
-            return self.refresh_session(app, &session).await;
+            return self.refresh_session(ctx, &session).await;
         }
 
         Ok(session)
     }
 
-    async fn refresh_session(&self, app: &AppHandle, session: &AuthSession) -> Result<AuthSession, String> {
+    async fn refresh_session(
+        &self,
+        ctx: &AgentContext,
+        session: &AuthSession,
+    ) -> Result<AuthSession, UploadError> {
+        if session.kind == SessionKind::OAuth {
+            return self.refresh_oauth_session(ctx, session).await;
+        }
+
         let response = self
             .client
-            .post(format!("{}/auth/refresh", API_BASE_URL))
+            .post(format!("{}/auth/refresh", self.api_base_url))
             .header(
                 "Cookie",
                 format!("refresh_token={}", session.refresh_token),
@@ -273,7 +584,7 @@ impl Uploader {
             .map_err(|e| format!("Network error during refresh: {}", e))?;
 
         if !response.status().is_success() {
-            return Err("Session expired, please login again".to_string());
+            return Err(UploadError::Auth(SESSION_EXPIRED_ERROR.to_string()));
         }
 
         // Extract new tokens from cookies
@@ -300,7 +611,7 @@ impl Uploader {
         }
 
         // Create new session with updated tokens
-        let now = chrono::Utc::now();
+        let now = ctx.now();
         let new_session = AuthSession {
             access_token: new_access_token,
             refresh_token: new_refresh_token,
@@ -308,68 +619,376 @@ impl Uploader {
             refresh_token_expiry: (now + chrono::Duration::days(7)).to_rfc3339(),
             user: session.user.clone(),
             device_id: session.device_id.clone(),
+            kind: session.kind,
         };
 
-        config::save_session(app, &new_session)?;
+        config::save_session(ctx, &new_session)?;
         tracing::info!("Session refreshed successfully");
 
         Ok(new_session)
     }
 
-    fn save_to_history(&self, app: &AppHandle, record: &UploadRecord) -> Result<(), String> {
-        let mut history = config::load_history(app)?;
+    async fn refresh_oauth_session(
+        &self,
+        ctx: &AgentContext,
+        session: &AuthSession,
+    ) -> Result<AuthSession, UploadError> {
+        if session.refresh_token.is_empty() {
+            return Err(UploadError::Auth(SESSION_EXPIRED_ERROR.to_string()));
+        }
 
-        // Add new record at the beginning
-        history.records.insert(0, record.clone());
+        let tokens = oauth::refresh(&self.client, &OAuthConfig::default(), &session.refresh_token)
+            .await
+            .map_err(|e| {
+                tracing::warn!("OAuth refresh failed: {}", e);
+                UploadError::Auth(SESSION_EXPIRED_ERROR.to_string())
+            })?;
 
-        // Keep only last 100 records
-        history.records.truncate(100);
+        let now = ctx.now();
+        let new_session = AuthSession {
+            access_token: tokens.access_token,
+            access_token_expiry: (now + chrono::Duration::seconds(tokens.expires_in as i64))
+                .to_rfc3339(),
+            refresh_token: tokens
+                .refresh_token
+                .unwrap_or_else(|| session.refresh_token.clone()),
+            refresh_token_expiry: match tokens.refresh_token_expires_in {
+                Some(secs) => (now + chrono::Duration::seconds(secs as i64)).to_rfc3339(),
+                None => session.refresh_token_expiry.clone(),
+            },
+            ..session.clone()
+        };
 
-        config::save_history(app, &history)
-    }
+        config::save_session(ctx, &new_session)?;
+        tracing::info!("OAuth session refreshed successfully");
 
-    async fn send_notification(&self, _app: &AppHandle, filename: &str, replay_url: &str) {
-        // Check if notifications are enabled
-        if let Ok(config) = config::load_config(_app) {
-            if !config.notifications_enabled {
-                return;
-            }
-        }
+        Ok(new_session)
+    }
 
-        // Use native Windows toast notification
-        #[cfg(windows)]
-        {
-            use tauri_winrt_notification::{Toast, Duration};
-
-            // Use a known Windows AUMID for dev mode, or the app's own ID in production
-            // Microsoft.Windows.Explorer works as a fallback
-            let _ = Toast::new(Toast::POWERSHELL_APP_ID)
-                .title("BallCam Agent")
-                .text1("Replay Uploaded!")
-                .text2(&format!("{} uploaded to BallCam", filename))
-                .duration(Duration::Short)
-                .show();
-        }
+    fn save_to_history(&self, ctx: &AgentContext, record: &UploadRecord) -> Result<(), String> {
+        library::track_upload(ctx, record);
+        history::store(ctx)?.save(record)
+    }
 
-        // Fallback for non-Windows platforms
-        #[cfg(not(windows))]
-        {
-            use tauri_plugin_notification::NotificationExt;
-
-            let _ = _app
-                .notification()
-                .builder()
-                .title("Replay Uploaded!")
-                .body(&format!("{} uploaded to BallCam", filename))
-                .show();
-        }
+    async fn send_notification(&self, ctx: &AgentContext, filename: &str, replay_url: &str) {
+        notify(
+            ctx,
+            "Replay Uploaded!",
+            &format!("{} uploaded to BallCam", filename),
+        );
 
         tracing::info!("Notification sent for: {} -> {}", filename, replay_url);
     }
 }
 
+/// Attach session credentials to a request
+/// Access tokens use a bearer header, other sessions use the access token cookie
+pub fn authorize(request: reqwest::RequestBuilder, session: &AuthSession) -> reqwest::RequestBuilder {
+    match session.kind {
+        SessionKind::Token | SessionKind::OAuth => request.bearer_auth(&session.access_token),
+        _ => request.header("Cookie", format!("access_token={}", session.access_token)),
+    }
+}
+
+/// Show a notification if notifications are enabled
+pub fn notify(ctx: &AgentContext, title: &str, body: &str) {
+    // Check if notifications are enabled
+    if let Ok(config) = config::load_config(ctx) {
+        if !config.notifications_enabled {
+            return;
+        }
+    }
+
+    ctx.events.notify(title, body);
+}
+
 impl Default for Uploader {
     fn default() -> Self {
         Self::new()
     }
 }
+
+/// Local stand-in for the BallCam API
+#[cfg(test)]
+pub mod testing {
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Answers requests with canned responses, in order, and records their method and path
+    pub struct ApiServer {
+        pub url: String,
+        requests: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl ApiServer {
+        /// Serve `(status, body)` responses, 500 once they run out
+        pub async fn start(responses: Vec<(u16, &'static str)>) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}/api", listener.local_addr().unwrap());
+            let requests = Arc::new(Mutex::new(Vec::new()));
+
+            let recorded = requests.clone();
+            tokio::spawn(async move {
+                let mut responses = responses.into_iter();
+                while let Ok((mut stream, _)) = listener.accept().await {
+                    let Some(request) = read_request(&mut stream).await else {
+                        continue;
+                    };
+                    recorded.lock().unwrap().push(request);
+
+                    let (status, body) = responses.next().unwrap_or((500, "no more responses"));
+                    let response = format!(
+                        "HTTP/1.1 {} Test\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        status,
+                        body.len(),
+                        body
+                    );
+                    let _ = stream.write_all(response.as_bytes()).await;
+                    let _ = stream.shutdown().await;
+                }
+            });
+
+            Self { url, requests }
+        }
+
+        /// Requests received so far as "METHOD /path"
+        pub fn requests(&self) -> Vec<String> {
+            self.requests.lock().unwrap().clone()
+        }
+    }
+
+    /// Read a whole request and return its method and path (without the /api prefix)
+    async fn read_request(stream: &mut tokio::net::TcpStream) -> Option<String> {
+        let mut data = Vec::new();
+        let mut buf = [0u8; 8192];
+        let header_end = loop {
+            let read = stream.read(&mut buf).await.ok()?;
+            if read == 0 {
+                return None;
+            }
+            data.extend_from_slice(&buf[..read]);
+            if let Some(end) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+                break end + 4;
+            }
+        };
+
+        let head = String::from_utf8_lossy(&data[..header_end]).to_string();
+        let content_length = head
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+            .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+            .unwrap_or(0);
+        while data.len() < header_end + content_length {
+            let read = stream.read(&mut buf).await.ok()?;
+            if read == 0 {
+                break;
+            }
+            data.extend_from_slice(&buf[..read]);
+        }
+
+        let mut request_line = head.lines().next()?.split(' ');
+        let method = request_line.next()?;
+        let path = request_line.next()?;
+        Some(format!("{} {}", method, path.trim_start_matches("/api")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::testing::ApiServer;
+    use crate::agent::testing::{now, TestAgent};
+    use crate::types::{AccountRoute, AppConfig};
+
+    fn rejected(status: u16) -> UploadError {
+        UploadError::Rejected {
+            status,
+            message: format!("Upload failed ({}): nope", status),
+        }
+    }
+
+    #[test]
+    fn classifies_upload_errors() {
+        assert_eq!(UploadError::Auth(NOT_LOGGED_IN_ERROR.to_string()).class(), ErrorClass::Auth);
+        assert_eq!(UploadError::File("Failed to read file: gone".to_string()).class(), ErrorClass::Permanent);
+        assert_eq!(UploadError::Other("Network error: reset".to_string()).class(), ErrorClass::Transient);
+
+        for status in [400, 404, 413, 422] {
+            assert_eq!(rejected(status).class(), ErrorClass::Permanent, "{}", status);
+        }
+        for status in [408, 429, 500, 502, 503] {
+            assert_eq!(rejected(status).class(), ErrorClass::Transient, "{}", status);
+        }
+    }
+
+    #[test]
+    fn only_rejections_carry_a_status() {
+        assert_eq!(rejected(422).http_status(), Some(422));
+        assert_eq!(UploadError::Auth(SESSION_EXPIRED_ERROR.to_string()).http_status(), None);
+        assert_eq!(UploadError::Other("Network error".to_string()).http_status(), None);
+    }
+
+    #[test]
+    fn errors_read_as_their_message() {
+        assert_eq!(rejected(422).to_string(), "Upload failed (422): nope");
+        let error: String = UploadError::Auth(SESSION_EXPIRED_ERROR.to_string()).into();
+        assert_eq!(error, SESSION_EXPIRED_ERROR);
+    }
+
+    #[tokio::test]
+    async fn held_retries_are_not_counted_as_attempts() {
+        let agent = TestAgent::new();
+        let replay = replay(&agent, "a.replay");
+        let record: UploadRecord = serde_json::from_value(serde_json::json!({
+            "id": "failed",
+            "filename": "a.replay",
+            "filePath": replay,
+            "status": "failed",
+            "errorMessage": "Network error",
+            "attempts": 1,
+            "createdAt": "2024-01-15T20:00:00Z",
+            "attemptLog": [{ "startedAt": "2024-01-15T20:00:00Z", "durationMs": 10, "error": "Network error" }],
+        }))
+        .unwrap();
+
+        // Nobody is logged in, the retry is held instead of attempted
+        let error = Uploader::new().retry_record(&agent.ctx, record).await.unwrap_err();
+        assert_eq!(error, UPLOAD_HELD_ERROR);
+
+        let held = agent.events.payloads("upload_held");
+        assert_eq!(held.len(), 1);
+        assert_eq!(held[0]["attempts"], 1);
+        assert_eq!(held[0]["attemptLog"].as_array().unwrap().len(), 1);
+        assert!(agent.ctx.upload_queue.is_paused());
+    }
+
+    /// Write a replay to upload
+    fn replay(agent: &TestAgent, name: &str) -> String {
+        agent
+            .file(name, b"not really a replay")
+            .to_string_lossy()
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn uploads_replays_to_the_api() {
+        let agent = TestAgent::new().logged_in(chrono::Duration::days(1));
+        let server = ApiServer::start(vec![(201, r#"{"replay": {"id": "r1"}}"#)]).await;
+        let replay = replay(&agent, "a.replay");
+
+        let record = Uploader::with_base_url(&server.url)
+            .upload_replay(&agent.ctx, &replay, None)
+            .await
+            .unwrap();
+
+        assert_eq!(server.requests(), vec!["POST /replays"]);
+        assert!(matches!(record.status, UploadStatus::Completed));
+        assert_eq!(record.replay_id.as_deref(), Some("r1"));
+        assert_eq!(record.replay_url, Some(format!("{}/replays/r1", FRONTEND_URL)));
+        assert_eq!(record.account_id.as_deref(), Some("user-1"));
+        assert_eq!(record.created_at, now().to_rfc3339());
+        assert_eq!(record.completed_at, Some(now().to_rfc3339()));
+        assert_eq!(record.attempts, 1);
+        assert_eq!(record.attempt_log[0].started_at, now().to_rfc3339());
+
+        let saved = history::store(&agent.ctx).unwrap().get(&record.id).unwrap().unwrap();
+        assert!(matches!(saved.status, UploadStatus::Completed));
+        assert_eq!(agent.events.payloads("upload_completed").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn permanent_rejections_fail_without_retrying() {
+        let agent = TestAgent::new().logged_in(chrono::Duration::days(1));
+        let server = ApiServer::start(vec![(422, "not a replay")]).await;
+        let replay = replay(&agent, "a.replay");
+
+        let error = Uploader::with_base_url(&server.url)
+            .upload_replay(&agent.ctx, &replay, None)
+            .await
+            .unwrap_err();
+
+        assert!(error.contains("not a replay"), "{}", error);
+        assert_eq!(server.requests().len(), 1);
+
+        let failed = agent.events.payloads("upload_failed");
+        assert_eq!(failed.len(), 1);
+        let record: UploadRecord = serde_json::from_value(failed[0].clone()).unwrap();
+        assert!(matches!(record.status, UploadStatus::Failed));
+        assert_eq!(record.error_class, Some(ErrorClass::Permanent));
+        assert_eq!(record.attempts, 1);
+        assert_eq!(record.attempt_log[0].http_status, Some(422));
+    }
+
+    #[tokio::test]
+    async fn expiring_sessions_are_refreshed_before_uploading() {
+        // Expires within the refresh margin on the agent's clock, not the system's
+        let agent = TestAgent::new().logged_in(chrono::Duration::minutes(10));
+        agent.clock.advance(chrono::Duration::minutes(6));
+        let server = ApiServer::start(vec![(200, "{}"), (201, r#"{"id": 7}"#)]).await;
+        let replay = replay(&agent, "a.replay");
+
+        let record = Uploader::with_base_url(&server.url)
+            .upload_replay(&agent.ctx, &replay, None)
+            .await
+            .unwrap();
+
+        assert_eq!(server.requests(), vec!["POST /auth/refresh", "POST /replays"]);
+        assert_eq!(record.replay_id.as_deref(), Some("7"));
+
+        let refreshed = config::load_session(&agent.ctx).unwrap().unwrap();
+        let expected = agent.ctx.now() + chrono::Duration::minutes(30);
+        assert_eq!(refreshed.access_token_expiry, expected.to_rfc3339());
+    }
+
+    #[tokio::test]
+    async fn dry_runs_record_replays_routed_to_logged_out_accounts() {
+        let agent = TestAgent::new().logged_in(chrono::Duration::days(1));
+        let replay = replay(&agent, "a.replay");
+        let folder = agent.dir.to_string_lossy().to_string();
+        let agent = agent.with_config(&AppConfig {
+            account_routes: vec![AccountRoute {
+                folder,
+                account_id: "user-2".to_string(),
+            }],
+            ..Default::default()
+        });
+
+        let record = Uploader::new()
+            .with_dry_run(true)
+            .upload_replay(&agent.ctx, &replay, None)
+            .await
+            .unwrap();
+
+        assert!(matches!(record.status, UploadStatus::Skipped));
+        assert_eq!(record.skip_reason.as_deref(), Some(DRY_RUN_REASON));
+        assert_eq!(record.account_id.as_deref(), Some("user-2"));
+        assert_eq!(record.account_username, None);
+        assert_eq!(agent.events.payloads("upload_skipped").len(), 1);
+
+        // Uploading for real needs the account
+        let error = Uploader::new()
+            .upload_replay(&agent.ctx, &replay, None)
+            .await
+            .unwrap_err();
+        assert!(error.contains("user-2 for a.replay is not logged in"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn unauthorized_uploads_are_held_for_login() {
+        let agent = TestAgent::new().logged_in(chrono::Duration::days(1));
+        let server = ApiServer::start(vec![(401, "expired")]).await;
+        let replay = replay(&agent, "a.replay");
+
+        let error = Uploader::with_base_url(&server.url)
+            .upload_replay(&agent.ctx, &replay, None)
+            .await
+            .unwrap_err();
+
+        assert_eq!(error, UPLOAD_HELD_ERROR);
+        assert!(agent.ctx.upload_queue.is_paused());
+        assert_eq!(agent.events.payloads("upload_held")[0]["attempts"], 0);
+        assert!(agent.events.payloads("upload_failed").is_empty());
+    }
+}
@@ -1,36 +1,50 @@
 use notify::{Config, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
-use tauri::{AppHandle, Emitter};
 
-use crate::types::WatcherState;
+use crate::agent::AgentContext;
+use crate::archive::{self, MAX_ARCHIVE_SIZE};
+use crate::batch;
+use crate::library;
+use crate::types::{AppConfig, BatchRequest, WatcherState};
 use crate::uploader::Uploader;
 
-const MAX_FILE_SIZE: u64 = 50 * 1024 * 1024; // 50MB
+pub const MAX_FILE_SIZE: u64 = 50 * 1024 * 1024; // 50MB
 
 pub struct FileWatcher {
     watcher: Option<RecommendedWatcher>,
-    watch_path: Option<PathBuf>,
+    watch_paths: Vec<PathBuf>,
     is_paused: Arc<Mutex<bool>>,
     pending_files: Arc<Mutex<Vec<String>>>,
     stop_tx: Option<mpsc::Sender<()>>,
+    /// Stopped on request, config changes don't start it again
+    stopped: bool,
+    uploader: Uploader,
 }
 
 impl FileWatcher {
     pub fn new() -> Self {
+        Self::with_uploader(Uploader::new())
+    }
+
+    /// Watcher uploading detected replays with a given uploader
+    pub fn with_uploader(uploader: Uploader) -> Self {
         Self {
             watcher: None,
-            watch_path: None,
+            watch_paths: Vec::new(),
             is_paused: Arc::new(Mutex::new(false)),
             pending_files: Arc::new(Mutex::new(Vec::new())),
             stop_tx: None,
+            stopped: false,
+            uploader,
         }
     }
 
-    pub fn start(&mut self, app: AppHandle, watch_path: PathBuf) -> Result<(), String> {
+    pub fn start(&mut self, ctx: AgentContext, watch_paths: Vec<PathBuf>) -> Result<(), String> {
         if self.watcher.is_some() {
             return Err("Watcher already running".to_string());
         }
@@ -39,6 +53,7 @@ impl FileWatcher {
         let (event_tx, event_rx) = mpsc::channel();
         let is_paused = self.is_paused.clone();
         let pending_files = self.pending_files.clone();
+        let uploader = self.uploader.clone();
 
         // Create the watcher
         let watcher = RecommendedWatcher::new(
@@ -52,20 +67,25 @@ impl FileWatcher {
         .map_err(|e| format!("Failed to create watcher: {}", e))?;
 
         self.watcher = Some(watcher);
-        self.watch_path = Some(watch_path.clone());
+        self.watch_paths = watch_paths.clone();
         self.stop_tx = Some(stop_tx);
+        self.stopped = false;
 
         // Start watching
         if let Some(ref mut w) = self.watcher {
-            w.watch(&watch_path, RecursiveMode::NonRecursive)
-                .map_err(|e| format!("Failed to watch folder: {}", e))?;
+            for watch_path in &watch_paths {
+                w.watch(watch_path, RecursiveMode::NonRecursive)
+                    .map_err(|e| format!("Failed to watch folder {:?}: {}", watch_path, e))?;
+                tracing::info!("Started watching folder: {:?}", watch_path);
+            }
         }
 
-        tracing::info!("Started watching folder: {:?}", watch_path);
-
         // Spawn event processing thread
-        let app_clone = app.clone();
         thread::spawn(move || {
+            // Size and modification time of archives already imported, reading
+            // them must not import them again
+            let mut imported_archives = HashMap::new();
+
             loop {
                 // Check for stop signal
                 if stop_rx.try_recv().is_ok() {
@@ -77,6 +97,14 @@ impl FileWatcher {
                 if let Ok(event) = event_rx.recv_timeout(Duration::from_millis(100)) {
                     tracing::debug!("File event received: {:?}", event);
 
+                    // Keep the library in sync even while paused
+                    if let EventKind::Remove(_) = event.kind {
+                        for path in &event.paths {
+                            library::remove_file(&ctx, path);
+                        }
+                        continue;
+                    }
+
                     // Skip if paused
                     if *is_paused.lock().unwrap() {
                         continue;
@@ -100,22 +128,56 @@ impl FileWatcher {
                             }
 
                             if let Some(file_path) = Self::validate_file(&path) {
+                                if archive::is_archive(&path) {
+                                    let Ok(metadata) = std::fs::metadata(&path) else {
+                                        continue;
+                                    };
+                                    let version = (metadata.len(), metadata.modified().ok());
+                                    if imported_archives.get(&file_path) == Some(&version) {
+                                        continue;
+                                    }
+                                    imported_archives.insert(file_path.clone(), version);
+
+                                    tracing::info!("New replay archive detected: {:?}", file_path);
+                                    pending_files.lock().unwrap().push(file_path.clone());
+                                    ctx.emit("file_detected", &file_path);
+
+                                    // Extract and upload its replays as a batch
+                                    let batch_ctx = ctx.clone();
+                                    let pending_files_clone = pending_files.clone();
+                                    ctx.spawn(async move {
+                                        let request = BatchRequest {
+                                            paths: vec![file_path.clone()],
+                                            ..Default::default()
+                                        };
+                                        if let Err(e) = batch::start(&batch_ctx, request).await {
+                                            tracing::error!("Archive import failed: {}", e);
+                                        }
+                                        pending_files_clone.lock().unwrap().retain(|f| f != &file_path);
+                                    });
+                                    continue;
+                                }
+
                                 tracing::info!("New replay file detected: {:?}", file_path);
 
                                 // Add to pending files
                                 pending_files.lock().unwrap().push(file_path.clone());
 
+                                if let Err(e) = library::index_file(&ctx, &path) {
+                                    tracing::warn!("Failed to index {}: {}", file_path, e);
+                                }
+
                                 // Emit event to frontend
-                                let _ = app_clone.emit("file_detected", &file_path);
+                                ctx.emit("file_detected", &file_path);
 
                                 // Trigger upload directly
-                                let app_for_upload = app_clone.clone();
+                                let upload_ctx = ctx.clone();
                                 let file_path_clone = file_path.clone();
                                 let pending_files_clone = pending_files.clone();
+                                let uploader = uploader.clone();
 
-                                tauri::async_runtime::spawn(async move {
-                                    let uploader = Uploader::new();
-                                    match uploader.upload_replay(&app_for_upload, &file_path_clone, None).await {
+                                ctx.spawn(async move {
+                                    match uploader.upload_replay(&upload_ctx, &file_path_clone, None).await {
                                         Ok(record) => {
                                             tracing::info!("Upload successful: {:?}", record.replay_url);
                                         }
@@ -137,22 +199,28 @@ impl FileWatcher {
     }
 
     pub fn stop(&mut self) -> Result<(), String> {
+        self.unwatch();
+        self.stopped = true;
+        tracing::info!("Watcher stopped");
+        Ok(())
+    }
+
+    /// Stop watching the current folders, e.g. to watch others
+    fn unwatch(&mut self) {
         // Send stop signal
         if let Some(tx) = self.stop_tx.take() {
             let _ = tx.send(());
         }
 
         // Stop watching
-        if let (Some(ref mut w), Some(ref path)) = (&mut self.watcher, &self.watch_path) {
-            let _ = w.unwatch(path);
+        if let Some(ref mut w) = self.watcher {
+            for path in &self.watch_paths {
+                let _ = w.unwatch(path);
+            }
         }
 
         self.watcher = None;
-        self.watch_path = None;
-
-        tracing::info!("Watcher stopped");
-
-        Ok(())
+        self.watch_paths.clear();
     }
 
     pub fn pause(&self) -> Result<(), String> {
@@ -177,11 +245,14 @@ impl FileWatcher {
     }
 
     fn validate_file(path: &PathBuf) -> Option<String> {
-        // Check extension
-        let extension = path.extension()?.to_str()?;
-        if extension.to_lowercase() != "replay" {
+        // Check extension: replays, and .zip archives of replays
+        let max_size = if library::is_replay(path) {
+            MAX_FILE_SIZE
+        } else if archive::is_archive(path) {
+            MAX_ARCHIVE_SIZE
+        } else {
             return None;
-        }
+        };
 
         // Check file exists and get metadata
         let metadata = std::fs::metadata(path).ok()?;
@@ -192,8 +263,8 @@ impl FileWatcher {
         }
 
         // Check file size
-        if metadata.len() > MAX_FILE_SIZE {
-            tracing::warn!("File too large (>50MB): {:?}", path);
+        if metadata.len() > max_size {
+            tracing::warn!("File too large: {:?}", path);
             return None;
         }
 
@@ -223,8 +294,153 @@ impl FileWatcher {
     }
 }
 
+/// Restart the agent's watcher on the configured folders (stopped until setup is complete)
+/// A watcher that was stopped stays stopped, a paused one stays paused
+pub fn restart(ctx: &AgentContext, config: &AppConfig) -> Result<(), String> {
+    let mut watcher = ctx.watcher.lock().unwrap();
+    watcher.unwatch();
+
+    let folders = config.watched_folders();
+    if watcher.stopped || !config.setup_complete || folders.is_empty() {
+        return Ok(());
+    }
+
+    watcher.start(ctx.clone(), folders.iter().map(PathBuf::from).collect())
+}
+
+/// Pause or resume the agent's watcher and tell the app
+pub fn set_paused(ctx: &AgentContext, paused: bool) -> Result<(), String> {
+    let watcher = ctx.watcher.lock().unwrap();
+    if paused {
+        watcher.pause()?;
+        ctx.emit("watcher_paused", &());
+    } else {
+        watcher.resume()?;
+        ctx.emit("watcher_resumed", &());
+    }
+    Ok(())
+}
+
 impl Default for FileWatcher {
     fn default() -> Self {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::testing::{now, TestAgent};
+    use crate::config;
+    use crate::types::UploadRecord;
+    use crate::uploader::testing::ApiServer;
+
+    fn config(folder: &std::path::Path) -> AppConfig {
+        AppConfig {
+            replay_folder: folder.to_string_lossy().to_string(),
+            setup_complete: true,
+            ..Default::default()
+        }
+    }
+
+    fn state(ctx: &AgentContext) -> WatcherState {
+        ctx.watcher.lock().unwrap().get_state()
+    }
+
+    #[test]
+    fn restart_keeps_the_watcher_state() {
+        let agent = TestAgent::new();
+        let ctx = &agent.ctx;
+        let (first, second) = (agent.dir.join("first"), agent.dir.join("second"));
+        std::fs::create_dir_all(&first).unwrap();
+        std::fs::create_dir_all(&second).unwrap();
+
+        // Not started before setup is complete
+        let mut pending_setup = config(&first);
+        pending_setup.setup_complete = false;
+        restart(ctx, &pending_setup).unwrap();
+        assert!(!state(ctx).is_watching);
+
+        restart(ctx, &config(&first)).unwrap();
+        assert!(state(ctx).is_watching);
+
+        // Paused stays paused on the new folder
+        set_paused(ctx, true).unwrap();
+        restart(ctx, &config(&second)).unwrap();
+        let paused = state(ctx);
+        assert!(paused.is_watching);
+        assert!(paused.is_paused);
+        assert_eq!(ctx.watcher.lock().unwrap().watch_paths, vec![second.clone()]);
+        set_paused(ctx, false).unwrap();
+
+        // Stopped stays stopped until started again
+        ctx.watcher.lock().unwrap().stop().unwrap();
+        restart(ctx, &config(&first)).unwrap();
+        assert!(!state(ctx).is_watching);
+
+        ctx.watcher
+            .lock()
+            .unwrap()
+            .start(ctx.clone(), vec![first.clone()])
+            .unwrap();
+        restart(ctx, &config(&second)).unwrap();
+        assert!(state(ctx).is_watching);
+
+        ctx.watcher.lock().unwrap().stop().unwrap();
+    }
+
+    /// Wait for the next event with a given name
+    async fn next_event(
+        events: &mut tokio::sync::broadcast::Receiver<crate::agent::AgentEvent>,
+        name: &str,
+    ) -> serde_json::Value {
+        let wait = async {
+            loop {
+                let event = events.recv().await.unwrap();
+                if event.event == name {
+                    return event.payload;
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(20), wait)
+            .await
+            .unwrap_or_else(|_| panic!("no {} event", name))
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn uploads_replays_written_to_the_folder_unless_paused() {
+        let agent = TestAgent::new().logged_in(chrono::Duration::days(1));
+        let server = ApiServer::start(vec![(201, r#"{"id": "r1"}"#)]).await;
+        let folder = agent.dir.join("Demos");
+        std::fs::create_dir_all(&folder).unwrap();
+        let ctx = &agent.ctx;
+        *ctx.watcher.lock().unwrap() = FileWatcher::with_uploader(Uploader::with_base_url(&server.url));
+        config::save_config(ctx, &config(&folder)).unwrap();
+        restart(ctx, &config(&folder)).unwrap();
+        let mut events = ctx.subscribe();
+
+        // Replays written while paused are left alone
+        set_paused(ctx, true).unwrap();
+        std::fs::write(folder.join("paused.replay"), b"replay").unwrap();
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        set_paused(ctx, false).unwrap();
+
+        agent.clock.advance(chrono::Duration::hours(1));
+        let replay = folder.join("match.replay");
+        std::fs::write(&replay, b"replay").unwrap();
+
+        let detected = next_event(&mut events, "file_detected").await;
+        assert_eq!(detected, replay.to_string_lossy().as_ref());
+        let completed = next_event(&mut events, "upload_completed").await;
+        let record: UploadRecord = serde_json::from_value(completed).unwrap();
+        assert_eq!(record.filename, "match.replay");
+        assert_eq!(record.replay_id.as_deref(), Some("r1"));
+        assert_eq!(record.created_at, (now() + chrono::Duration::hours(1)).to_rfc3339());
+        assert_eq!(server.requests(), vec!["POST /replays"]);
+
+        // Done uploading, the file isn't pending anymore
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(state(ctx).pending_files.is_empty());
+        ctx.watcher.lock().unwrap().stop().unwrap();
+    }
+}
@@ -15,6 +15,7 @@ function App() {
   const [setupComplete, setSetupComplete] = useState(false);
   const [hasSession, setHasSession] = useState(false);
   const [currentTab, setCurrentTab] = useState<Tab>('home');
+  const [highlightId, setHighlightId] = useState<string | null>(null);
 
   useEffect(() => {
     const checkState = async () => {
@@ -44,9 +45,28 @@ function App() {
       setCurrentTab('history');
     });
 
+    // A ballcam://history/<id> link was opened
+    const openHistoryItem = (id: string | null) => {
+      if (id) {
+        setHighlightId(id);
+        setCurrentTab('history');
+      }
+    };
+    api.takePendingHistoryItem().then(openHistoryItem).catch(console.error);
+    const unlistenHistoryItem = listen('open_history_item', () => {
+      api.takePendingHistoryItem().then(openHistoryItem).catch(console.error);
+    });
+
+    // Uploads are paused until the user logs in again
+    const unlistenExpired = listen('session_expired', () => {
+      setHasSession(false);
+    });
+
     return () => {
       unlistenSettings.then((fn) => fn());
       unlistenHistory.then((fn) => fn());
+      unlistenHistoryItem.then((fn) => fn());
+      unlistenExpired.then((fn) => fn());
     };
   }, []);
 
@@ -86,7 +106,7 @@ function App() {
       case 'settings':
         return <Settings />;
       case 'history':
-        return <History />;
+        return <History highlightId={highlightId} />;
       default:
         return <Main />;
     }
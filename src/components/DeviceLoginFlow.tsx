@@ -138,6 +138,17 @@ export function DeviceLoginFlow({ onSuccess, onSwitchToPassword }: DeviceLoginFl
               </div>
             </div>
 
+            {state.code.qr_code_png && (
+              <div className="flex flex-col items-center gap-2">
+                <img
+                  src={state.code.qr_code_png}
+                  alt="Scan to authorize this device"
+                  className="w-40 h-40 rounded-lg bg-white p-2"
+                />
+                <p className="text-xs text-gray-500">Or scan with your phone</p>
+              </div>
+            )}
+
             {state.status === 'code_ready' && (
               <GradientButton
                 className="w-full gap-2"
@@ -1,16 +1,25 @@
-import { useState } from 'react';
-import { CheckCircle2, XCircle, Clock, Loader2, ExternalLink, RotateCcw } from 'lucide-react';
+import { useState, useEffect, useRef } from 'react';
+import { CheckCircle2, XCircle, Clock, Loader2, ExternalLink, RotateCcw, MinusCircle } from 'lucide-react';
 import type { UploadRecord } from '@/lib/types';
 import * as api from '@/lib/api';
 
 interface HistoryItemProps {
   record: UploadRecord;
   onRetryComplete?: () => void;
+  /** Opened from a ballcam:// link: scrolled into view and outlined */
+  highlighted?: boolean;
 }
 
-export function HistoryItem({ record, onRetryComplete }: HistoryItemProps) {
+export function HistoryItem({ record, onRetryComplete, highlighted }: HistoryItemProps) {
   const [isRetrying, setIsRetrying] = useState(false);
   const [retryError, setRetryError] = useState<string | null>(null);
+  const ref = useRef<HTMLDivElement>(null);
+
+  useEffect(() => {
+    if (highlighted) {
+      ref.current?.scrollIntoView({ behavior: 'smooth', block: 'center' });
+    }
+  }, [highlighted]);
 
   const handleRetry = async () => {
     setIsRetrying(true);
@@ -34,6 +43,8 @@ export function HistoryItem({ record, onRetryComplete }: HistoryItemProps) {
         return <CheckCircle2 className="w-5 h-5 text-green-400" />;
       case 'failed':
         return <XCircle className="w-5 h-5 text-red-400" />;
+      case 'skipped':
+        return <MinusCircle className="w-5 h-5 text-gray-400" />;
       case 'uploading':
       case 'processing':
         return <Loader2 className="w-5 h-5 text-violet-400 animate-spin" />;
@@ -48,6 +59,8 @@ export function HistoryItem({ record, onRetryComplete }: HistoryItemProps) {
         return 'text-green-400';
       case 'failed':
         return 'text-red-400';
+      case 'skipped':
+        return 'text-gray-400';
       case 'uploading':
       case 'processing':
         return 'text-violet-400';
@@ -73,7 +86,12 @@ export function HistoryItem({ record, onRetryComplete }: HistoryItemProps) {
   };
 
   return (
-    <div className="bg-gray-900/50 border border-gray-800 rounded-xl p-4 hover:border-gray-700 transition-colors">
+    <div
+      ref={ref}
+      className={`bg-gray-900/50 border rounded-xl p-4 transition-colors ${
+        highlighted ? 'border-violet-500' : 'border-gray-800 hover:border-gray-700'
+      }`}
+    >
       <div className="flex items-start gap-3">
         <div className="mt-0.5">
           {getStatusIcon()}
@@ -116,6 +134,10 @@ export function HistoryItem({ record, onRetryComplete }: HistoryItemProps) {
             </div>
           )}
 
+          {record.status === 'skipped' && record.skipReason && (
+            <p className="mt-2 text-xs text-gray-500">Skipped ({record.skipReason.toLowerCase()})</p>
+          )}
+
           {record.attempts > 1 && (
             <p className="mt-1 text-xs text-gray-500">
               {record.attempts} attempts
@@ -6,7 +6,12 @@ import * as api from '@/lib/api';
 
 const PAGE_SIZE = 50;
 
-export function HistoryList() {
+interface HistoryListProps {
+  /** Record to bring into view, e.g. from a ballcam:// link */
+  highlightId?: string | null;
+}
+
+export function HistoryList({ highlightId }: HistoryListProps) {
   const [allRecords, setAllRecords] = useState<UploadRecord[]>([]);
   const [displayCount, setDisplayCount] = useState(PAGE_SIZE);
   const [loading, setLoading] = useState(true);
@@ -32,6 +37,15 @@ export function HistoryList() {
     }
   };
 
+  // Show enough records to include the highlighted one
+  useEffect(() => {
+    if (!highlightId) return;
+    const index = allRecords.findIndex((record) => record.id === highlightId);
+    if (index >= displayCount) {
+      setDisplayCount(Math.ceil((index + 1) / PAGE_SIZE) * PAGE_SIZE);
+    }
+  }, [highlightId, allRecords, displayCount]);
+
   const loadMore = () => {
     setDisplayCount(prev => prev + PAGE_SIZE);
   };
@@ -77,7 +91,12 @@ export function HistoryList() {
       </div>
 
       {records.map((record) => (
-        <HistoryItem key={record.id} record={record} onRetryComplete={loadHistory} />
+        <HistoryItem
+          key={record.id}
+          record={record}
+          onRetryComplete={loadHistory}
+          highlighted={record.id === highlightId}
+        />
       ))}
 
       {/* Load more button */}
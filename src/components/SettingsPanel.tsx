@@ -1,7 +1,7 @@
 import { useState, useEffect } from 'react';
 import { Loader2, Globe, Lock, FolderOpen, Bell, Power, Info, Download, Check, AlertCircle, RefreshCw } from 'lucide-react';
 import { getVersion } from '@tauri-apps/api/app';
-import type { AppConfig, Visibility } from '@/lib/types';
+import type { AppConfig, SaveConfigError, Visibility } from '@/lib/types';
 import * as api from '@/lib/api';
 import { useUpdater } from '@/hooks/useUpdater';
 
@@ -44,7 +44,8 @@ export function SettingsPanel() {
       await api.saveConfig(newConfig);
       setConfig(newConfig);
     } catch (err) {
-      setError('Failed to save settings');
+      const saveError = err as SaveConfigError;
+      setError(saveError.fields?.[0]?.message ?? saveError.message ?? 'Failed to save settings');
       console.error(err);
     } finally {
       setSaving(false);
@@ -1,14 +1,29 @@
 import { invoke } from '@tauri-apps/api/core';
 import type {
+  AccountInfo,
   AppConfig,
   AuthSession,
+  BatchRequest,
+  BatchStarted,
+  BulkRetryResult,
   DetectedFolder,
   DeviceCodeResponse,
   DevicePollResult,
   FolderInfo,
+  HistoryExportFormat,
+  HistoryImportResult,
+  HistoryPage,
+  HistoryQuery,
+  LibraryPage,
+  LibraryQuery,
+  LibraryScanResult,
+  StatsQuery,
+  UploadQueueState,
   UploadRecord,
+  UploadReport,
   UploadStats,
   User,
+  Visibility,
   WatcherState,
 } from './types';
 
@@ -17,6 +32,7 @@ export async function getConfig(): Promise<AppConfig> {
   return invoke('get_config');
 }
 
+/** Rejects with a SaveConfigError */
 export async function saveConfig(config: AppConfig): Promise<void> {
   return invoke('save_config', { newConfig: config });
 }
@@ -30,10 +46,31 @@ export async function login(email: string, password: string): Promise<User> {
   return invoke('login', { email, password });
 }
 
+export async function loginWithToken(token: string): Promise<User> {
+  return invoke('login_with_token', { token });
+}
+
+export async function loginWithBrowser(): Promise<User> {
+  return invoke('login_with_browser');
+}
+
 export async function logout(): Promise<void> {
   return invoke('logout');
 }
 
+// Account commands
+export async function listAccounts(): Promise<AccountInfo[]> {
+  return invoke('list_accounts');
+}
+
+export async function switchAccount(accountId: string): Promise<User> {
+  return invoke('switch_account', { accountId });
+}
+
+export async function removeAccount(accountId: string): Promise<void> {
+  return invoke('remove_account', { accountId });
+}
+
 // Device flow commands
 export async function requestDeviceCode(): Promise<DeviceCodeResponse> {
   return invoke('request_device_code');
@@ -60,10 +97,51 @@ export async function getHistory(): Promise<UploadRecord[]> {
   return invoke('get_history');
 }
 
+export async function queryHistory(query: HistoryQuery): Promise<HistoryPage> {
+  return invoke('query_history', { query });
+}
+
+/** Export records matching the filters, returns how many were written */
+export async function exportHistory(
+  format: HistoryExportFormat,
+  path: string,
+  filters?: HistoryQuery
+): Promise<number> {
+  return invoke('export_history', { format, path, filters });
+}
+
+/** Merge records exported from another machine (.csv or .jsonl) */
+export async function importHistory(path: string): Promise<HistoryImportResult> {
+  return invoke('import_history', { path });
+}
+
 export async function retryUpload(recordId: string): Promise<UploadRecord> {
   return invoke('retry_upload', { recordId });
 }
 
+/** Retry every failed upload, skipping errors that retrying can't fix */
+export async function retryFailedUploads(): Promise<BulkRetryResult> {
+  return invoke('retry_failed_uploads');
+}
+
+export async function retryUploads(recordIds: string[]): Promise<BulkRetryResult> {
+  return invoke('retry_uploads', { recordIds });
+}
+
+/** Returns how many records were deleted */
+export async function deleteHistoryRecords(recordIds: string[]): Promise<number> {
+  return invoke('delete_history_records', { recordIds });
+}
+
+/** Returns how many records were deleted */
+export async function clearHistory(): Promise<number> {
+  return invoke('clear_history');
+}
+
+export async function getUploadQueue(): Promise<UploadQueueState> {
+  return invoke('get_upload_queue');
+}
+
 // Watcher commands
 export async function pauseWatcher(): Promise<void> {
   return invoke('pause_watcher');
@@ -105,10 +183,44 @@ export async function openFolder(path: string): Promise<void> {
 }
 
 // Upload statistics
+// Library commands
+export async function queryLibrary(query: LibraryQuery): Promise<LibraryPage> {
+  return invoke('query_library', { query });
+}
+
+export async function rescanLibrary(): Promise<LibraryScanResult> {
+  return invoke('rescan_library');
+}
+
+/** Upload replays from the library in the background, returns how many were queued */
+export async function uploadLibraryReplays(
+  paths: string[],
+  visibility?: Visibility
+): Promise<number> {
+  return invoke('upload_library_replays', { paths, visibility });
+}
+
+/**
+ * Upload files and folders of replays in the background
+ * Progress comes with batch_progress events, the outcome with batch_completed
+ */
+export async function uploadBatch(request: BatchRequest): Promise<BatchStarted> {
+  return invoke('upload_batch', { request });
+}
+
 export async function getUploadStats(): Promise<UploadStats> {
   return invoke('get_upload_stats');
 }
 
+export async function getUploadReport(query: StatsQuery = {}): Promise<UploadReport> {
+  return invoke('get_upload_report', { query });
+}
+
+// History item a ballcam://history/<id> link asked to show, if any
+export async function takePendingHistoryItem(): Promise<string | null> {
+  return invoke('take_pending_history_item');
+}
+
 // Open URL in default browser
 export async function openUrl(url: string): Promise<void> {
   const { open } = await import('@tauri-apps/plugin-shell');
@@ -8,6 +8,78 @@ export interface AppConfig {
   autoStart: boolean;
   notificationsEnabled: boolean;
   setupComplete: boolean;
+  /** Folders mapped to a specific account (also watched alongside replayFolder) */
+  accountRoutes: AccountRoute[];
+  /** Personal access token for headless setups */
+  apiToken?: string;
+  /** Delete history records older than this many days (keep everything when null) */
+  historyRetentionDays: number | null;
+  /** Background retries of failed uploads */
+  autoRetry: AutoRetryPolicy;
+  /** Copies of replays kept for uploads and retries */
+  staging: StagingPolicy;
+  /** Move or copy replays to a local archive once uploaded */
+  localArchive: LocalArchivePolicy;
+  /** Delete uploaded replays from the watched folders */
+  cleanup: CleanupPolicy;
+  /** Run detection, validation and metadata parsing without uploading; replays are recorded as skipped */
+  dryRun: boolean;
+}
+
+// Schedule for retrying failed uploads; the delay doubles after every failed round
+export interface AutoRetryPolicy {
+  enabled: boolean;
+  /** Delay before the first retry, counted from the failure */
+  initialDelayMinutes: number;
+  maxDelayMinutes: number;
+  /** Give up on uploads that first failed longer ago than this */
+  maxAgeHours: number;
+}
+
+// Staging cache of replays being uploaded; copies of failed uploads are kept
+// for retries until they succeed or expire
+export interface StagingPolicy {
+  /** Least recently used copies are evicted beyond this size */
+  maxSizeMb: number;
+  /** Copies unused for longer than this are deleted */
+  maxAgeDays: number;
+}
+
+// What to do with a replay once it's uploaded
+export type ArchiveAction = 'none' | 'move' | 'copy';
+
+// Local archive uploaded replays are moved or copied to
+export interface LocalArchivePolicy {
+  action: ArchiveAction;
+  folder: string;
+  /**
+   * File name of archived replays, / separates subfolders
+   * Placeholders: {date}, {time}, {playlist}, {map}, {score}, {player}, {name}
+   */
+  nameTemplate: string;
+}
+
+// Deletes uploaded replays from the watched folders; only replays whose
+// upload is confirmed in the history are deleted
+export interface CleanupPolicy {
+  enabled: boolean;
+  /** Delete uploaded replays older than this many days */
+  maxAgeDays: number | null;
+  /** Keep this many replays per folder, deleting older uploaded ones */
+  maxCount: number | null;
+}
+
+// Payload of the replays_cleaned_up event
+export interface CleanupResult {
+  /** Uploaded replays deleted from the watched folders */
+  deleted: number;
+  freedBytes: number;
+}
+
+// Maps a watched folder to the account its replays are uploaded as
+export interface AccountRoute {
+  folder: string;
+  accountId: string;
 }
 
 // User information from BallCam API
@@ -27,6 +99,16 @@ export interface AuthSession {
   refreshTokenExpiry: string;
   user: User;
   deviceId?: string;
+  kind: SessionKind;
+}
+
+// How an auth session was obtained
+export type SessionKind = 'password' | 'device' | 'token' | 'oauth';
+
+// Stored account summary for the account switcher
+export interface AccountInfo {
+  user: User;
+  isActive: boolean;
 }
 
 // ============================================================================
@@ -40,6 +122,12 @@ export interface DeviceCodeResponse {
   verification_url: string;
   expires_in: number;
   interval: number;
+  /** Verification URL with the user code pre-filled */
+  verification_url_complete?: string;
+  /** QR code of the complete verification URL as an SVG document */
+  qr_code_svg?: string;
+  /** QR code of the complete verification URL as a PNG data URL */
+  qr_code_png?: string;
 }
 
 // Response from POST /api/auth/device/token on success
@@ -60,7 +148,7 @@ export type DevicePollResult =
   | { status: 'denied' };
 
 // Upload status enum
-export type UploadStatus = 'pending' | 'uploading' | 'processing' | 'completed' | 'failed';
+export type UploadStatus = 'pending' | 'uploading' | 'processing' | 'completed' | 'failed' | 'skipped';
 
 // Record of a single upload attempt
 export interface UploadRecord {
@@ -76,6 +164,207 @@ export interface UploadRecord {
   completedAt?: string;
   /** File size in bytes (for statistics tracking) */
   fileSize?: number;
+  /** ID of the account the replay was uploaded as */
+  accountId?: string;
+  /** Username of the account the replay was uploaded as */
+  accountUsername?: string;
+  /** Visibility the replay was uploaded with */
+  visibility?: Visibility;
+  /** Match details parsed from the replay header */
+  metadata?: ReplayMetadata;
+  /** Kind of failure, for failed uploads */
+  errorClass?: ErrorClass;
+  /** Every attempt so far, oldest first (attempts counts them) */
+  attemptLog?: UploadAttempt[];
+  /** Batch the upload was started in */
+  batchId?: string;
+  /** .zip archive the replay was extracted from */
+  archiveName?: string;
+  /** Where the replay was moved or copied in the local archive */
+  archivedPath?: string;
+  /** Why the replay wasn't uploaded, for skipped records ("Dry run") */
+  skipReason?: string;
+}
+
+// One attempt at uploading a replay
+export interface UploadAttempt {
+  startedAt: string;
+  durationMs: number;
+  /** Error message, absent if the attempt succeeded */
+  error?: string;
+  errorClass?: ErrorClass;
+  /** HTTP status of a rejected upload */
+  httpStatus?: number;
+}
+
+// Kind of upload failure: transient errors are worth retrying, permanent ones aren't
+export type ErrorClass = 'transient' | 'auth' | 'permanent';
+
+// Match details read from a replay's header
+export interface ReplayMetadata {
+  replayGuid: string | null;
+  replayName: string | null;
+  mapName: string | null;
+  /** "Online", "Private", "Offline", ... */
+  matchType: string | null;
+  /** Players per team */
+  teamSize: number | null;
+  team0Score: number;
+  team1Score: number;
+  goals: number;
+  /** Player who recorded the replay */
+  playerName: string | null;
+  /** Match date as written by the game ("2024-01-15 20-30-12") */
+  date: string | null;
+  durationSeconds: number | null;
+  buildVersion: string | null;
+  unfairTeams: boolean;
+}
+
+// Field to sort history queries by
+export type HistorySortField = 'createdAt' | 'filename' | 'fileSize';
+
+// Filters, sorting and pagination for history queries
+export interface HistoryQuery {
+  /** Only records with one of these statuses (all when empty) */
+  statuses?: UploadStatus[];
+  /** Created at or after this RFC 3339 timestamp */
+  from?: string;
+  /** Created at or before this RFC 3339 timestamp */
+  to?: string;
+  /** Filename contains this text */
+  filename?: string;
+  visibility?: Visibility;
+  accountId?: string;
+  sortBy?: HistorySortField;
+  sortOrder?: 'asc' | 'desc';
+  /** Cursor from a previous page's nextCursor */
+  cursor?: string;
+  /** Page size (default 50, max 500) */
+  limit?: number;
+}
+
+// One page of history query results
+export interface HistoryPage {
+  records: UploadRecord[];
+  /** Cursor for the next page, null on the last page */
+  nextCursor: string | null;
+  /** Number of records matching the filters */
+  total: number;
+}
+
+// Outcome of a bulk retry
+export interface BulkRetryResult {
+  succeeded: number;
+  failed: number;
+  /** Records that weren't retried (not failed, permanent error, file missing) */
+  skipped: number;
+}
+
+// File format for history export and import
+export type HistoryExportFormat = 'csv' | 'jsonl';
+
+export interface HistoryImportResult {
+  imported: number;
+  /** Records already in history (same record or same replay ID) */
+  skipped: number;
+}
+
+// Upload state of a replay in the library
+export type LibraryStatus = 'never' | 'pending' | 'uploaded' | 'failed' | 'skipped';
+
+// A replay file found in a watched folder
+export interface LibraryEntry {
+  path: string;
+  filename: string;
+  folder: string;
+  fileSize: number;
+  /** Last modification time of the file */
+  modifiedAt: string;
+  status: LibraryStatus;
+  /** Latest history record for this file */
+  recordId: string | null;
+  replayUrl: string | null;
+  /** Match details, null if the header couldn't be read */
+  metadata: ReplayMetadata | null;
+}
+
+// Filters and pagination for library queries (newest files first)
+export interface LibraryQuery {
+  statuses?: LibraryStatus[];
+  folder?: string;
+  /** Case-insensitive match on the filename or map name */
+  search?: string;
+  from?: string;
+  to?: string;
+  /** Opaque cursor from the previous page */
+  cursor?: string;
+  limit?: number;
+}
+
+export interface LibraryPage {
+  entries: LibraryEntry[];
+  /** Cursor for the next page, null on the last page */
+  nextCursor: string | null;
+  /** Number of entries matching the filters */
+  total: number;
+}
+
+// Outcome of a library scan (also the library_updated event payload)
+export interface LibraryScanResult {
+  /** Replay files found in the watched folders */
+  files: number;
+  /** New or changed files that were (re)indexed */
+  indexed: number;
+  /** Entries removed because their file is gone */
+  removed: number;
+}
+
+// A replay waiting to be uploaded
+export interface UploadJob {
+  filePath: string;
+  visibility: Visibility | null;
+  /** History record to continue, for retries */
+  recordId?: string;
+  /** Batch the upload was started in */
+  batchId?: string;
+  /** .zip archive the replay was extracted from */
+  archiveName?: string;
+}
+
+// Upload queue runtime state
+export interface UploadQueueState {
+  /** Uploads are paused until the user logs in again */
+  pausedForAuth: boolean;
+  /** Jobs held back by an expired session */
+  heldJobs: UploadJob[];
+}
+
+// Payload of the store_recovered event
+export interface StoreRecovered {
+  /** Store file that was corrupt (e.g. "config.json") */
+  store: string;
+  /** Where the data was recovered from */
+  source: 'backup' | 'defaults';
+}
+
+// Payload of the config_changed event
+export interface ConfigChanged {
+  /** Names of the fields that changed */
+  changed: (keyof AppConfig)[];
+  config: AppConfig;
+}
+
+export interface ConfigFieldError {
+  field: keyof AppConfig;
+  message: string;
+}
+
+// Error thrown by saveConfig
+export interface SaveConfigError {
+  message: string;
+  /** Per-field validation errors */
+  fields: ConfigFieldError[];
 }
 
 // Watcher runtime state
@@ -128,6 +417,57 @@ export interface UploadStats {
   totalBytesFormatted: string;
 }
 
+// Bucket size for upload report time series (weeks start on Monday)
+export type StatsInterval = 'day' | 'week';
+
+// Date range and bucket size of an upload report
+export interface StatsQuery {
+  /** Start of the range (RFC 3339), from the first record when omitted */
+  from?: string;
+  /** End of the range (RFC 3339), until now when omitted */
+  to?: string;
+  interval?: StatsInterval;
+}
+
+// Upload counts and bytes for a bucket or a split
+export interface StatsCounts {
+  completed: number;
+  failed: number;
+  /** Bytes of completed uploads */
+  bytes: number;
+}
+
+export interface StatsBucket extends StatsCounts {
+  /** First day of the bucket (YYYY-MM-DD, local time) */
+  start: string;
+}
+
+export interface StatsSplit extends StatsCounts {
+  /** Folder path or account ID */
+  key: string;
+  /** Display name (account username), if known */
+  label: string | null;
+}
+
+// Upload statistics over a date range
+export interface UploadReport {
+  totals: StatsCounts;
+  /** Percentage of successful uploads (0-100) */
+  successRate: number;
+  /** One entry per interval, including empty ones */
+  buckets: StatsBucket[];
+  /** Average duration of successful upload attempts */
+  averageDurationMs: number | null;
+  /** Bytes per second over successful upload attempts */
+  throughputBytesPerSec: number | null;
+  /** Failed uploads per error class */
+  failures: Record<ErrorClass, number>;
+  /** Per watched folder, most uploads first */
+  byFolder: StatsSplit[];
+  /** Per account, most uploads first */
+  byAccount: StatsSplit[];
+}
+
 // Information about the watched folder for display
 export interface FolderInfo {
   /** Full folder path */
@@ -139,3 +479,61 @@ export interface FolderInfo {
   /** Whether folder currently exists */
   exists: boolean;
 }
+
+// Files and folders to upload in one batch
+export interface BatchRequest {
+  /** Replay files, .zip archives of replays and folders of replays */
+  paths: string[];
+  /** Also upload replays in subfolders of the given folders */
+  recursive?: boolean;
+  /** Only replays modified at or after this RFC 3339 timestamp */
+  modifiedAfter?: string;
+  /** Only replays modified at or before this RFC 3339 timestamp */
+  modifiedBefore?: string;
+  visibility?: Visibility;
+}
+
+// A file left out of a batch, or a failed upload in its summary
+export interface BatchFileIssue {
+  filePath: string;
+  reason: string;
+}
+
+// A batch accepted for upload (also the batch_started event payload)
+export interface BatchStarted {
+  batchId: string;
+  /** Files that will be uploaded */
+  total: number;
+  /** Files left out: not replays, outside the date range, duplicates, already uploaded */
+  skipped: BatchFileIssue[];
+}
+
+// Payload of the batch_progress event, sent before each file and at the end
+export interface BatchProgress {
+  batchId: string;
+  total: number;
+  completed: number;
+  failed: number;
+  /** Held until the user logs in again */
+  held: number;
+  /** Processed without being uploaded in a dry run */
+  skipped: number;
+  /** File being uploaded */
+  currentFile: string | null;
+  /** Share of files done (0-100) */
+  percentage: number;
+}
+
+// Payload of the batch_completed event
+export interface BatchSummary {
+  batchId: string;
+  total: number;
+  completed: number;
+  failed: number;
+  held: number;
+  /** Left out when collecting the files, or not uploaded in a dry run */
+  skipped: number;
+  failures: BatchFileIssue[];
+  startedAt: string;
+  finishedAt: string;
+}
@@ -1,9 +1,13 @@
 import { HistoryList } from '@/components/HistoryList';
 
-export function History() {
+interface HistoryProps {
+  highlightId?: string | null;
+}
+
+export function History({ highlightId }: HistoryProps) {
   return (
     <div className="p-4">
-      <HistoryList />
+      <HistoryList highlightId={highlightId} />
     </div>
   );
 }
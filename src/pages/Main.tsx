@@ -1,5 +1,5 @@
 import { useState, useEffect } from 'react';
-import { Eye, Upload, Clock, CheckCircle2, XCircle, AlertCircle, Loader2 } from 'lucide-react';
+import { Eye, Upload, Clock, CheckCircle2, XCircle, AlertCircle, Loader2, MinusCircle } from 'lucide-react';
 import { listen } from '@tauri-apps/api/event';
 import type { FolderInfo as FolderInfoType, UploadProgress as UploadProgressType, UploadRecord, UploadStats as UploadStatsType, User, WatcherState } from '@/lib/types';
 import { UserCard } from '@/components/StatusPage/UserCard';
@@ -94,11 +94,17 @@ export function Main() {
       setLastUpload(event.payload);
     });
 
+    // Dry run: processed without uploading
+    const unlistenSkipped = listen<UploadRecord>('upload_skipped', (event) => {
+      setLastUpload(event.payload);
+    });
+
     return () => {
       unlistenStarted.then((fn) => fn());
       unlistenProgress.then((fn) => fn());
       unlistenCompleted.then((fn) => fn());
       unlistenFailed.then((fn) => fn());
+      unlistenSkipped.then((fn) => fn());
     };
   }, []);
 
@@ -209,6 +215,8 @@ export function Main() {
           <div className="flex items-start gap-3">
             {lastUpload.status === 'completed' ? (
               <CheckCircle2 className="w-5 h-5 text-green-400 mt-0.5" />
+            ) : lastUpload.status === 'skipped' ? (
+              <MinusCircle className="w-5 h-5 text-gray-400 mt-0.5" />
             ) : (
               <XCircle className="w-5 h-5 text-red-400 mt-0.5" />
             )}
@@ -225,6 +233,9 @@ export function Main() {
               {lastUpload.status === 'failed' && lastUpload.errorMessage && (
                 <p className="text-xs text-red-400 mt-1">{lastUpload.errorMessage}</p>
               )}
+              {lastUpload.status === 'skipped' && lastUpload.skipReason && (
+                <p className="text-xs text-gray-400 mt-1">Not uploaded: {lastUpload.skipReason}</p>
+              )}
             </div>
           </div>
         </div>